@@ -0,0 +1,39 @@
+//! Throughput of the interpreter's input path
+//!
+//! Run with `cargo bench --bench input`. Each configuration echoes a
+//! megabyte through `,[.[-],]` from an unbuffered reader, so the numbers
+//! show how much the input buffer saves on `read` calls.
+
+use bfina::{BrainfuckConfig, interpreter, parse_brainfuck};
+use std::io::{self, Read};
+use std::time::Instant;
+
+/// Reader that hands out at most what was asked for, like a raw file
+struct Unbuffered<'a>(&'a [u8]);
+
+impl Read for Unbuffered<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Keep the optimizer from seeing through the copy
+        std::hint::black_box(self.0.read(buf))
+    }
+}
+
+fn main() {
+    let ast = parse_brainfuck(",[.[-],]").unwrap();
+    let input: Vec<u8> = (0..1 << 20).map(|i| (i % 255 + 1) as u8).collect();
+
+    for input_buffer_size in [1, 64, 8 * 1024] {
+        let config = BrainfuckConfig {
+            input_buffer_size,
+            ..BrainfuckConfig::default()
+        };
+        let start = Instant::now();
+        let mut output = io::sink();
+        interpreter::run(&ast, &config, &mut Unbuffered(&input), &mut output).unwrap();
+        println!(
+            "input_buffer_size {:>5}: {:?}",
+            input_buffer_size,
+            start.elapsed()
+        );
+    }
+}
@@ -0,0 +1,96 @@
+//! Bytecode interpreter against a naive AST walker
+//!
+//! Run with `cargo bench --bench interpreter`. Each runs `primes.b`, which
+//! prints the primes below 100 by trial division: the walker recurses into
+//! every loop body and executes one command at a time, while
+//! [`interpreter::run`] executes the fused bytecode from
+//! [`interpreter::compile`], on the program as written and once optimized
+//! at [`MAX_OPT_LEVEL`].
+//!
+//! The classic benchmark is mandelbrot, but its source is too large to vendor
+//! here; `primes.b` is small enough to check in and still spends its time in
+//! nested loops, so it exercises the same dispatch.
+
+use bfina::{
+    AstNode, BrainfuckConfig, Command, FixedTape, MAX_OPT_LEVEL, Tape, interpreter, parse_brainfuck,
+};
+use criterion::{Criterion, black_box, criterion_group, criterion_main};
+use std::io;
+
+const PROGRAM: &str = include_str!("primes.b");
+
+/// Run `ast` by walking the tree, the way a first interpreter would
+///
+/// Like the interpreter it checks the pointer before every move and reads
+/// cells through a [`Tape`], so only the dispatch differs.
+fn walk(ast: &[AstNode], tape: &mut FixedTape, pointer: &mut usize, output: &mut Vec<u8>) {
+    for node in ast {
+        match node {
+            AstNode::Command(Command::Right) => {
+                assert!(*pointer + 1 < tape.len(), "pointer out of bounds");
+                *pointer += 1;
+            }
+            AstNode::Command(Command::Left) => {
+                assert!(*pointer > 0, "pointer out of bounds");
+                *pointer -= 1;
+            }
+            AstNode::Command(Command::Increment) => {
+                tape.set(*pointer, tape.get(*pointer).wrapping_add(1))
+            }
+            AstNode::Command(Command::Decrement) => {
+                tape.set(*pointer, tape.get(*pointer).wrapping_sub(1))
+            }
+            AstNode::Command(Command::Output) => output.push(tape.get(*pointer)),
+            AstNode::Command(Command::Input) => {}
+            AstNode::Loop(body) => {
+                while tape.get(*pointer) != 0 {
+                    walk(body, tape, pointer, output);
+                }
+            }
+            _ => walk(&node.expand(), tape, pointer, output),
+        }
+    }
+}
+
+fn walk_program(ast: &[AstNode], config: &BrainfuckConfig) -> Vec<u8> {
+    let mut tape = FixedTape::new(config.tape_size);
+    let mut output = Vec::new();
+    walk(ast, &mut tape, &mut 0, &mut output);
+    output
+}
+
+fn run_program(ast: &[AstNode], config: &BrainfuckConfig) -> Vec<u8> {
+    let mut output = Vec::new();
+    interpreter::run(ast, config, &mut io::empty(), &mut output).unwrap();
+    output
+}
+
+fn primes(c: &mut Criterion) {
+    let ast = parse_brainfuck(PROGRAM).unwrap();
+    let config = BrainfuckConfig {
+        opt_level: MAX_OPT_LEVEL,
+        ..BrainfuckConfig::default()
+    };
+    let optimized = config.optimize_program(&ast);
+    assert_ne!(optimized, ast);
+
+    let expected = walk_program(&ast, &config);
+    assert_eq!(run_program(&ast, &config), expected);
+    assert_eq!(run_program(&optimized, &config), expected);
+
+    let mut group = c.benchmark_group("primes");
+    group.sample_size(20);
+    group.bench_function("ast walker", |b| {
+        b.iter(|| walk_program(black_box(&ast), &config))
+    });
+    group.bench_function("bytecode", |b| {
+        b.iter(|| run_program(black_box(&ast), &config))
+    });
+    group.bench_function("bytecode, optimized", |b| {
+        b.iter(|| run_program(black_box(&optimized), &config))
+    });
+    group.finish();
+}
+
+criterion_group!(benches, primes);
+criterion_main!(benches);
@@ -0,0 +1,2 @@
+Prints the primes below 100 by trial division
+++>++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++++[>>[-]+<[-]++>>[-]>[-]<<<<<[->>>>+>+<<<<<]>>>>>[-<<<<<+>>>>>]<--[>>[-]>[-]>[-]>[-]>[-]>[-]<<<<<[-]<[-]<<<<<[->>>>>>+<+<<<<<]>>>>>[-<<<<<+>>>>>]>>[-]<<[-]<<<[->>>>>+<<+<<<]>>>[-<<<+>>>]>[->-[>+>>]>[+[-<+>]>+>>]<<<<<]>>>>[-]+<<[[-]>>-<<]>>[<<<<<<<[-]>>>>>>>-]<<<<<<<<+>>-]<[>>>[-]>[-]>[-]>[-]>[-]>[-]>[-]>[-]>[-]>[-]>[-]>[-]<<<<<<<<<<<[-]<[-]<<<<<[->>>>>>+<+<<<<<]>>>>>[-<<<<<+>>>>>]>>++++++++++<[->-[>+>>]>[+[-<+>]>+>>]<<<<<]>>>[->>>+<<<]>>>>++++++++++<[->-[>+>>]>[+[-<+>]>+>>]<<<<<]>>>++++++++++++++++++++++++++++++++++++++++++++++++.<++++++++++++++++++++++++++++++++++++++++++++++++.<<<<<<++++++++++++++++++++++++++++++++++++++++++++++++.<<<[-]++++++++++.[-]<<-]<<<+>-]
@@ -0,0 +1,1035 @@
+//! Static analysis of Brainfuck programs
+//!
+//! This module contains conservative analyses over the AST that the optimizer
+//! and diagnostics can rely on. Every analysis gives up (returns `None`) rather
+//! than guessing when the program's behavior depends on runtime data.
+
+use crate::lamina_builder::optimize::is_clear_loop;
+use crate::lamina_builder::utils::{contains_input, net_pointer_movement};
+use crate::lexer::{AstNode, Command, Position};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Inclusive range of values a cell may hold
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ValueRange {
+    pub min: u8,
+    pub max: u8,
+}
+
+impl ValueRange {
+    /// Range containing every possible cell value
+    pub const FULL: ValueRange = ValueRange { min: 0, max: 255 };
+
+    /// Range containing a single known value
+    pub fn exact(value: u8) -> Self {
+        Self {
+            min: value,
+            max: value,
+        }
+    }
+
+    /// Returns the value if the range contains exactly one value
+    pub fn as_exact(&self) -> Option<u8> {
+        (self.min == self.max).then_some(self.min)
+    }
+
+    /// Returns true if the cell may hold zero
+    pub fn contains_zero(&self) -> bool {
+        self.min == 0
+    }
+
+    /// Shift the range by `delta`, widening to the full range if the cell
+    /// could wrap around
+    fn shift(self, delta: i32) -> Self {
+        let min = self.min as i32 + delta;
+        let max = self.max as i32 + delta;
+
+        if min < 0 || max > 255 {
+            Self::FULL
+        } else {
+            Self {
+                min: min as u8,
+                max: max as u8,
+            }
+        }
+    }
+}
+
+/// Possible cell values after running a program, as computed by
+/// [`cell_value_ranges`]
+///
+/// Cells are indexed by their offset from the starting cell. Cells the program
+/// never touches keep their initial value of zero.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CellRanges {
+    ranges: BTreeMap<isize, ValueRange>,
+    pointer: isize,
+}
+
+impl CellRanges {
+    /// Range of values the cell at `offset` may hold
+    pub fn get(&self, offset: isize) -> ValueRange {
+        self.ranges
+            .get(&offset)
+            .copied()
+            .unwrap_or(ValueRange::exact(0))
+    }
+
+    /// Final data pointer offset relative to the starting cell
+    pub fn pointer(&self) -> isize {
+        self.pointer
+    }
+
+    fn set(&mut self, offset: isize, range: ValueRange) {
+        self.ranges.insert(offset, range);
+    }
+
+    fn current(&self) -> ValueRange {
+        self.get(self.pointer)
+    }
+
+    /// Apply a straight-line command to the abstract state
+    fn apply(&mut self, cmd: Command) {
+        match cmd {
+            Command::Right => self.pointer += 1,
+            Command::Left => self.pointer -= 1,
+            Command::Increment => self.set(self.pointer, self.current().shift(1)),
+            Command::Decrement => self.set(self.pointer, self.current().shift(-1)),
+            Command::Input => self.set(self.pointer, ValueRange::FULL),
+            Command::Output => {}
+        }
+    }
+
+    /// Apply a simple loop: a body of plain commands with zero net pointer
+    /// movement. Returns `None` for any other kind of loop.
+    fn apply_loop(&mut self, body: &[AstNode]) -> Option<()> {
+        let mut offset = 0isize;
+        let mut written = Vec::new();
+
+        for node in body {
+            match node {
+                AstNode::Command(Command::Right) => offset += 1,
+                AstNode::Command(Command::Left) => offset -= 1,
+                AstNode::Move(delta) => offset += *delta as isize,
+                AstNode::Command(Command::Increment | Command::Decrement | Command::Input)
+                | AstNode::SetZero
+                | AstNode::Add(_) => written.push(self.pointer + offset),
+                AstNode::InputAtOffset(io_offset) => {
+                    written.push(self.pointer + offset + *io_offset as isize)
+                }
+                AstNode::CopyAndClear(dest) => {
+                    written.push(self.pointer + offset);
+                    written.push(self.pointer + offset + *dest as isize);
+                }
+                AstNode::MultiplyLoop(targets) => {
+                    written.push(self.pointer + offset);
+                    for target in targets {
+                        written.push(self.pointer + offset + target.offset as isize);
+                    }
+                }
+                AstNode::Command(Command::Output) | AstNode::OutputAtOffset(_) => {}
+                AstNode::Loop(_) => return None,
+            }
+        }
+
+        if offset != 0 {
+            return None;
+        }
+
+        // A loop entered on a cell that is certainly zero never runs
+        if self.current() == ValueRange::exact(0) {
+            return Some(());
+        }
+
+        for cell in written {
+            self.set(cell, ValueRange::FULL);
+        }
+
+        // The loop only exits once the control cell is zero
+        self.set(self.pointer, ValueRange::exact(0));
+        Some(())
+    }
+}
+
+/// Compute the possible value range of every cell after running `ast`
+///
+/// This is a conservative abstract interpretation starting from an all-zero
+/// tape. It handles straight-line code and simple loops (no nested loops and
+/// zero net pointer movement); anything else makes the pointer position
+/// data-dependent, in which case `None` is returned.
+pub fn cell_value_ranges(ast: &[AstNode]) -> Option<CellRanges> {
+    let mut state = CellRanges::default();
+
+    for node in ast {
+        match node {
+            AstNode::Command(cmd) => state.apply(*cmd),
+            AstNode::Loop(body) => state.apply_loop(body)?,
+            AstNode::SetZero => state.set(state.pointer, ValueRange::exact(0)),
+            AstNode::Add(delta) => state.set(state.pointer, state.current().shift(*delta)),
+            AstNode::Move(delta) => state.pointer += *delta as isize,
+            AstNode::OutputAtOffset(_) => {}
+            AstNode::InputAtOffset(offset) => {
+                state.set(state.pointer + *offset as isize, ValueRange::FULL)
+            }
+            AstNode::CopyAndClear(offset) => {
+                state.set(state.pointer + *offset as isize, state.current());
+                state.set(state.pointer, ValueRange::exact(0));
+            }
+            AstNode::MultiplyLoop(targets) => {
+                // Adding a multiple of zero leaves the targets unchanged
+                if state.current() != ValueRange::exact(0) {
+                    for target in targets {
+                        state.set(state.pointer + target.offset as isize, ValueRange::FULL);
+                    }
+                }
+                state.set(state.pointer, ValueRange::exact(0));
+            }
+        }
+    }
+
+    Some(state)
+}
+
+/// Find a pointer move that certainly leaves a tape of `tape_size` cells
+///
+/// Only moves whose target is statically known are considered: moves inside
+/// loop bodies may never run and are skipped, and analysis stops at the first
+/// loop with data-dependent pointer movement. Returns the out-of-bounds cell
+/// index the pointer reaches, relative to the start of the tape.
+pub fn certain_out_of_bounds(ast: &[AstNode], tape_size: usize) -> Option<isize> {
+    certain_out_of_bounds_from(ast, tape_size, 0)
+}
+
+/// Like [`certain_out_of_bounds`], but with the pointer starting on cell
+/// `start` instead of the first cell
+pub fn certain_out_of_bounds_from(
+    ast: &[AstNode],
+    tape_size: usize,
+    start: usize,
+) -> Option<isize> {
+    let mut pointer = start as isize;
+    let out_of_bounds = |cell: isize| cell < 0 || cell >= tape_size as isize;
+
+    for node in ast {
+        let reached = match node {
+            AstNode::Command(Command::Right) => {
+                pointer += 1;
+                pointer
+            }
+            AstNode::Command(Command::Left) => {
+                pointer -= 1;
+                pointer
+            }
+            AstNode::Move(delta) => {
+                pointer += *delta as isize;
+                pointer
+            }
+            AstNode::OutputAtOffset(offset)
+            | AstNode::InputAtOffset(offset)
+            | AstNode::CopyAndClear(offset) => pointer + *offset as isize,
+            AstNode::MultiplyLoop(targets) => targets
+                .iter()
+                .map(|target| pointer + target.offset as isize)
+                .find(|&cell| out_of_bounds(cell))
+                .unwrap_or(pointer),
+            AstNode::Loop(body) => {
+                if net_pointer_movement(body) != Some(0) {
+                    return None;
+                }
+                pointer
+            }
+            AstNode::Command(_) | AstNode::SetZero | AstNode::Add(_) => pointer,
+        };
+
+        if out_of_bounds(reached) {
+            return Some(reached);
+        }
+    }
+
+    None
+}
+
+/// Conservatively check whether a program might run forever
+///
+/// Returns `false` only if every loop provably terminates: its body is
+/// balanced, the control cell isn't read from input or copied from another
+/// cell, and each iteration either leaves the control cell zero or changes it
+/// by an odd amount (which reaches zero within 256 iterations, since cells
+/// wrap). Nested loops must terminate by the same rules. Any other loop,
+/// including ones that do terminate for data-dependent reasons like `[>]`,
+/// makes this return `true`.
+///
+/// `precompute_output` skips programs this flags rather than running them
+/// until its step budget is spent, and still needs the budget: a program
+/// that terminates can take arbitrarily long to do so.
+pub fn may_not_terminate(ast: &[AstNode]) -> bool {
+    ast.iter()
+        .any(|node| matches!(node, AstNode::Loop(body) if !loop_terminates(body)))
+}
+
+/// Whether a loop with this body provably terminates, see
+/// [`may_not_terminate`]
+fn loop_terminates(body: &[AstNode]) -> bool {
+    let mut offset = 0isize;
+    // Change to the control cell per iteration, or its value after the
+    // iteration once it has been cleared
+    let mut delta = 0i32;
+    let mut cleared = false;
+
+    for node in body {
+        match node {
+            AstNode::Command(Command::Right) => offset += 1,
+            AstNode::Command(Command::Left) => offset -= 1,
+            AstNode::Move(d) => offset += *d as isize,
+            AstNode::Command(Command::Increment) if offset == 0 => delta += 1,
+            AstNode::Command(Command::Decrement) if offset == 0 => delta -= 1,
+            AstNode::Add(d) if offset == 0 => delta = delta.wrapping_add(*d),
+            AstNode::Command(Command::Input) if offset == 0 => return false,
+            AstNode::InputAtOffset(o) if offset + *o as isize == 0 => return false,
+            AstNode::CopyAndClear(d) if offset + *d as isize == 0 => return false,
+            AstNode::MultiplyLoop(targets)
+                if targets
+                    .iter()
+                    .any(|target| offset + target.offset as isize == 0) =>
+            {
+                return false;
+            }
+            AstNode::SetZero | AstNode::CopyAndClear(_) | AstNode::MultiplyLoop(_)
+                if offset == 0 =>
+            {
+                cleared = true;
+                delta = 0;
+            }
+            AstNode::Loop(inner) => {
+                if !loop_terminates(inner) {
+                    return false;
+                }
+                if offset == 0 {
+                    // The inner loop only exits once the control cell is zero
+                    cleared = true;
+                    delta = 0;
+                } else if writes_cell(inner, -offset) {
+                    return false;
+                }
+            }
+            AstNode::Command(_)
+            | AstNode::SetZero
+            | AstNode::Add(_)
+            | AstNode::OutputAtOffset(_)
+            | AstNode::InputAtOffset(_)
+            | AstNode::CopyAndClear(_)
+            | AstNode::MultiplyLoop(_) => {}
+        }
+    }
+
+    if offset != 0 {
+        return false;
+    }
+
+    let delta = delta.rem_euclid(256);
+    if cleared { delta == 0 } else { delta % 2 == 1 }
+}
+
+/// Whether any node of a balanced loop body may write the cell `target`
+/// cells from the loop's control cell
+fn writes_cell(body: &[AstNode], target: isize) -> bool {
+    let mut offset = 0isize;
+
+    for node in body {
+        let written = match node {
+            AstNode::Command(Command::Right) => {
+                offset += 1;
+                continue;
+            }
+            AstNode::Command(Command::Left) => {
+                offset -= 1;
+                continue;
+            }
+            AstNode::Move(d) => {
+                offset += *d as isize;
+                continue;
+            }
+            AstNode::Command(Command::Output) | AstNode::OutputAtOffset(_) => continue,
+            AstNode::Command(_) | AstNode::SetZero | AstNode::Add(_) => offset == target,
+            AstNode::InputAtOffset(o) => offset + *o as isize == target,
+            AstNode::CopyAndClear(d) => offset == target || offset + *d as isize == target,
+            AstNode::MultiplyLoop(targets) => {
+                offset == target || targets.iter().any(|t| offset + t.offset as isize == target)
+            }
+            AstNode::Loop(inner) => {
+                net_pointer_movement(inner) != Some(0) || writes_cell(inner, target - offset)
+            }
+        };
+        if written {
+            return true;
+        }
+    }
+
+    false
+}
+
+/// Find a top-level loop that certainly never exits
+///
+/// Returns the index in `ast` of the first loop that is certainly entered
+/// with a nonzero cell (per [`cell_value_ranges`] of the code before it) and
+/// whose body leaves the pointer where it started without ever writing the
+/// control cell, like `+[]` or `+[>+<]`. Such a loop runs forever, so
+/// everything after it is unreachable. Returns `None` whenever this can't be
+/// proven.
+pub fn certain_infinite_loop(ast: &[AstNode]) -> Option<usize> {
+    for (index, node) in ast.iter().enumerate() {
+        let AstNode::Loop(body) = node else {
+            continue;
+        };
+        if net_pointer_movement(body) != Some(0) || writes_cell(body, 0) {
+            continue;
+        }
+
+        // Once the code before a loop can't be analyzed, neither can the
+        // code before any later loop
+        let entry = cell_value_ranges(&ast[..index])?;
+        if !entry.get(entry.pointer()).contains_zero() {
+            return Some(index);
+        }
+    }
+
+    None
+}
+
+/// Count the input commands of a program whose input is all outside loops
+///
+/// Returns `None` if any input command is inside a loop, since the number
+/// of bytes read then depends on the data.
+pub fn static_input_count(ast: &[AstNode]) -> Option<usize> {
+    let mut count = 0;
+
+    for node in ast {
+        match node {
+            AstNode::Command(Command::Input) | AstNode::InputAtOffset(_) => count += 1,
+            AstNode::Loop(body) if contains_input(body) => return None,
+            _ => {}
+        }
+    }
+
+    Some(count)
+}
+
+/// Number of nodes of each kind in a program, loop bodies included
+///
+/// Optimized nodes are counted by kind rather than as the commands they
+/// stand for, so the optimizer can tell which of its passes have anything
+/// to work on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CommandHistogram {
+    pub right: usize,
+    pub left: usize,
+    pub increment: usize,
+    pub decrement: usize,
+    pub output: usize,
+    pub input: usize,
+    pub loops: usize,
+    /// Loops whose body leaves the pointer where it started
+    pub balanced_loops: usize,
+    pub set_zero: usize,
+    pub adds: usize,
+    pub moves: usize,
+    /// [`AstNode::OutputAtOffset`] and [`AstNode::InputAtOffset`] nodes
+    pub io_at_offset: usize,
+    pub copy_and_clear: usize,
+    pub multiply_loops: usize,
+}
+
+impl CommandHistogram {
+    /// Returns true if any node moves the data pointer
+    pub fn moves_pointer(&self) -> bool {
+        self.right + self.left + self.moves > 0
+    }
+
+    /// Returns true if any node reads input or writes output
+    pub fn has_io(&self) -> bool {
+        self.output + self.input + self.io_at_offset > 0
+    }
+}
+
+/// Count the nodes of each kind in a program
+pub fn command_histogram(ast: &[AstNode]) -> CommandHistogram {
+    fn count(nodes: &[AstNode], histogram: &mut CommandHistogram) {
+        for node in nodes {
+            match node {
+                AstNode::Command(Command::Right) => histogram.right += 1,
+                AstNode::Command(Command::Left) => histogram.left += 1,
+                AstNode::Command(Command::Increment) => histogram.increment += 1,
+                AstNode::Command(Command::Decrement) => histogram.decrement += 1,
+                AstNode::Command(Command::Output) => histogram.output += 1,
+                AstNode::Command(Command::Input) => histogram.input += 1,
+                AstNode::Loop(body) => {
+                    histogram.loops += 1;
+                    if net_pointer_movement(body) == Some(0) {
+                        histogram.balanced_loops += 1;
+                    }
+                    count(body, histogram);
+                }
+                AstNode::SetZero => histogram.set_zero += 1,
+                AstNode::Add(_) => histogram.adds += 1,
+                AstNode::Move(_) => histogram.moves += 1,
+                AstNode::OutputAtOffset(_) | AstNode::InputAtOffset(_) => {
+                    histogram.io_at_offset += 1
+                }
+                AstNode::CopyAndClear(_) => histogram.copy_and_clear += 1,
+                AstNode::MultiplyLoop(_) => histogram.multiply_loops += 1,
+            }
+        }
+    }
+
+    let mut histogram = CommandHistogram::default();
+    count(ast, &mut histogram);
+    histogram
+}
+
+/// Rough size and running time of a program's IR, as computed by
+/// [`estimate_cost`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CostEstimate {
+    /// IR instructions generated for the program, counting each loop body
+    /// once
+    pub static_instructions: usize,
+    /// IR instructions executed by a run, or `None` if the program has loops
+    /// and the count depends on how often they iterate
+    pub dynamic_instructions: Option<usize>,
+}
+
+impl std::fmt::Display for CostEstimate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "~{} IR instructions, ", self.static_instructions)?;
+        match self.dynamic_instructions {
+            Some(count) => write!(f, "~{} executed", count),
+            None => write!(f, "unbounded at runtime (loops)"),
+        }
+    }
+}
+
+/// Estimate how many IR instructions a program compiles to and executes
+///
+/// Each node is weighted by the instructions the Lamina backend emits for
+/// it: a cell update is a load, an add and a store (3), a pointer move
+/// updates the data pointer the same way (3), I/O is an access plus the
+/// call (2), clearing a cell is one store, and a loop adds a load, a branch
+/// and a jump (3) around its body. Multiply and copy loops cost a load and
+/// a store of the source cell plus 3 per target (4 for a multiply). The
+/// numbers are meant for comparing programs, such as before and after
+/// optimization, not as an exact count.
+pub fn estimate_cost(ast: &[AstNode]) -> CostEstimate {
+    fn weigh(nodes: &[AstNode]) -> usize {
+        nodes
+            .iter()
+            .map(|node| match node {
+                AstNode::Command(Command::Output | Command::Input)
+                | AstNode::OutputAtOffset(_)
+                | AstNode::InputAtOffset(_) => 2,
+                AstNode::Command(_) | AstNode::Add(_) | AstNode::Move(_) => 3,
+                AstNode::SetZero => 1,
+                AstNode::Loop(body) => 3 + weigh(body),
+                AstNode::CopyAndClear(_) => 2 + 3,
+                AstNode::MultiplyLoop(targets) => 2 + 4 * targets.len(),
+            })
+            .sum()
+    }
+
+    let static_instructions = weigh(ast);
+    let has_loops = ast.iter().any(|node| matches!(node, AstNode::Loop(_)));
+    CostEstimate {
+        static_instructions,
+        dynamic_instructions: (!has_loops).then_some(static_instructions),
+    }
+}
+
+/// Cells a straight-line region reads and writes, as computed by
+/// [`region_cell_access`]
+///
+/// Cells are indexed by their offset from the cell the pointer is on when
+/// the region starts.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct CellAccessInfo {
+    /// Cells whose value the region uses
+    pub reads: BTreeSet<isize>,
+    /// Cells the region may change
+    pub writes: BTreeSet<isize>,
+    /// Cells read before the region overwrites them, so their value on
+    /// entry to the region matters
+    pub reads_before_write: BTreeSet<isize>,
+}
+
+impl CellAccessInfo {
+    fn read(&mut self, cell: isize) {
+        self.reads.insert(cell);
+        if !self.writes.contains(&cell) {
+            self.reads_before_write.insert(cell);
+        }
+    }
+
+    fn write(&mut self, cell: isize) {
+        self.writes.insert(cell);
+    }
+}
+
+/// Find which cells a straight-line region reads and writes
+///
+/// `+`, `-` and `Add` read the cell they change, and so does input, since
+/// the cell keeps its old value at end of input. Only a clear or the
+/// destination of `CopyAndClear` overwrites a cell without reading it;
+/// the targets of `MultiplyLoop` are read and written.
+/// Returns `None` if the region contains a loop, since which cells a loop
+/// touches depends on how often it runs.
+pub fn region_cell_access(ast: &[AstNode]) -> Option<CellAccessInfo> {
+    let mut info = CellAccessInfo::default();
+    let mut pointer: isize = 0;
+
+    for node in ast {
+        match node {
+            AstNode::Command(Command::Right) => pointer += 1,
+            AstNode::Command(Command::Left) => pointer -= 1,
+            AstNode::Move(delta) => pointer += *delta as isize,
+            AstNode::Command(Command::Increment | Command::Decrement | Command::Input)
+            | AstNode::Add(_) => {
+                info.read(pointer);
+                info.write(pointer);
+            }
+            AstNode::Command(Command::Output) => info.read(pointer),
+            AstNode::OutputAtOffset(offset) => info.read(pointer + *offset as isize),
+            AstNode::InputAtOffset(offset) => {
+                let cell = pointer + *offset as isize;
+                info.read(cell);
+                info.write(cell);
+            }
+            AstNode::SetZero => info.write(pointer),
+            AstNode::CopyAndClear(offset) => {
+                info.read(pointer);
+                info.write(pointer + *offset as isize);
+                info.write(pointer);
+            }
+            AstNode::MultiplyLoop(targets) => {
+                info.read(pointer);
+                for target in targets {
+                    let cell = pointer + target.offset as isize;
+                    info.read(cell);
+                    info.write(cell);
+                }
+                info.write(pointer);
+            }
+            AstNode::Loop(_) => return None,
+        }
+    }
+
+    Some(info)
+}
+
+/// Find the first input or output command in source order
+///
+/// `spans` are the node positions returned by
+/// [`parse_brainfuck_spanned`](crate::lexer::parse_brainfuck_spanned) for
+/// `ast`. Loop bodies are searched in order, whether or not the loop runs,
+/// and the I/O nodes produced by the optimizer count as their command.
+///
+/// # Panics
+///
+/// Panics if `spans` has fewer entries than `ast` has nodes.
+pub fn first_io(ast: &[AstNode], spans: &[Position]) -> Option<(Command, Position)> {
+    fn search(nodes: &[AstNode], index: &mut usize) -> Option<(usize, Command)> {
+        for node in nodes {
+            let node_index = *index;
+            *index += 1;
+            match node {
+                AstNode::Command(cmd @ (Command::Output | Command::Input)) => {
+                    return Some((node_index, *cmd));
+                }
+                AstNode::OutputAtOffset(_) => return Some((node_index, Command::Output)),
+                AstNode::InputAtOffset(_) => return Some((node_index, Command::Input)),
+                AstNode::Loop(body) => {
+                    if let Some(found) = search(body, index) {
+                        return Some(found);
+                    }
+                }
+                AstNode::Command(_)
+                | AstNode::SetZero
+                | AstNode::Add(_)
+                | AstNode::Move(_)
+                | AstNode::CopyAndClear(_)
+                | AstNode::MultiplyLoop(_) => {}
+            }
+        }
+        None
+    }
+
+    let (index, cmd) = search(ast, &mut 0)?;
+    Some((cmd, spans[index]))
+}
+
+/// Kind of problem reported by [`lint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LintKind {
+    /// An empty loop `[]` that may be entered, which never exits once it is
+    InfiniteLoop,
+    /// A clear loop such as `[-]` on a cell that is already zero, because
+    /// it directly follows another loop or clear
+    RedundantClear,
+}
+
+impl std::fmt::Display for LintKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LintKind::InfiniteLoop => write!(f, "empty loop never exits once entered"),
+            LintKind::RedundantClear => write!(f, "clear loop on a cell that is already zero"),
+        }
+    }
+}
+
+/// A likely mistake found by [`lint`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Lint {
+    pub kind: LintKind,
+    /// Index of the offending node in source order, which is also its index
+    /// in the spans returned by
+    /// [`parse_brainfuck_spanned`](crate::lexer::parse_brainfuck_spanned)
+    pub node: usize,
+}
+
+/// Find loops that are certainly mistakes
+///
+/// Only provable cases are reported: an empty loop is flagged unless its
+/// cell is known to be zero (before the program first writes to the tape or
+/// after another loop, `[]` is a comment), and a clear loop only when its
+/// cell is known to be zero. A loop, clear, copy or multiply loop always
+/// leaves its cell zero.
+pub fn lint(ast: &[AstNode]) -> Vec<Lint> {
+    fn visit(nodes: &[AstNode], index: &mut usize, lints: &mut Vec<Lint>, mut tape_is_zero: bool) {
+        // The cell is only known to be zero right after a loop ends, or
+        // while nothing has been written to the tape
+        let mut cell_is_zero = tape_is_zero;
+        for node in nodes {
+            let node_index = *index;
+            *index += 1;
+            let kind = match node {
+                AstNode::Loop(body) if body.is_empty() && !cell_is_zero => {
+                    Some(LintKind::InfiniteLoop)
+                }
+                AstNode::Loop(body) if cell_is_zero && is_clear_loop(body) => {
+                    Some(LintKind::RedundantClear)
+                }
+                AstNode::SetZero if cell_is_zero => Some(LintKind::RedundantClear),
+                _ => None,
+            };
+            if let Some(kind) = kind {
+                lints.push(Lint {
+                    kind,
+                    node: node_index,
+                });
+            }
+
+            if let AstNode::Loop(body) = node {
+                visit(body, index, lints, false);
+            }
+            let clears = matches!(
+                node,
+                AstNode::Loop(_)
+                    | AstNode::SetZero
+                    | AstNode::CopyAndClear(_)
+                    | AstNode::MultiplyLoop(_)
+            );
+            // Clearing a zero cell writes nothing
+            tape_is_zero &= (clears && cell_is_zero)
+                || matches!(
+                    node,
+                    AstNode::Command(Command::Right | Command::Left | Command::Output)
+                        | AstNode::Move(_)
+                        | AstNode::OutputAtOffset(_)
+                );
+            cell_is_zero = tape_is_zero || clears;
+        }
+    }
+
+    let mut lints = Vec::new();
+    visit(ast, &mut 0, &mut lints, true);
+    lints
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::parse_brainfuck;
+
+    fn ranges(source: &str) -> Option<CellRanges> {
+        cell_value_ranges(&parse_brainfuck(source).unwrap())
+    }
+
+    #[test]
+    fn test_straight_line_ranges() {
+        let state = ranges("+++>++<-").unwrap();
+        assert_eq!(state.get(0), ValueRange::exact(2));
+        assert_eq!(state.get(1), ValueRange::exact(2));
+        assert_eq!(state.get(2), ValueRange::exact(0));
+        assert_eq!(state.pointer(), 0);
+    }
+
+    #[test]
+    fn test_wrapping_widens_to_full_range() {
+        let state = ranges("-").unwrap();
+        assert_eq!(state.get(0), ValueRange::FULL);
+    }
+
+    #[test]
+    fn test_input_is_full_range() {
+        let state = ranges(",>+").unwrap();
+        assert_eq!(state.get(0), ValueRange::FULL);
+        assert_eq!(state.get(1), ValueRange::exact(1));
+    }
+
+    #[test]
+    fn test_simple_loop_clears_control_cell() {
+        let state = ranges("+++[>++<-]").unwrap();
+        assert_eq!(state.get(0), ValueRange::exact(0));
+        assert_eq!(state.get(1), ValueRange::FULL);
+
+        // The counter is provably positive on entry, so the loop runs
+        let entry = ranges("+++").unwrap();
+        assert!(!entry.get(0).contains_zero());
+    }
+
+    #[test]
+    fn test_loop_on_zero_cell_is_skipped() {
+        let state = ranges(">+<[>-<-]").unwrap();
+        assert_eq!(state.get(1), ValueRange::exact(1));
+    }
+
+    #[test]
+    fn test_certain_out_of_bounds() {
+        let oob = |source: &str, tape_size| {
+            certain_out_of_bounds(&parse_brainfuck(source).unwrap(), tape_size)
+        };
+
+        assert_eq!(oob(">>>", 3), Some(3));
+        assert_eq!(oob(">>", 3), None);
+        assert_eq!(oob("+<", 3), Some(-1));
+        assert_eq!(oob("+[->+<]<", 3), Some(-1));
+
+        // Loop bodies may never run, and unbalanced loops hide the pointer
+        assert_eq!(oob("[<]", 3), None);
+        assert_eq!(oob(",[>]<<<<", 3), None);
+
+        let ast = parse_brainfuck("<>>").unwrap();
+        assert_eq!(certain_out_of_bounds_from(&ast, 3, 1), None);
+        assert_eq!(certain_out_of_bounds_from(&ast, 2, 1), Some(2));
+    }
+
+    #[test]
+    fn test_unknown_for_unbalanced_or_nested_loops() {
+        assert_eq!(ranges("+[>]"), None);
+        assert_eq!(ranges("+[[-]]"), None);
+    }
+
+    #[test]
+    fn test_region_cell_access() {
+        let access = |source: &str| region_cell_access(&parse_brainfuck(source).unwrap());
+        let set = |cells: &[isize]| cells.iter().copied().collect::<BTreeSet<_>>();
+
+        // Output of a cell the region never wrote reads its entry value
+        let info = access(">.<+.").unwrap();
+        assert_eq!(info.reads, set(&[0, 1]));
+        assert_eq!(info.writes, set(&[0]));
+        assert_eq!(info.reads_before_write, set(&[0, 1]));
+
+        // Unlowered loops make the region unknown
+        assert_eq!(access(">>[-]+.<<"), None);
+
+        // A clear overwrites the cell, so later reads don't see the entry value
+        let info = region_cell_access(&[
+            AstNode::Move(2),
+            AstNode::SetZero,
+            AstNode::Add(1),
+            AstNode::Command(Command::Output),
+        ])
+        .unwrap();
+        assert_eq!(info.reads, set(&[2]));
+        assert_eq!(info.writes, set(&[2]));
+        assert!(info.reads_before_write.is_empty());
+
+        // Copying overwrites the destination and clears the source
+        let info =
+            region_cell_access(&[AstNode::CopyAndClear(-1), AstNode::OutputAtOffset(-1)]).unwrap();
+        assert_eq!(info.reads, set(&[-1, 0]));
+        assert_eq!(info.writes, set(&[-1, 0]));
+        assert_eq!(info.reads_before_write, set(&[0]));
+
+        // Input may leave the old value in place at end of input
+        assert_eq!(access(",").unwrap().reads_before_write, set(&[0]));
+        assert_eq!(access("").unwrap(), CellAccessInfo::default());
+    }
+
+    #[test]
+    fn test_first_io() {
+        let first = |source: &str| {
+            let (ast, spans) = crate::lexer::parse_brainfuck_spanned(source).unwrap();
+            first_io(&ast, &spans)
+        };
+        let at = |line, column| Position { line, column };
+
+        assert_eq!(first(""), None);
+        assert_eq!(first("+[->+<]>"), None);
+        assert_eq!(first("++.,"), Some((Command::Output, at(1, 3))));
+        assert_eq!(first("+\n>  ,."), Some((Command::Input, at(2, 4))));
+
+        // Nested bodies are searched in order, even if they never run
+        assert_eq!(first("+[>[-]\n [<.]]"), Some((Command::Output, at(2, 4))));
+        assert_eq!(first("[,][.]"), Some((Command::Input, at(1, 2))));
+    }
+
+    #[test]
+    fn test_may_not_terminate() {
+        let check = |source: &str| may_not_terminate(&parse_brainfuck(source).unwrap());
+
+        assert!(!check("+++.>"));
+        assert!(!check("+[-]"));
+        assert!(!check("+++[>+++[>+<-]<-]"));
+        assert!(!check("+[>+<---]"));
+        assert!(!check("+[[-]]"));
+
+        assert!(check("+[]"));
+        assert!(check("+[--]"));
+        assert!(check("+[[-]+]"));
+        assert!(check("+[>]"));
+        assert!(check(",[.,]"));
+        // The inner loop refills the control cell
+        assert!(check("+[->[<+>-]<]"));
+    }
+
+    #[test]
+    fn test_certain_infinite_loop() {
+        let find = |source: &str| certain_infinite_loop(&parse_brainfuck(source).unwrap());
+
+        assert_eq!(find("+[]>+."), Some(1));
+        assert_eq!(find("++>+[>+<]<[-]"), Some(4));
+        assert_eq!(find("+>[]<[]."), Some(4));
+
+        // Entered on a zero cell, or one that may be zero
+        assert_eq!(find("[]+."), None);
+        assert_eq!(find(",[]."), None);
+        // The body may change the control cell or move the pointer
+        assert_eq!(find("+[-]."), None);
+        assert_eq!(find("+[>]."), None);
+        assert_eq!(find("+[>[-]<[-]]."), None);
+        // The code before the loop is too complex to analyze
+        assert_eq!(find("+[>]+[]"), None);
+    }
+
+    #[test]
+    fn test_lint() {
+        let lints = |source: &str| lint(&parse_brainfuck(source).unwrap());
+        let at = |kind, node| Lint { kind, node };
+
+        assert_eq!(lints("+[]"), [at(LintKind::InfiniteLoop, 1)]);
+        assert_eq!(lints(",[>[]<]"), [at(LintKind::InfiniteLoop, 3)]);
+        assert_eq!(lints("+[-][-]>[+]"), [at(LintKind::RedundantClear, 3)]);
+        assert_eq!(lints("+[>+<-][-]"), [at(LintKind::RedundantClear, 6)]);
+
+        // A cleared cell may be changed again before the next clear
+        assert!(lints("+[-]+[-]").is_empty());
+        // After a loop, `[]` never runs and is commonly a comment
+        assert!(lints("+[-][]").is_empty());
+        assert!(lints("++[>+<-]>.").is_empty());
+        // So does a leading comment loop, before anything is written
+        assert!(lints("[]+[-]").is_empty());
+        assert!(lints(">.[comment]<[]+.").is_empty());
+        assert_eq!(lints("[-]+"), [at(LintKind::RedundantClear, 0)]);
+        assert_eq!(lints(",>[]"), [at(LintKind::InfiniteLoop, 2)]);
+
+        let optimized = crate::lamina_builder::optimize::lower_clear_loops(
+            &parse_brainfuck("+[-][-]").unwrap(),
+        );
+        assert_eq!(lint(&optimized), [at(LintKind::RedundantClear, 2)]);
+
+        // Copies and multiply loops leave their cell zero as well
+        let target = crate::lexer::MultiplyAdd {
+            offset: 1,
+            factor: 2,
+        };
+        for clear in [
+            AstNode::CopyAndClear(1),
+            AstNode::MultiplyLoop(vec![target]),
+        ] {
+            let ast = [
+                AstNode::Command(Command::Input),
+                clear,
+                AstNode::Loop(Vec::new()),
+            ];
+            assert!(lint(&ast).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_estimate_cost() {
+        let cost = |source: &str| estimate_cost(&parse_brainfuck(source).unwrap());
+
+        assert_eq!(
+            cost("++>."),
+            CostEstimate {
+                static_instructions: 11,
+                dynamic_instructions: Some(11),
+            }
+        );
+        let looped = cost("+[-]");
+        assert_eq!(looped.static_instructions, 9);
+        assert_eq!(looped.dynamic_instructions, None);
+        assert_eq!(
+            looped.to_string(),
+            "~9 IR instructions, unbounded at runtime (loops)"
+        );
+
+        // Optimizing shrinks the estimate and can make it bounded
+        let ast = parse_brainfuck("++++[->++<]>.").unwrap();
+        let before = estimate_cost(&ast);
+        let after = estimate_cost(&crate::lamina_builder::optimize::optimize_ast(&ast));
+        assert!(after.static_instructions < before.static_instructions);
+        assert_eq!(before.dynamic_instructions, None);
+        assert!(after.dynamic_instructions.is_some());
+    }
+
+    #[test]
+    fn test_command_histogram() {
+        let histogram = command_histogram(&parse_brainfuck("++[>+<-]>[>]<.,").unwrap());
+        assert_eq!(
+            histogram,
+            CommandHistogram {
+                right: 3,
+                left: 2,
+                increment: 3,
+                decrement: 1,
+                output: 1,
+                input: 1,
+                loops: 2,
+                balanced_loops: 1,
+                ..CommandHistogram::default()
+            }
+        );
+        assert!(histogram.moves_pointer());
+        assert!(histogram.has_io());
+
+        let histogram = command_histogram(&[AstNode::SetZero, AstNode::OutputAtOffset(2)]);
+        assert_eq!(histogram.set_zero, 1);
+        assert!(!histogram.moves_pointer());
+        assert!(histogram.has_io());
+    }
+
+    #[test]
+    fn test_static_input_count() {
+        let count = |source: &str| static_input_count(&parse_brainfuck(source).unwrap());
+
+        assert_eq!(count("+++."), Some(0));
+        assert_eq!(count(",>,.<,"), Some(3));
+        assert_eq!(count(",[-]>,[>+<-]"), Some(2));
+        assert_eq!(count(",[.,]"), None);
+        assert_eq!(count("+[[,]]"), None);
+
+        let optimized =
+            crate::lamina_builder::optimize::optimize_ast(&parse_brainfuck(">,<,").unwrap());
+        assert_eq!(static_input_count(&optimized), Some(2));
+    }
+}
@@ -0,0 +1,1169 @@
+//! Pure-Rust Brainfuck interpreter
+//!
+//! The interpreter compiles the AST into flat bytecode with precomputed jump
+//! targets (see [`compile`]) and executes it one step at a time. It needs no
+//! external toolchain, which makes it useful for quick testing and for
+//! checking the compiled output against a reference.
+
+use crate::analysis::static_input_count;
+use crate::lamina_builder::{
+    BrainfuckConfig, CellWrap, ConfigError, EofBehavior, OnError, OutputNewline, TapeModel,
+};
+use crate::lexer::{AstNode, Command, LexerError, MultiplyAdd, parse_brainfuck};
+use crate::tape::{FixedTape, PointerMode, Tape};
+use crate::token::Token;
+use std::io::{self, Read, Write};
+
+/// Error type for interpreter operations
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// The configuration is invalid
+    Config(String),
+    /// The data pointer moved outside of the tape
+    PointerOutOfBounds { pc: usize, pointer: isize },
+    /// A cell went past 255 or below 0 under [`CellWrap::Error`]
+    CellOverflow { pc: usize, pointer: usize },
+    /// The program ran for more than [`BrainfuckConfig::max_steps`] steps
+    StepLimitExceeded { pc: usize, limit: u64 },
+    /// Reading input or writing output failed
+    Io(io::Error),
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeError::Config(msg) => write!(f, "Invalid configuration: {}", msg),
+            RuntimeError::PointerOutOfBounds { pc, pointer } => {
+                write!(
+                    f,
+                    "Data pointer moved out of bounds to cell {} at instruction {}",
+                    pointer, pc
+                )
+            }
+            RuntimeError::CellOverflow { pc, pointer } => {
+                write!(f, "Cell {} overflowed at instruction {}", pointer, pc)
+            }
+            RuntimeError::StepLimitExceeded { pc, limit } => {
+                write!(f, "Step limit of {} exceeded at instruction {}", limit, pc)
+            }
+            RuntimeError::Io(err) => write!(f, "I/O error: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+impl From<io::Error> for RuntimeError {
+    fn from(err: io::Error) -> Self {
+        RuntimeError::Io(err)
+    }
+}
+
+/// Result type for interpreter operations
+pub type Result<T> = std::result::Result<T, RuntimeError>;
+
+/// Error returned by [`run_source`]
+#[derive(Debug)]
+pub enum RunError {
+    /// The source failed to parse
+    Parse(LexerError),
+    /// The program failed while running
+    Runtime(RuntimeError),
+    /// The program could not be compiled or the compiled binary failed to run
+    Compile(String),
+}
+
+impl std::fmt::Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RunError::Parse(err) => write!(f, "Parse error: {}", err),
+            RunError::Runtime(err) => write!(f, "Runtime error: {}", err),
+            RunError::Compile(msg) => write!(f, "Compile error: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+impl From<LexerError> for RunError {
+    fn from(err: LexerError) -> Self {
+        RunError::Parse(err)
+    }
+}
+
+impl From<RuntimeError> for RunError {
+    fn from(err: RuntimeError) -> Self {
+        RunError::Runtime(err)
+    }
+}
+
+/// A single bytecode instruction
+///
+/// Jump targets are indices into the program returned by [`compile`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Command(Command),
+    /// Add a signed amount to the current cell (a run of `+` or `-`)
+    Add(i32),
+    /// Move the data pointer by a signed number of cells (a run of `>` or
+    /// `<`)
+    Move(i32),
+    /// Set the current cell to zero (a `[-]` loop)
+    SetZero,
+    /// Add `factor` times the current cell to the cell at `offset`, one
+    /// target of a multiply loop
+    MultiplyAdd {
+        offset: i32,
+        factor: i32,
+    },
+    /// Jump past the matching `]` if the current cell is zero
+    JumpIfZero(usize),
+    /// Jump back past the matching `[` if the current cell is nonzero
+    JumpIfNonZero(usize),
+}
+
+impl Op {
+    /// The command this instruction is reported as in a [`TraceEntry`]
+    ///
+    /// Runs report their command, and the instructions that replace a loop
+    /// report the `]` that ends it.
+    fn token(&self) -> Token {
+        match self {
+            Op::Command(cmd) => Token::from(*cmd),
+            Op::Add(delta) if *delta < 0 => Token::Decrement,
+            Op::Add(_) => Token::Increment,
+            Op::Move(delta) if *delta < 0 => Token::Left,
+            Op::Move(_) => Token::Right,
+            Op::SetZero | Op::MultiplyAdd { .. } => Token::LoopEnd,
+            Op::JumpIfZero(_) => Token::LoopStart,
+            Op::JumpIfNonZero(_) => Token::LoopEnd,
+        }
+    }
+}
+
+/// Compile the AST into bytecode with resolved jump targets
+///
+/// Loops become a pair of jumps, so running the program needs no recursion
+/// and never scans for a matching bracket. Runs of `+`, `-`, `>` or `<`
+/// become one [`Op::Add`] or [`Op::Move`], `[-]` becomes [`Op::SetZero`],
+/// and multiply loops become an [`Op::MultiplyAdd`] per target guarded by a
+/// jump. Only runs in one direction are merged, so saturating and checked
+/// cells, and pointers that stop at the tape's ends, behave as they would
+/// step by step.
+pub fn compile(ast: &[AstNode]) -> Vec<Op> {
+    let mut program = Vec::new();
+    fuse(ast, &mut program);
+    program
+}
+
+fn fuse(ast: &[AstNode], program: &mut Vec<Op>) {
+    for node in ast {
+        match node {
+            AstNode::Command(Command::Increment) => push_add(program, 1),
+            AstNode::Command(Command::Decrement) => push_add(program, -1),
+            AstNode::Add(delta) => push_add(program, *delta),
+            AstNode::Command(Command::Right) => push_move(program, 1),
+            AstNode::Command(Command::Left) => push_move(program, -1),
+            AstNode::Move(delta) => push_move(program, *delta),
+            AstNode::Command(cmd) => program.push(Op::Command(*cmd)),
+            AstNode::SetZero => program.push(Op::SetZero),
+            AstNode::Loop(body) if body[..] == [AstNode::Command(Command::Decrement)] => {
+                program.push(Op::SetZero)
+            }
+            AstNode::Loop(body) => {
+                let start = program.len();
+                program.push(Op::JumpIfZero(0));
+                fuse(body, program);
+                let end = program.len();
+                program.push(Op::JumpIfNonZero(start + 1));
+                program[start] = Op::JumpIfZero(end + 1);
+            }
+            AstNode::MultiplyLoop(targets) => {
+                let start = program.len();
+                program.push(Op::JumpIfZero(0));
+                program.extend(targets.iter().map(|target| Op::MultiplyAdd {
+                    offset: target.offset,
+                    factor: target.factor,
+                }));
+                program.push(Op::SetZero);
+                program[start] = Op::JumpIfZero(program.len());
+            }
+            AstNode::CopyAndClear(offset) => {
+                let transfer = AstNode::MultiplyLoop(vec![MultiplyAdd {
+                    offset: *offset,
+                    factor: 1,
+                }]);
+                let nodes = [
+                    AstNode::Move(*offset),
+                    AstNode::SetZero,
+                    AstNode::Move(-offset),
+                    transfer,
+                ];
+                fuse(&nodes, program)
+            }
+            _ => fuse(&node.expand(), program),
+        }
+    }
+}
+
+/// Push an [`Op::Add`], merging it into an `Add` in the same direction
+fn push_add(program: &mut Vec<Op>, delta: i32) {
+    match program.last_mut() {
+        _ if delta == 0 => {}
+        Some(Op::Add(last)) if last.signum() == delta.signum() => *last += delta,
+        _ => program.push(Op::Add(delta)),
+    }
+}
+
+/// Push an [`Op::Move`], merging it into a `Move` in the same direction
+fn push_move(program: &mut Vec<Op>, delta: i32) {
+    match program.last_mut() {
+        _ if delta == 0 => {}
+        Some(Op::Move(last)) if last.signum() == delta.signum() => *last += delta,
+        _ => program.push(Op::Move(delta)),
+    }
+}
+
+/// Compile the AST into one instruction per command, for tracing
+///
+/// Optimized nodes are expanded back into plain commands (see
+/// [`AstNode::expand`]), so traces show the same steps as the source
+/// program.
+fn compile_commands(ast: &[AstNode], program: &mut Vec<Op>) {
+    for node in ast {
+        match node {
+            AstNode::Command(cmd) => program.push(Op::Command(*cmd)),
+            AstNode::Loop(body) => {
+                let start = program.len();
+                program.push(Op::JumpIfZero(0));
+                compile_commands(body, program);
+                let end = program.len();
+                program.push(Op::JumpIfNonZero(start + 1));
+                program[start] = Op::JumpIfZero(end + 1);
+            }
+            _ => compile_commands(&node.expand(), program),
+        }
+    }
+}
+
+/// One recorded step of a traced execution
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceEntry {
+    /// Index of the executed instruction
+    pub pc: usize,
+    /// The executed command (loops appear as `[` and `]`)
+    pub command: Token,
+    /// Data pointer after the step
+    pub pointer: usize,
+    /// Value of the current cell after the step
+    pub cell_value: u8,
+}
+
+/// Step-by-step Brainfuck interpreter
+///
+/// The tape defaults to a [`FixedTape`] of `tape_size` cells; any other
+/// [`Tape`] can be supplied with [`Interpreter::with_tape`].
+pub struct Interpreter<T: Tape = FixedTape> {
+    program: Vec<Op>,
+    tape: T,
+    pointer: usize,
+    pc: usize,
+    /// Largest number of cells the tape may grow to, or `None` if it has a
+    /// fixed size
+    max_tape_len: Option<usize>,
+    output_newline: OutputNewline,
+    on_error: OnError,
+    cell_wrap: CellWrap,
+    eof_behavior: EofBehavior,
+    /// Input read ahead of the `,` commands that consume it
+    input_buffer: Vec<u8>,
+    /// Range of `input_buffer` that hasn't been consumed yet
+    input_start: usize,
+    input_end: usize,
+    /// Bytes the program has yet to read, if that is known statically
+    input_left: Option<usize>,
+    /// Configured step limit, and the steps taken towards it so far
+    max_steps: Option<u64>,
+    steps: u64,
+}
+
+impl Interpreter {
+    /// Create an interpreter for the given AST
+    pub fn new(ast: &[AstNode], config: &BrainfuckConfig) -> Result<Self> {
+        // Validate before allocating, so an oversized tape is never allocated
+        validate(config)?;
+        let tape = FixedTape::new(config.tape_size);
+        Self::with_tape(ast, config, tape)
+    }
+
+    /// Create an interpreter that executes one command per step, so every
+    /// step of the source program can be traced
+    fn for_tracing(ast: &[AstNode], config: &BrainfuckConfig) -> Result<Self> {
+        let mut interpreter = Self::new(ast, config)?;
+        interpreter.program.clear();
+        compile_commands(&config.wrap_program(ast), &mut interpreter.program);
+        Ok(interpreter)
+    }
+}
+
+impl<T: Tape> Interpreter<T> {
+    /// Create an interpreter for the given AST that runs on `tape`
+    ///
+    /// The tape's length takes the place of `config.tape_size`, including
+    /// when picking the middle cell for `config.allow_negative_tape`. The
+    /// data pointer is bounds-checked before every access, and a pointer off
+    /// the tape is handled as `config.on_error` says.
+    pub fn with_tape(ast: &[AstNode], config: &BrainfuckConfig, tape: T) -> Result<Self> {
+        validate(config)?;
+        if tape.is_empty() {
+            return Err(RuntimeError::Config(ConfigError::ZeroTapeSize.to_string()));
+        }
+
+        let ast = config.wrap_program(ast);
+        let program = compile(&ast);
+
+        let max_tape_len = match config.tape_model {
+            TapeModel::Fixed => None,
+            TapeModel::Growable => Some(
+                config
+                    .max_tape_bytes
+                    .map_or(usize::MAX, |bytes| bytes / config.cell_size),
+            ),
+        };
+
+        let pointer = if config.allow_negative_tape {
+            tape.len() / 2
+        } else {
+            0
+        };
+
+        Ok(Self {
+            program,
+            tape,
+            pointer,
+            pc: 0,
+            max_tape_len,
+            output_newline: config.output_newline,
+            on_error: config.on_error,
+            cell_wrap: config.cell_wrap,
+            eof_behavior: config.eof_behavior,
+            input_buffer: vec![0; config.input_buffer_size],
+            input_start: 0,
+            input_end: 0,
+            input_left: static_input_count(&ast),
+            max_steps: config.max_steps,
+            steps: 0,
+        })
+    }
+
+    /// Returns true once every instruction has been executed
+    pub fn is_finished(&self) -> bool {
+        self.pc >= self.program.len()
+    }
+
+    /// Current data pointer
+    pub fn pointer(&self) -> usize {
+        self.pointer
+    }
+
+    /// Current tape contents
+    pub fn tape(&self) -> &T {
+        &self.tape
+    }
+
+    /// Execute a single instruction
+    ///
+    /// Returns the executed entry, or `None` if the program already finished.
+    pub fn step(
+        &mut self,
+        input: &mut impl Read,
+        output: &mut impl Write,
+    ) -> Result<Option<TraceEntry>> {
+        let Some((pc, op)) = self.execute(input, output)? else {
+            return Ok(None);
+        };
+
+        Ok(Some(TraceEntry {
+            pc,
+            command: op.token(),
+            pointer: self.pointer,
+            cell_value: self.tape.get(self.pointer),
+        }))
+    }
+
+    /// Run the program until it finishes
+    pub fn run(&mut self, input: &mut impl Read, output: &mut impl Write) -> Result<()> {
+        while self.execute(input, output)?.is_some() {}
+        output.flush()?;
+        Ok(())
+    }
+
+    /// Execute the next instruction and return it with its index, without
+    /// building a [`TraceEntry`]
+    fn execute(
+        &mut self,
+        input: &mut impl Read,
+        output: &mut impl Write,
+    ) -> Result<Option<(usize, Op)>> {
+        let Some(&op) = self.program.get(self.pc) else {
+            return Ok(None);
+        };
+        let pc = self.pc;
+        if let Some(limit) = self.max_steps {
+            if self.steps >= limit {
+                return Err(RuntimeError::StepLimitExceeded { pc, limit });
+            }
+            self.steps += 1;
+        }
+        self.pc += 1;
+
+        match op {
+            Op::Command(Command::Right) => self.move_pointer(pc, 1)?,
+            Op::Command(Command::Left) => self.move_pointer(pc, -1)?,
+            Op::Command(Command::Increment) => self.add_to_cell(pc, self.pointer, 1)?,
+            Op::Command(Command::Decrement) => self.add_to_cell(pc, self.pointer, -1)?,
+            Op::Add(delta) => self.add_to_cell(pc, self.pointer, delta)?,
+            Op::Move(delta) => self.move_pointer(pc, delta)?,
+            Op::SetZero => self.tape.set(self.pointer, 0),
+            Op::MultiplyAdd { offset, factor } => {
+                let target = self.pointer as isize + offset as isize;
+                if let Some(target) = self.cell_index(pc, target)? {
+                    let value = self.tape.get(self.pointer) as i32;
+                    self.add_to_cell(pc, target, factor.wrapping_mul(value))?;
+                }
+            }
+            Op::Command(Command::Output) => {
+                self.write_byte(output, self.tape.get(self.pointer))?;
+            }
+            Op::Command(Command::Input) => {
+                let byte = match (self.read_byte(input)?, self.eof_behavior) {
+                    (Some(byte), _) => Some(byte),
+                    (None, EofBehavior::Unchanged) => None,
+                    (None, EofBehavior::Zero) => Some(0),
+                    (None, EofBehavior::NegativeOne) => Some(255),
+                };
+                if let Some(byte) = byte {
+                    self.tape.set(self.pointer, byte);
+                }
+            }
+            Op::JumpIfZero(target) => {
+                if self.tape.get(self.pointer) == 0 {
+                    self.pc = target;
+                }
+            }
+            Op::JumpIfNonZero(target) => {
+                if self.tape.get(self.pointer) != 0 {
+                    self.pc = target;
+                }
+            }
+        }
+
+        Ok(Some((pc, op)))
+    }
+
+    /// Add `delta` to the cell at `pointer`, handling overflow as
+    /// `cell_wrap` says
+    fn add_to_cell(&mut self, pc: usize, pointer: usize, delta: i32) -> Result<()> {
+        let sum = self.tape.get(pointer) as i32 + delta;
+        let new_value = match (u8::try_from(sum), self.cell_wrap) {
+            (Ok(new_value), _) => new_value,
+            (Err(_), CellWrap::Wrap) => sum.rem_euclid(256) as u8,
+            (Err(_), CellWrap::Saturate) => sum.clamp(0, 255) as u8,
+            (Err(_), CellWrap::Error) => {
+                return Err(RuntimeError::CellOverflow { pc, pointer });
+            }
+        };
+        self.tape.set(pointer, new_value);
+        Ok(())
+    }
+
+    /// Move the data pointer by `delta` cells
+    ///
+    /// A move off the tape is handled as `on_error` says, with the pointer
+    /// stopping on the last cell it could reach.
+    fn move_pointer(&mut self, pc: usize, delta: i32) -> Result<()> {
+        let target = self.pointer as isize + delta as isize;
+        self.pointer = match self.cell_index(pc, target)? {
+            Some(pointer) => pointer,
+            None => PointerMode::Clamp
+                .resolve(target, self.tape.len())
+                .expect("the tape is never empty"),
+        };
+        Ok(())
+    }
+
+    /// Check that `target` is on the tape, growing it if needed
+    ///
+    /// Returns `None` if it isn't and `on_error` says to carry on. The
+    /// reported pointer is the first cell off the tape, as if the pointer
+    /// had moved there one step at a time.
+    fn cell_index(&mut self, pc: usize, target: isize) -> Result<Option<usize>> {
+        while target >= self.tape.len() as isize && self.grow_tape() {}
+        match PointerMode::Trap.resolve(target, self.tape.len()) {
+            Some(index) => Ok(Some(index)),
+            None => {
+                let pointer = if target < 0 {
+                    -1
+                } else {
+                    self.tape.len() as isize
+                };
+                self.pointer_out_of_bounds(pc, pointer)?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Double the length of a growable tape, up to its limit
+    ///
+    /// Returns false if the tape has a fixed size, is already as long as it
+    /// may get, or doesn't support growing.
+    fn grow_tape(&mut self) -> bool {
+        let Some(limit) = self.max_tape_len else {
+            return false;
+        };
+        let len = self.tape.len();
+        let new_len = len.saturating_mul(2).min(limit);
+        new_len > len && self.tape.grow(new_len)
+    }
+
+    /// Handle a pointer move off the tape according to `on_error`
+    ///
+    /// Returns `Ok` if the move should be skipped, leaving the pointer on the
+    /// boundary cell.
+    fn pointer_out_of_bounds(&self, pc: usize, pointer: isize) -> Result<()> {
+        let err = RuntimeError::PointerOutOfBounds { pc, pointer };
+        match self.on_error {
+            OnError::Return => Err(err),
+            OnError::Saturate => Ok(()),
+            OnError::Panic => panic!("{}", err),
+        }
+    }
+
+    /// Read a single byte, returning `None` at end of input
+    ///
+    /// Bytes are served from the internal buffer, which is refilled with one
+    /// `read` call of up to `input_buffer_size` bytes whenever it runs dry.
+    /// End of input is only reported when a refill returns no bytes, so it is
+    /// never mistaken for the end of a full buffer.
+    ///
+    /// When the program's input count is known (see [`static_input_count`]),
+    /// the refill asks for no more than the bytes the program still needs,
+    /// so nothing past them is taken from `input`. A short read is used as
+    /// it is, so interactive input is handled as soon as it arrives.
+    fn read_byte(&mut self, input: &mut impl Read) -> Result<Option<u8>> {
+        if self.input_start == self.input_end {
+            let want = match self.input_left {
+                Some(left) => left.min(self.input_buffer.len()),
+                None => self.input_buffer.len(),
+            };
+            let len = self.fill(input, want)?;
+            if len == 0 {
+                return Ok(None);
+            }
+            self.input_start = 0;
+            self.input_end = len;
+        }
+
+        let byte = self.input_buffer[self.input_start];
+        self.input_start += 1;
+        Ok(Some(byte))
+    }
+
+    /// Refill up to `want` bytes of the buffer with a single `read`,
+    /// returning the bytes read
+    fn fill(&mut self, input: &mut impl Read, want: usize) -> Result<usize> {
+        let len = loop {
+            match input.read(&mut self.input_buffer[..want]) {
+                Ok(len) => break len,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        };
+
+        if let Some(left) = &mut self.input_left {
+            *left -= len;
+        }
+        Ok(len)
+    }
+
+    /// Write a byte, applying the configured newline translation
+    fn write_byte(&self, output: &mut impl Write, byte: u8) -> Result<()> {
+        match (self.output_newline, byte) {
+            (OutputNewline::CrLf, b'\n') => output.write_all(b"\r\n")?,
+            (OutputNewline::Lf, b'\r') => {}
+            _ => output.write_all(&[byte])?,
+        }
+        Ok(())
+    }
+}
+
+fn validate(config: &BrainfuckConfig) -> Result<()> {
+    config
+        .validate()
+        .map_err(|e| RuntimeError::Config(e.to_string()))
+}
+
+/// Run a Brainfuck program with the given input and output streams
+pub fn run(
+    ast: &[AstNode],
+    config: &BrainfuckConfig,
+    input: &mut impl Read,
+    output: &mut impl Write,
+) -> Result<()> {
+    Interpreter::new(ast, config)?.run(input, output)
+}
+
+/// Parse and run Brainfuck source in one call
+pub fn run_source(
+    source: &str,
+    mut input: impl Read,
+    mut output: impl Write,
+    config: &BrainfuckConfig,
+) -> std::result::Result<(), RunError> {
+    let ast = parse_brainfuck(source)?;
+    run(&ast, config, &mut input, &mut output)?;
+    Ok(())
+}
+
+/// Run a Brainfuck program while recording a trace of every step
+///
+/// Unlike [`Interpreter::step`], which executes the fused instructions from
+/// [`compile`], every command and every loop check is its own step here.
+/// Recording stops, and the program is halted, once `max_entries` steps have
+/// been recorded, so long-running programs can't exhaust memory.
+pub fn trace(
+    ast: &[AstNode],
+    config: &BrainfuckConfig,
+    input: &mut impl Read,
+    output: &mut impl Write,
+    max_entries: usize,
+) -> Result<Vec<TraceEntry>> {
+    let mut interpreter = Interpreter::for_tracing(ast, config)?;
+    let mut entries = Vec::new();
+
+    while entries.len() < max_entries {
+        match interpreter.step(input, output)? {
+            Some(entry) => entries.push(entry),
+            None => break,
+        }
+    }
+
+    output.flush()?;
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lamina_builder::optimize::optimize_ast;
+    use crate::lexer::parse_brainfuck;
+
+    fn interpret(source: &str, input: &[u8]) -> Vec<u8> {
+        let ast = parse_brainfuck(source).unwrap();
+        let mut output = Vec::new();
+        run(
+            &ast,
+            &BrainfuckConfig::default(),
+            &mut &input[..],
+            &mut output,
+        )
+        .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_compile_resolves_jump_targets() {
+        let ast = parse_brainfuck("+[-[>]]").unwrap();
+        assert_eq!(
+            compile(&ast),
+            [
+                Op::Add(1),
+                Op::JumpIfZero(7),
+                Op::Add(-1),
+                Op::JumpIfZero(6),
+                Op::Move(1),
+                Op::JumpIfNonZero(4),
+                Op::JumpIfNonZero(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_compile_fuses_runs_and_loops() {
+        let ast = parse_brainfuck("+++-->><[-]<.").unwrap();
+        assert_eq!(
+            compile(&ast),
+            [
+                Op::Add(3),
+                Op::Add(-2),
+                Op::Move(2),
+                Op::Move(-1),
+                Op::SetZero,
+                Op::Move(-1),
+                Op::Command(Command::Output),
+            ]
+        );
+
+        let ast = optimize_ast(&parse_brainfuck(",[->++>+++<<]").unwrap());
+        assert_eq!(
+            compile(&ast),
+            [
+                Op::Command(Command::Input),
+                Op::JumpIfZero(5),
+                Op::MultiplyAdd {
+                    offset: 1,
+                    factor: 2
+                },
+                Op::MultiplyAdd {
+                    offset: 2,
+                    factor: 3
+                },
+                Op::SetZero,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_optimized_ast_runs_like_the_source() {
+        for source in [
+            ",[->++>+++<<]>.>.",
+            ",>+++<[->[-]<[->+<]]>.",
+            "++>,[<+>-]<[->>+<<]>>.",
+        ] {
+            let ast = parse_brainfuck(source).unwrap();
+            let mut expected = Vec::new();
+            let config = BrainfuckConfig::default();
+            run(&ast, &config, &mut &b"\x07"[..], &mut expected).unwrap();
+
+            let mut output = Vec::new();
+            run(&optimize_ast(&ast), &config, &mut &b"\x07"[..], &mut output).unwrap();
+            assert_eq!(output, expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_run_hello() {
+        let source = "++++++++[>+++++++++<-]>.<+++++[>++++++<-]>-.";
+        assert_eq!(interpret(source, b""), b"He");
+    }
+
+    #[test]
+    fn test_run_echo() {
+        assert_eq!(interpret(",.,.", b"hi"), b"hi");
+    }
+
+    #[test]
+    fn test_input_byte_is_incremented_and_written() {
+        assert_eq!(interpret(",+.", &[64]), [65]);
+    }
+
+    #[test]
+    fn test_step_limit_stops_infinite_loops() {
+        let config = BrainfuckConfig {
+            max_steps: Some(100),
+            ..BrainfuckConfig::default()
+        };
+        let ast = parse_brainfuck("+[]").unwrap();
+        let result = run(&ast, &config, &mut io::empty(), &mut io::sink());
+        assert!(matches!(
+            result,
+            Err(RuntimeError::StepLimitExceeded { limit: 100, .. })
+        ));
+
+        // Two steps: the increment and the fused clear loop
+        let ast = parse_brainfuck("+[-]").unwrap();
+        for (limit, finishes) in [(1, false), (2, true)] {
+            let config = BrainfuckConfig {
+                max_steps: Some(limit),
+                ..BrainfuckConfig::default()
+            };
+            let result = run(&ast, &config, &mut io::empty(), &mut io::sink());
+            assert_eq!(result.is_ok(), finishes, "{:?}", result);
+        }
+    }
+
+    #[test]
+    fn test_prelude_and_epilogue() {
+        let config = BrainfuckConfig {
+            // Reads the first byte of input into the cell the program uses
+            prelude: parse_brainfuck(",>++<").unwrap(),
+            epilogue: parse_brainfuck(">[-]++++++++++.").unwrap(),
+            ..BrainfuckConfig::default()
+        };
+        let mut output = Vec::new();
+        run_source("+.>[<+>-]<.", &b"a"[..], &mut output, &config).unwrap();
+        assert_eq!(output, b"bd\n");
+    }
+
+    #[test]
+    fn test_cell_wrap_modes() {
+        let source = format!("{}+.-.", "+".repeat(255));
+        let run_with = |cell_wrap| {
+            let config = BrainfuckConfig {
+                cell_wrap,
+                ..BrainfuckConfig::default()
+            };
+            let mut output = Vec::new();
+            let result = run_source(&source, &b""[..], &mut output, &config);
+            (result, output)
+        };
+
+        let (result, output) = run_with(CellWrap::Wrap);
+        assert!(result.is_ok());
+        assert_eq!(output, [0, 255]);
+
+        let (result, output) = run_with(CellWrap::Saturate);
+        assert!(result.is_ok());
+        assert_eq!(output, [255, 254]);
+
+        let (result, output) = run_with(CellWrap::Error);
+        assert!(matches!(
+            result,
+            Err(RunError::Runtime(RuntimeError::CellOverflow {
+                pc: 0,
+                pointer: 0
+            }))
+        ));
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_eof_behavior() {
+        for (eof_behavior, expected) in [
+            (EofBehavior::Unchanged, 1),
+            (EofBehavior::Zero, 0),
+            (EofBehavior::NegativeOne, 255),
+        ] {
+            let config = BrainfuckConfig {
+                eof_behavior,
+                ..BrainfuckConfig::default()
+            };
+            let mut output = Vec::new();
+            run_source("+,.", &b""[..], &mut output, &config).unwrap();
+            assert_eq!(output, [expected], "{:?}", eof_behavior);
+        }
+    }
+
+    /// Reader that counts its `read` calls
+    struct CountingReader<'a> {
+        data: &'a [u8],
+        reads: usize,
+    }
+
+    impl Read for CountingReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.reads += 1;
+            self.data.read(buf)
+        }
+    }
+
+    #[test]
+    fn test_fixed_input_count_is_read_in_one_block() {
+        let ast = parse_brainfuck(",>,>,<<.>.>.").unwrap();
+        let mut reader = CountingReader {
+            data: b"abcdef",
+            reads: 0,
+        };
+        let mut output = Vec::new();
+        run(&ast, &BrainfuckConfig::default(), &mut reader, &mut output).unwrap();
+
+        assert_eq!(output, b"abc");
+        assert_eq!(reader.reads, 1);
+        // Input the program never reads is left in the reader
+        assert_eq!(reader.data, b"def");
+
+        // Loop-controlled input is read ahead as far as the buffer allows
+        let ast = parse_brainfuck(",[.[-],]").unwrap();
+        let mut reader = CountingReader {
+            data: b"abcdef",
+            reads: 0,
+        };
+        let mut output = Vec::new();
+        run(&ast, &BrainfuckConfig::default(), &mut reader, &mut output).unwrap();
+        assert_eq!(output, b"abcdef");
+        assert_eq!(reader.reads, 2);
+    }
+
+    /// Reader that hands out one byte per `read`, then blocks like a
+    /// terminal waiting for more
+    struct InteractiveReader<'a> {
+        data: &'a [u8],
+    }
+
+    impl Read for InteractiveReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match self.data.split_first() {
+                Some((&byte, rest)) if !buf.is_empty() => {
+                    buf[0] = byte;
+                    self.data = rest;
+                    Ok(1)
+                }
+                _ => Err(io::ErrorKind::WouldBlock.into()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_fixed_input_count_uses_short_reads() {
+        // Each byte is echoed as soon as it is read, without waiting for the
+        // rest of the block
+        let ast = parse_brainfuck(",.,.,.").unwrap();
+        let mut reader = InteractiveReader { data: b"ab" };
+        let mut output = Vec::new();
+        let result = run(&ast, &BrainfuckConfig::default(), &mut reader, &mut output);
+
+        assert!(matches!(result, Err(RuntimeError::Io(_))));
+        assert_eq!(output, b"ab");
+    }
+
+    #[test]
+    fn test_input_across_buffer_boundaries() {
+        // Echo until end of input, then read twice more past the end
+        let ast = parse_brainfuck(",[.[-],]+,,.").unwrap();
+        let input = b"abcdef";
+
+        for input_buffer_size in 1..=8 {
+            let config = BrainfuckConfig {
+                input_buffer_size,
+                ..BrainfuckConfig::default()
+            };
+            let mut reader = CountingReader {
+                data: input,
+                reads: 0,
+            };
+            let mut output = Vec::new();
+            run(&ast, &config, &mut reader, &mut output).unwrap();
+
+            assert_eq!(output, b"abcdef\x01", "buffer of {}", input_buffer_size);
+            // Full refills, a final partial one, then three reads at the end
+            assert_eq!(reader.reads, input.len().div_ceil(input_buffer_size) + 3);
+        }
+    }
+
+    #[test]
+    fn test_run_source_hello_world() {
+        let mut output = Vec::new();
+        run_source(
+            include_str!("../testcases/hello_world.bf"),
+            io::empty(),
+            &mut output,
+            &BrainfuckConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(output, b"Hello, World!");
+    }
+
+    #[test]
+    fn test_run_source_errors() {
+        let config = BrainfuckConfig::default();
+        assert!(matches!(
+            run_source("[", io::empty(), io::sink(), &config),
+            Err(RunError::Parse(LexerError::UnmatchedOpeningBracket { .. }))
+        ));
+        assert!(matches!(
+            run_source("<", io::empty(), io::sink(), &config),
+            Err(RunError::Runtime(RuntimeError::PointerOutOfBounds { .. }))
+        ));
+    }
+
+    #[test]
+    fn test_custom_tape() {
+        use crate::tape::SparseTape;
+
+        // Far more cells than could be allocated up front
+        let ast = parse_brainfuck(">>>++[>+<-]>.").unwrap();
+        let tape = SparseTape::new(1 << 40);
+        let mut interpreter =
+            Interpreter::with_tape(&ast, &BrainfuckConfig::default(), tape).unwrap();
+        let mut output = Vec::new();
+        interpreter.run(&mut io::empty(), &mut output).unwrap();
+
+        assert_eq!(output, [2]);
+        assert_eq!(interpreter.tape().get(4), 2);
+        assert_eq!(interpreter.tape().nonzero_cells(), 1);
+
+        // The pointer is checked against the tape's own length
+        let ast = parse_brainfuck(">>").unwrap();
+        let tape = FixedTape::new(2);
+        assert!(matches!(
+            run_with_tape(&ast, tape),
+            Err(RuntimeError::PointerOutOfBounds { pc: 0, pointer: 2 })
+        ));
+        let tape = FixedTape::new(0);
+        assert!(matches!(
+            run_with_tape(&ast, tape),
+            Err(RuntimeError::Config(_))
+        ));
+    }
+
+    fn run_with_tape(ast: &[AstNode], tape: FixedTape) -> Result<()> {
+        Interpreter::with_tape(ast, &BrainfuckConfig::default(), tape)?
+            .run(&mut io::empty(), &mut io::sink())
+    }
+
+    #[test]
+    fn test_growable_tape() {
+        let ast = parse_brainfuck(&(">".repeat(100) + "+++.")).unwrap();
+        let config = BrainfuckConfig {
+            tape_size: 4,
+            tape_model: TapeModel::Growable,
+            ..BrainfuckConfig::default()
+        };
+        let mut interpreter = Interpreter::new(&ast, &config).unwrap();
+        let mut output = Vec::new();
+        interpreter.run(&mut io::empty(), &mut output).unwrap();
+        assert_eq!(output, [3]);
+        assert_eq!(interpreter.tape().len(), 128);
+
+        // Growth stops at max_tape_bytes
+        let config = BrainfuckConfig {
+            max_tape_bytes: Some(50),
+            ..config
+        };
+        assert!(matches!(
+            run(&ast, &config, &mut io::empty(), &mut io::sink()),
+            Err(RuntimeError::PointerOutOfBounds { pc: 0, pointer: 50 })
+        ));
+    }
+
+    #[test]
+    fn test_negative_tape() {
+        // Cell -1 is distinct from cell 0
+        let ast = parse_brainfuck("+<++>.<.").unwrap();
+        let config = BrainfuckConfig {
+            tape_size: 4,
+            allow_negative_tape: true,
+            ..BrainfuckConfig::default()
+        };
+        let mut interpreter = Interpreter::new(&ast, &config).unwrap();
+        let mut output = Vec::new();
+        interpreter.run(&mut io::empty(), &mut output).unwrap();
+        assert_eq!(output, [1, 2]);
+        assert_eq!(interpreter.tape().as_slice(), [0, 2, 1, 0]);
+
+        let ast = parse_brainfuck("<<<").unwrap();
+        assert!(matches!(
+            run(&ast, &config, &mut io::empty(), &mut io::sink()),
+            Err(RuntimeError::PointerOutOfBounds { pc: 0, pointer: -1 })
+        ));
+    }
+
+    #[test]
+    fn test_on_error_strategies() {
+        let ast = parse_brainfuck("+<+.>>>+.").unwrap();
+        let run_with = |on_error| {
+            let config = BrainfuckConfig {
+                tape_size: 3,
+                on_error,
+                ..BrainfuckConfig::default()
+            };
+            let mut output = Vec::new();
+            run(&ast, &config, &mut io::empty(), &mut output).map(|_| output)
+        };
+
+        assert!(matches!(
+            run_with(OnError::Return),
+            Err(RuntimeError::PointerOutOfBounds { pc: 1, pointer: -1 })
+        ));
+        // The pointer stops at cell 0, then at cell 2
+        assert_eq!(run_with(OnError::Saturate).unwrap(), [2, 1]);
+
+        let panicked = std::panic::catch_unwind(|| run_with(OnError::Panic));
+        let message = panicked.unwrap_err();
+        assert!(
+            message
+                .downcast_ref::<String>()
+                .unwrap()
+                .contains("out of bounds to cell -1")
+        );
+    }
+
+    #[test]
+    fn test_pointer_out_of_bounds() {
+        let ast = parse_brainfuck("<").unwrap();
+        let result = run(
+            &ast,
+            &BrainfuckConfig::default(),
+            &mut io::empty(),
+            &mut io::sink(),
+        );
+        assert!(matches!(
+            result,
+            Err(RuntimeError::PointerOutOfBounds { pc: 0, pointer: -1 })
+        ));
+    }
+
+    #[test]
+    fn test_oversized_tape_is_rejected_before_allocation() {
+        let ast = parse_brainfuck("+").unwrap();
+        let result = run(
+            &ast,
+            &BrainfuckConfig::new(1 << 40, 1),
+            &mut io::empty(),
+            &mut io::sink(),
+        );
+        assert!(matches!(result, Err(RuntimeError::Config(_))));
+    }
+
+    #[test]
+    fn test_output_newline_translation() {
+        let ast = parse_brainfuck("++++++++++.+++.").unwrap();
+
+        for (mode, expected) in [
+            (OutputNewline::AsIs, &b"\n\r"[..]),
+            (OutputNewline::CrLf, &b"\r\n\r"[..]),
+            (OutputNewline::Lf, &b"\n"[..]),
+        ] {
+            let config = BrainfuckConfig {
+                output_newline: mode,
+                ..BrainfuckConfig::default()
+            };
+            let mut output = Vec::new();
+            run(&ast, &config, &mut io::empty(), &mut output).unwrap();
+            assert_eq!(output, expected, "mode {:?}", mode);
+        }
+    }
+
+    #[test]
+    fn test_trace_records_steps() {
+        let ast = parse_brainfuck("+>+[-]").unwrap();
+        let entries = trace(
+            &ast,
+            &BrainfuckConfig::default(),
+            &mut io::empty(),
+            &mut io::sink(),
+            100,
+        )
+        .unwrap();
+
+        let steps: Vec<_> = entries
+            .iter()
+            .map(|e| (e.pc, e.command, e.pointer, e.cell_value))
+            .collect();
+        assert_eq!(
+            steps,
+            vec![
+                (0, Token::Increment, 0, 1),
+                (1, Token::Right, 1, 0),
+                (2, Token::Increment, 1, 1),
+                (3, Token::LoopStart, 1, 1),
+                (4, Token::Decrement, 1, 0),
+                (5, Token::LoopEnd, 1, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_trace_is_capped() {
+        let ast = parse_brainfuck("+[]").unwrap();
+        let entries = trace(
+            &ast,
+            &BrainfuckConfig::default(),
+            &mut io::empty(),
+            &mut io::sink(),
+            10,
+        )
+        .unwrap();
+        assert_eq!(entries.len(), 10);
+    }
+}
@@ -1,53 +1,168 @@
 //! Compiler functions for converting Brainfuck to various output formats
 
-use super::config::BrainfuckConfig;
-use super::ir_builder::BrainfuckIRBuilder;
-use crate::lexer::AstNode;
+use super::config::{
+    BrainfuckConfig, CellWrap, CompilerToolchain, DEFAULT_COMPILERS, EofBehavior, LaminaOptions,
+    LinkMode, OutputNewline, TapeModel, default_build_dir,
+};
+use super::ir_builder::{
+    BrainfuckIRBuilder, READ_BYTE_FUNCTION, TAPE_BASE_FUNCTION, TAPE_GLOBAL, TRAP_FUNCTION,
+};
+use super::utils::{check_static_bounds, net_pointer_movement};
+use crate::lexer::{AstNode, Command, LexerError, Position};
+use lamina::ir::Module;
+use std::fmt::Write as _;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+/// Error type for every compile function and
+/// [`compile_and_run`](crate::runner::compile_and_run)
+#[derive(Debug)]
+pub enum CompileError {
+    /// The source failed to parse
+    ///
+    /// The compile functions take an AST, so they never return this
+    /// themselves; it lets callers parse and compile with `?` alone.
+    Parse(LexerError),
+    /// Generating code from the AST failed, for example because the
+    /// configuration is invalid or unsupported by the backend
+    IrGeneration(String),
+    /// Lamina failed to compile the IR to assembly
+    LaminaCompile(String),
+    /// Lamina's assembly is empty or has no `main` label, so it was
+    /// truncated or the backend failed without reporting an error
+    EmptyAssembly,
+    /// The assembler rejected the generated assembly
+    Assemble(String),
+    /// Linking the object file failed
+    Link(String),
+    /// Writing an intermediate file or running the toolchain failed
+    Io(std::io::Error),
+    /// The compiled program was killed by a signal instead of exiting
+    Terminated(String),
+    /// A static library's symbol name isn't a valid C identifier
+    InvalidSymbol(String),
+}
+
+impl std::fmt::Display for CompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompileError::Parse(err) => write!(f, "Parse error: {}", err),
+            CompileError::IrGeneration(msg) => write!(f, "IR generation failed: {}", msg),
+            CompileError::LaminaCompile(msg) => write!(f, "Lamina compilation failed: {}", msg),
+            CompileError::EmptyAssembly => {
+                write!(
+                    f,
+                    "Lamina produced no assembly for the program's main function"
+                )
+            }
+            CompileError::Assemble(msg) => write!(f, "Assembling failed: {}", msg),
+            CompileError::Link(msg) => write!(f, "Linking failed: {}", msg),
+            CompileError::Io(err) => write!(f, "I/O error: {}", err),
+            CompileError::Terminated(status) => {
+                write!(f, "Program terminated abnormally ({})", status)
+            }
+            CompileError::InvalidSymbol(symbol) => write!(f, "Invalid symbol name: {:?}", symbol),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompileError::Parse(err) => Some(err),
+            CompileError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<LexerError> for CompileError {
+    fn from(err: LexerError) -> Self {
+        CompileError::Parse(err)
+    }
+}
+
+impl From<std::io::Error> for CompileError {
+    fn from(err: std::io::Error) -> Self {
+        CompileError::Io(err)
+    }
+}
+
+/// Files produced by [`compile_binary`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompileOutput {
+    /// The executable
+    pub binary_path: PathBuf,
+    /// The Lamina IR, if it was kept (see
+    /// [`BrainfuckConfig::keep_intermediates`])
+    pub ir_path: Option<PathBuf>,
+    /// The assembly, if it was kept
+    pub asm_path: Option<PathBuf>,
+    /// Command that linked the executable
+    pub linker: String,
+}
+
+/// Wall-clock time spent in each stage of [`compile_instrumented`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CompileTimings {
+    /// Running the AST optimization passes
+    pub optimize: Duration,
+    /// Building the Lamina IR module and printing it
+    pub ir_build: Duration,
+    /// Compiling the IR to assembly with Lamina
+    pub ir_to_asm: Duration,
+    /// Assembling to an object file
+    pub assemble: Duration,
+    /// Linking the object file into an executable
+    pub link: Duration,
+}
+
+impl CompileTimings {
+    /// Total time across all stages
+    pub fn total(&self) -> Duration {
+        self.optimize + self.ir_build + self.ir_to_asm + self.assemble + self.link
+    }
+}
+
+/// Convert Brainfuck AST to a Lamina IR module
+///
+/// This is [`brainfuck_to_lamina_ir`] without the final printing step, for
+/// callers that want to inspect or transform the module first. The module
+/// is built with `builder`'s configuration and borrows the block and value
+/// names it generates, so it can't outlive the builder.
+pub fn brainfuck_to_ir_module<'a>(
+    ast: &[AstNode],
+    builder: &'a BrainfuckIRBuilder,
+) -> Result<Module<'a>, CompileError> {
+    builder.build_ir(ast)
+}
 
 /// Convert Brainfuck AST to Lamina IR
-pub fn brainfuck_to_lamina_ir(ast: &[AstNode]) -> Result<String, String> {
-    let builder = BrainfuckIRBuilder::new();
-    let module = builder.build_ir(ast)?;
-    Ok(module.to_string())
+pub fn brainfuck_to_lamina_ir(ast: &[AstNode]) -> Result<String, CompileError> {
+    brainfuck_to_lamina_ir_with_config(ast, BrainfuckConfig::default())
 }
 
 /// Convert Brainfuck AST to Lamina IR with custom configuration
 pub fn brainfuck_to_lamina_ir_with_config(
     ast: &[AstNode],
     config: BrainfuckConfig,
-) -> Result<String, String> {
+) -> Result<String, CompileError> {
     let builder = BrainfuckIRBuilder::with_config(config);
-    let module = builder.build_ir(ast)?;
-    Ok(module.to_string())
+    Ok(brainfuck_to_ir_module(ast, &builder)?.to_string())
 }
 
 /// Convert Brainfuck AST to assembly code
-pub fn brainfuck_to_assembly(ast: &[AstNode]) -> Result<String, String> {
-    let builder = BrainfuckIRBuilder::new();
-    let module = builder.build_ir(ast)?;
-
-    // Convert module to IR string
-    let ir_source = module.to_string();
-
-    // Compile IR to assembly using Lamina
-    let mut asm_buffer = Vec::new();
-    match lamina::compile_lamina_ir_to_assembly(&ir_source, &mut asm_buffer) {
-        Ok(_) => {
-            // Convert assembly bytes to string
-            match String::from_utf8(asm_buffer) {
-                Ok(assembly) => Ok(assembly),
-                Err(e) => Err(format!("Failed to convert assembly to string: {}", e)),
-            }
-        }
-        Err(e) => Err(format!("Lamina compilation failed: {}", e)),
-    }
+pub fn brainfuck_to_assembly(ast: &[AstNode]) -> Result<String, CompileError> {
+    brainfuck_to_assembly_with_config(ast, BrainfuckConfig::default())
 }
 
 /// Convert Brainfuck AST to assembly code with custom configuration
 pub fn brainfuck_to_assembly_with_config(
     ast: &[AstNode],
     config: BrainfuckConfig,
-) -> Result<String, String> {
+) -> Result<String, CompileError> {
+    let options = config.lamina.clone();
     let builder = BrainfuckIRBuilder::with_config(config);
     let module = builder.build_ir(ast)?;
 
@@ -55,133 +170,2618 @@ pub fn brainfuck_to_assembly_with_config(
     let ir_source = module.to_string();
 
     // Compile IR to assembly using Lamina
-    let mut asm_buffer = Vec::new();
-    match lamina::compile_lamina_ir_to_assembly(&ir_source, &mut asm_buffer) {
-        Ok(_) => {
-            // Convert assembly bytes to string
-            match String::from_utf8(asm_buffer) {
-                Ok(assembly) => Ok(assembly),
-                Err(e) => Err(format!("Failed to convert assembly to string: {}", e)),
+    let asm_buffer =
+        lamina_to_assembly(&ir_source, &options).map_err(CompileError::LaminaCompile)?;
+
+    // Convert assembly bytes to string
+    String::from_utf8(asm_buffer).map_err(|e| {
+        CompileError::LaminaCompile(format!("Failed to convert assembly to string: {}", e))
+    })
+}
+
+/// Convert Brainfuck AST to textual LLVM IR
+///
+/// Lamina can't lower to LLVM, so this emits LLVM IR directly from the AST.
+/// The output is a self-contained module with a `main` function that uses
+/// `getchar`/`putchar` for I/O, suitable for `llvm-as`, `opt`, or `clang`.
+/// At end of input the current cell is left unchanged.
+///
+/// Output goes through libc's buffered `putchar`, so the generated code
+/// flushes stdout before every read (keeping prompts visible to interactive
+/// users) and before returning from `main`.
+///
+/// With `config.trace` set, every executed command is logged to stderr as
+/// `<command> <pointer> <cell>`, matching the interpreter's trace entries.
+///
+/// Loops whose body leaves the pointer where it started load the pointer once
+/// per iteration and address cells at fixed offsets from that snapshot.
+/// Tracing needs the live pointer, so it disables this.
+///
+/// With `config.annotate_ir` set, each node's code is preceded by a comment
+/// naming the node and its pre-order index.
+///
+/// With `config.exit_from_cell` set, `main` returns the value of the cell
+/// the pointer ends on.
+pub fn brainfuck_to_llvm_ir(
+    ast: &[AstNode],
+    config: &BrainfuckConfig,
+) -> Result<String, CompileError> {
+    llvm_module(ast, &[], config, !config.trace).map_err(CompileError::IrGeneration)
+}
+
+/// Convert Brainfuck AST to textual LLVM IR, annotating each node with its
+/// source position
+///
+/// `spans` are the node positions returned by
+/// [`parse_brainfuck_spanned`](crate::lexer::parse_brainfuck_spanned) for the
+/// same AST. They only appear in the output when `config.annotate_ir` is set.
+pub fn brainfuck_to_llvm_ir_with_spans(
+    ast: &[AstNode],
+    spans: &[Position],
+    config: &BrainfuckConfig,
+) -> Result<String, CompileError> {
+    llvm_module(ast, spans, config, !config.trace).map_err(CompileError::IrGeneration)
+}
+
+fn llvm_module(
+    ast: &[AstNode],
+    spans: &[Position],
+    config: &BrainfuckConfig,
+    hoist_pointer: bool,
+) -> Result<String, String> {
+    config.validate().map_err(|e| e.to_string())?;
+    if config.tape_model == TapeModel::Growable {
+        // The tape is a single fixed-size global
+        return Err("A growable tape is not supported by the LLVM backend".to_string());
+    }
+    if config.runtime_bounds_check.is_some() {
+        return Err("Runtime bounds checks are not supported by the LLVM backend".to_string());
+    }
+    // The optimizer assumes cells start at zero, which the prelude can
+    // change, so it sees the whole program
+    let wrapped = config.wrap_program(ast);
+    let optimized = config.optimize_program(&wrapped);
+    // Optimized nodes no longer line up with the source spans
+    let spans = if optimized == wrapped { spans } else { &[][..] };
+    check_static_bounds(&optimized, config)?;
+
+    let mut emitter = LlvmEmitter {
+        body: String::new(),
+        next_id: 0,
+        output_newline: config.output_newline,
+        cell_wrap: config.cell_wrap,
+        eof_behavior: config.eof_behavior,
+        trace: config.trace,
+        hoist_pointer,
+        snapshot: None,
+        annotate: config.annotate_ir,
+        spans,
+        node_index: 0,
+    };
+    if optimized == wrapped {
+        // Only the program itself lines up with `spans`
+        emitter.emit_expanded(&config.prelude);
+        emitter.emit_nodes(ast);
+        emitter.emit_expanded(&config.epilogue);
+    } else {
+        emitter.emit_nodes(&optimized);
+    }
+
+    let exit_code = if config.exit_from_cell {
+        let cell = emitter.cell_ptr();
+        let value = emitter.fresh();
+        let code = emitter.fresh();
+        emitter.line(&format!("%v{} = load i8, ptr {}", value, cell));
+        emitter.line(&format!("%v{} = zext i8 %v{} to i32", code, value));
+        format!("%v{}", code)
+    } else {
+        "0".to_string()
+    };
+
+    let tape_bytes = config.allocated_tape_bytes();
+    let mut module = String::new();
+    module.push_str("; Generated by bfina\n");
+    module.push_str("declare i32 @getchar()\n");
+    module.push_str("declare i32 @putchar(i32)\n");
+    module.push_str("declare i32 @fflush(ptr)\n");
+    if config.cell_wrap == CellWrap::Error {
+        module.push_str("declare void @llvm.trap()\n");
+    }
+    if config.trace {
+        module.push_str("declare i32 @dprintf(i32, ptr, ...)\n");
+        module.push_str("@trace_fmt = private constant [11 x i8] c\"%c %ld %d\\0A\\00\"\n");
+    }
+    // A global rather than an alloca, so large tapes don't overflow the
+    // stack, and zero-filled when the program loads
+    let _ = writeln!(
+        module,
+        "@tape = internal global [{} x i8] zeroinitializer, align 16",
+        tape_bytes
+    );
+    module.push('\n');
+    module.push_str("define i32 @main() {\n");
+    module.push_str("entry:\n");
+    module.push_str("  %ptr = alloca i64\n");
+    let _ = writeln!(module, "  store i64 {}, ptr %ptr", config.start_cell());
+    module.push_str(&emitter.body);
+    module.push_str("  call i32 @fflush(ptr null)\n");
+    let _ = writeln!(module, "  ret i32 {}", exit_code);
+    module.push_str("}\n");
+
+    Ok(module)
+}
+
+/// Emits the body of the LLVM `main` function
+struct LlvmEmitter<'a> {
+    body: String,
+    next_id: usize,
+    output_newline: OutputNewline,
+    cell_wrap: CellWrap,
+    eof_behavior: EofBehavior,
+    trace: bool,
+    /// Address cells relative to a pointer snapshot in balanced loops
+    hoist_pointer: bool,
+    /// Base cell pointer of the enclosing balanced loop and the current
+    /// offset from it; while set, `%ptr` is neither loaded nor stored
+    snapshot: Option<(String, isize)>,
+    /// Precede each node's code with a descriptive comment
+    annotate: bool,
+    /// Source positions of the nodes in pre-order, if known
+    spans: &'a [Position],
+    /// Pre-order index of the next node to annotate
+    node_index: usize,
+}
+
+impl LlvmEmitter<'_> {
+    /// Allocate a fresh SSA value or label id
+    fn fresh(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id
+    }
+
+    fn line(&mut self, line: &str) {
+        self.body.push_str("  ");
+        self.body.push_str(line);
+        self.body.push('\n');
+    }
+
+    fn label(&mut self, label: &str) {
+        self.body.push_str(label);
+        self.body.push_str(":\n");
+    }
+
+    /// Emit a pointer to the current cell and return its SSA name
+    fn cell_ptr(&mut self) -> String {
+        self.cell_ptr_at(0)
+    }
+
+    /// Emit a pointer to the cell `offset` cells from the current one,
+    /// without moving the data pointer
+    fn cell_ptr_at(&mut self, offset: isize) -> String {
+        if let Some((base, base_offset)) = self.snapshot.clone() {
+            if base_offset + offset == 0 {
+                return base;
             }
+            let cell = self.fresh();
+            self.line(&format!(
+                "%v{} = getelementptr i8, ptr {}, i64 {}",
+                cell,
+                base,
+                base_offset + offset
+            ));
+            return format!("%v{}", cell);
+        }
+
+        let mut index = self.fresh();
+        self.line(&format!("%v{} = load i64, ptr %ptr", index));
+        if offset != 0 {
+            let shifted = self.fresh();
+            self.line(&format!("%v{} = add i64 %v{}, {}", shifted, index, offset));
+            index = shifted;
         }
-        Err(e) => Err(format!("Lamina compilation failed: {}", e)),
+        let cell = self.fresh();
+        self.line(&format!(
+            "%v{} = getelementptr i8, ptr @tape, i64 %v{}",
+            cell, index
+        ));
+        format!("%v{}", cell)
     }
-}
 
-/// Convert Brainfuck AST to binary executable
-pub fn brainfuck_to_binary(ast: &[AstNode], output_path: &str) -> Result<String, String> {
-    let builder = BrainfuckIRBuilder::new();
-    let module = builder.build_ir(ast)?;
+    fn emit_nodes(&mut self, ast: &[AstNode]) {
+        for node in ast {
+            if self.annotate {
+                self.emit_annotation(node);
+            }
 
-    // Convert module to IR string
-    let ir_source = module.to_string();
+            match node {
+                AstNode::Command(cmd) => {
+                    self.emit_command(*cmd);
+                    self.emit_trace(cmd.as_char());
+                }
+                AstNode::Loop(body) => self.emit_loop(body),
+                // Traces log every step of the original commands
+                AstNode::SetZero
+                | AstNode::Add(_)
+                | AstNode::Move(_)
+                | AstNode::OutputAtOffset(_)
+                | AstNode::InputAtOffset(_)
+                | AstNode::CopyAndClear(_)
+                | AstNode::MultiplyLoop(_)
+                    if self.trace =>
+                {
+                    self.emit_expanded(&node.expand());
+                }
+                AstNode::SetZero => {
+                    let cell = self.cell_ptr();
+                    self.line(&format!("store i8 0, ptr {}", cell));
+                }
+                AstNode::Add(delta) => self.emit_add(*delta),
+                AstNode::Move(delta) => self.emit_move(*delta as isize),
+                AstNode::OutputAtOffset(offset) => {
+                    let cell = self.cell_ptr_at(*offset as isize);
+                    self.emit_output(&cell);
+                }
+                AstNode::InputAtOffset(offset) => {
+                    let cell = self.cell_ptr_at(*offset as isize);
+                    self.emit_input(&cell);
+                }
+                AstNode::CopyAndClear(offset) => {
+                    let source = self.cell_ptr();
+                    let dest = self.cell_ptr_at(*offset as isize);
+                    let value = self.fresh();
+                    self.line(&format!("%v{} = load i8, ptr {}", value, source));
+                    self.line(&format!("store i8 %v{}, ptr {}", value, dest));
+                    self.line(&format!("store i8 0, ptr {}", source));
+                }
+                AstNode::MultiplyLoop(targets) => {
+                    // Like the loop, touch the targets only if the cell is nonzero
+                    let id = self.fresh();
+                    let (body, done) = (format!("mul_body{}", id), format!("mul_done{}", id));
+                    self.emit_branch_on_cell(&body, &done);
+                    self.label(&body);
+                    let source = self.cell_ptr();
+                    let value = self.fresh();
+                    self.line(&format!("%v{} = load i8, ptr {}", value, source));
+                    for target in targets {
+                        let dest = self.cell_ptr_at(target.offset as isize);
+                        let old = self.fresh();
+                        let product = self.fresh();
+                        let sum = self.fresh();
+                        self.line(&format!("%v{} = load i8, ptr {}", old, dest));
+                        self.line(&format!(
+                            "%v{} = mul i8 %v{}, {}",
+                            product, value, target.factor as u8 as i8
+                        ));
+                        self.line(&format!("%v{} = add i8 %v{}, %v{}", sum, old, product));
+                        self.line(&format!("store i8 %v{}, ptr {}", sum, dest));
+                    }
+                    self.line(&format!("store i8 0, ptr {}", source));
+                    self.line(&format!("br label %{}", done));
+                    self.label(&done);
+                }
+            }
+        }
+    }
 
-    // Check if a .lamina file already exists (created by main.rs)
-    let lamina_file = format!("{}.lamina", output_path);
-    let lamina_file_exists = std::path::Path::new(&lamina_file).exists();
+    /// Emit nodes without annotating them, for code that stands in for a
+    /// single source node or isn't part of the source at all
+    fn emit_expanded(&mut self, nodes: &[AstNode]) {
+        let annotate = std::mem::replace(&mut self.annotate, false);
+        self.emit_nodes(nodes);
+        self.annotate = annotate;
+    }
 
-    if !lamina_file_exists {
-        // Write IR to .lamina file only if it doesn't exist
-        std::fs::write(&lamina_file, &ir_source)
-            .map_err(|e| format!("Failed to write Lamina IR file: {}", e))?;
+    /// Emit a comment naming `node`, its index, and its source position
+    fn emit_annotation(&mut self, node: &AstNode) {
+        let name = match node {
+            AstNode::Command(cmd) => format!("{:?}", cmd),
+            AstNode::Loop(_) => "Loop".to_string(),
+            AstNode::SetZero => "SetZero".to_string(),
+            AstNode::Add(delta) => format!("Add({})", delta),
+            AstNode::Move(delta) => format!("Move({})", delta),
+            AstNode::OutputAtOffset(offset) => format!("OutputAtOffset({})", offset),
+            AstNode::InputAtOffset(offset) => format!("InputAtOffset({})", offset),
+            AstNode::CopyAndClear(offset) => format!("CopyAndClear({})", offset),
+            AstNode::MultiplyLoop(targets) => {
+                let targets: Vec<String> = targets
+                    .iter()
+                    .map(|target| format!("{}*{}", target.offset, target.factor))
+                    .collect();
+                format!("MultiplyLoop({})", targets.join(", "))
+            }
+        };
+        let location = match self.spans.get(self.node_index) {
+            Some(pos) => format!(" (line {}, col {})", pos.line, pos.column),
+            None => String::new(),
+        };
+        self.line(&format!("; cmd {}: {}{}", self.node_index, name, location));
+        self.node_index += 1;
     }
 
-    // Use the normal Lamina library to compile
-    match compile_with_lamina_library(&ir_source, output_path) {
-        Ok(_) => {
-            // Only clean up if we created the file
-            if !lamina_file_exists {
-                let _ = std::fs::remove_file(&lamina_file);
+    /// Log the command and the current pointer and cell to stderr
+    fn emit_trace(&mut self, command: char) {
+        if !self.trace {
+            return;
+        }
+
+        let (index, cell, value, wide) = (self.fresh(), self.fresh(), self.fresh(), self.fresh());
+        self.line(&format!("%v{} = load i64, ptr %ptr", index));
+        self.line(&format!(
+            "%v{} = getelementptr i8, ptr @tape, i64 %v{}",
+            cell, index
+        ));
+        self.line(&format!("%v{} = load i8, ptr %v{}", value, cell));
+        self.line(&format!("%v{} = zext i8 %v{} to i32", wide, value));
+        self.line(&format!(
+            "call i32 (i32, ptr, ...) @dprintf(i32 2, ptr @trace_fmt, i32 {}, i64 %v{}, i32 %v{})",
+            command as u32, index, wide
+        ));
+    }
+
+    fn emit_command(&mut self, cmd: Command) {
+        match cmd {
+            Command::Right => self.emit_move(1),
+            Command::Left => self.emit_move(-1),
+            Command::Increment => self.emit_add(1),
+            Command::Decrement => self.emit_add(-1),
+            Command::Output => {
+                let cell = self.cell_ptr();
+                self.emit_output(&cell);
             }
-            Ok(format!("Binary executable created: {}", output_path))
+            Command::Input => {
+                let cell = self.cell_ptr();
+                self.emit_input(&cell);
+            }
+        }
+    }
+
+    /// Add `delta` to the current cell, handling overflow as `cell_wrap` says
+    fn emit_add(&mut self, delta: i32) {
+        let cell = self.cell_ptr();
+        let (old, new) = (self.fresh(), self.fresh());
+        self.line(&format!("%v{} = load i8, ptr {}", old, cell));
+
+        if self.cell_wrap == CellWrap::Wrap {
+            self.line(&format!(
+                "%v{} = add i8 %v{}, {}",
+                new,
+                old,
+                delta.rem_euclid(256) as u8 as i8
+            ));
+            self.line(&format!("store i8 %v{}, ptr {}", new, cell));
+            return;
+        }
+
+        // Add in 32 bits, where both overflow and underflow leave 0..=255
+        // when compared unsigned
+        let (wide, sum, out_of_range) = (self.fresh(), self.fresh(), self.fresh());
+        self.line(&format!("%v{} = zext i8 %v{} to i32", wide, old));
+        self.line(&format!("%v{} = add i32 %v{}, {}", sum, wide, delta));
+        self.line(&format!("%v{} = icmp ugt i32 %v{}, 255", out_of_range, sum));
+
+        if self.cell_wrap == CellWrap::Error {
+            let id = self.fresh();
+            self.line(&format!(
+                "br i1 %v{}, label %cell_trap{}, label %cell_ok{}",
+                out_of_range, id, id
+            ));
+            self.label(&format!("cell_trap{}", id));
+            self.line("call void @llvm.trap()");
+            self.line("unreachable");
+            self.label(&format!("cell_ok{}", id));
+            self.line(&format!("%v{} = trunc i32 %v{} to i8", new, sum));
+        } else {
+            let truncated = self.fresh();
+            let limit = if delta > 0 { -1 } else { 0 };
+            self.line(&format!("%v{} = trunc i32 %v{} to i8", truncated, sum));
+            self.line(&format!(
+                "%v{} = select i1 %v{}, i8 {}, i8 %v{}",
+                new, out_of_range, limit, truncated
+            ));
+        }
+        self.line(&format!("store i8 %v{}, ptr {}", new, cell));
+    }
+
+    /// Write the byte stored at `cell`
+    fn emit_output(&mut self, cell: &str) {
+        let (value, wide) = (self.fresh(), self.fresh());
+        self.line(&format!("%v{} = load i8, ptr {}", value, cell));
+        self.line(&format!("%v{} = zext i8 %v{} to i32", wide, value));
+        self.emit_putchar(wide);
+    }
+
+    /// Read a byte into `cell`, leaving it unchanged at end of input
+    fn emit_input(&mut self, cell: &str) {
+        let (read, is_eof, byte, id) = (self.fresh(), self.fresh(), self.fresh(), self.fresh());
+        self.line("call i32 @fflush(ptr null)");
+        self.line(&format!("%v{} = call i32 @getchar()", read));
+        self.line(&format!("%v{} = icmp eq i32 %v{}, -1", is_eof, read));
+
+        let eof_value = match self.eof_behavior {
+            EofBehavior::Unchanged => None,
+            EofBehavior::Zero => Some(0),
+            EofBehavior::NegativeOne => Some(-1),
+        };
+        if let Some(eof_value) = eof_value {
+            let value = self.fresh();
+            self.line(&format!("%v{} = trunc i32 %v{} to i8", byte, read));
+            self.line(&format!(
+                "%v{} = select i1 %v{}, i8 {}, i8 %v{}",
+                value, is_eof, eof_value, byte
+            ));
+            self.line(&format!("store i8 %v{}, ptr {}", value, cell));
+            return;
+        }
+
+        self.line(&format!(
+            "br i1 %v{}, label %input_done{}, label %input_store{}",
+            is_eof, id, id
+        ));
+        self.label(&format!("input_store{}", id));
+        self.line(&format!("%v{} = trunc i32 %v{} to i8", byte, read));
+        self.line(&format!("store i8 %v{}, ptr {}", byte, cell));
+        self.line(&format!("br label %input_done{}", id));
+        self.label(&format!("input_done{}", id));
+    }
+
+    /// Move the data pointer by `delta` cells
+    fn emit_move(&mut self, delta: isize) {
+        if let Some((_, offset)) = &mut self.snapshot {
+            *offset += delta;
+            return;
         }
-        Err(e) => {
-            // Only clean up if we created the file
-            if !lamina_file_exists {
-                let _ = std::fs::remove_file(&lamina_file);
+        let (old, new) = (self.fresh(), self.fresh());
+        self.line(&format!("%v{} = load i64, ptr %ptr", old));
+        self.line(&format!("%v{} = add i64 %v{}, {}", new, old, delta));
+        self.line(&format!("store i64 %v{}, ptr %ptr", new));
+    }
+
+    /// Write the i32 value `wide`, applying the newline translation
+    fn emit_putchar(&mut self, wide: usize) {
+        let special = match self.output_newline {
+            OutputNewline::AsIs => None,
+            OutputNewline::CrLf => Some(10),
+            OutputNewline::Lf => Some(13),
+        };
+
+        let Some(special) = special else {
+            self.line(&format!("call i32 @putchar(i32 %v{})", wide));
+            return;
+        };
+
+        let (is_special, id) = (self.fresh(), self.fresh());
+        self.line(&format!(
+            "%v{} = icmp eq i32 %v{}, {}",
+            is_special, wide, special
+        ));
+        self.line(&format!(
+            "br i1 %v{}, label %nl_special{}, label %nl_write{}",
+            is_special, id, id
+        ));
+        self.label(&format!("nl_special{}", id));
+        if self.output_newline == OutputNewline::CrLf {
+            self.line("call i32 @putchar(i32 13)");
+            self.line(&format!("br label %nl_write{}", id));
+        } else {
+            self.line(&format!("br label %nl_done{}", id));
+        }
+        self.label(&format!("nl_write{}", id));
+        self.line(&format!("call i32 @putchar(i32 %v{})", wide));
+        self.line(&format!("br label %nl_done{}", id));
+        self.label(&format!("nl_done{}", id));
+    }
+
+    /// Emit a loop as a guarded do-while, so `[` is checked once on entry
+    /// and `]` after every iteration, just like the interpreter
+    ///
+    /// A balanced body takes a snapshot of the pointer on entry; loops nested
+    /// inside it are balanced too and keep using the same snapshot.
+    fn emit_loop(&mut self, body: &[AstNode]) {
+        let id = self.fresh();
+        self.emit_trace('[');
+        self.emit_branch_on_cell(&format!("loop_body{}", id), &format!("loop_end{}", id));
+        self.label(&format!("loop_body{}", id));
+
+        let takes_snapshot =
+            self.hoist_pointer && self.snapshot.is_none() && net_pointer_movement(body) == Some(0);
+        if takes_snapshot {
+            let base = self.cell_ptr();
+            self.snapshot = Some((base, 0));
+        }
+
+        self.emit_nodes(body);
+        self.emit_trace(']');
+        self.emit_branch_on_cell(&format!("loop_body{}", id), &format!("loop_end{}", id));
+
+        if takes_snapshot {
+            self.snapshot = None;
+        }
+        self.label(&format!("loop_end{}", id));
+    }
+
+    /// Branch to `nonzero` if the current cell is nonzero, else to `zero`
+    fn emit_branch_on_cell(&mut self, nonzero: &str, zero: &str) {
+        let cell = self.cell_ptr();
+        let (value, is_nonzero) = (self.fresh(), self.fresh());
+        self.line(&format!("%v{} = load i8, ptr {}", value, cell));
+        self.line(&format!("%v{} = icmp ne i8 %v{}, 0", is_nonzero, value));
+        self.line(&format!(
+            "br i1 %v{}, label %{}, label %{}",
+            is_nonzero, nonzero, zero
+        ));
+    }
+}
+
+/// Convert Brainfuck AST to portable C source
+///
+/// The output is a self-contained C file with the tape in a static array,
+/// `getchar`/`putchar` for I/O, and a `while` loop for every Brainfuck loop,
+/// so programs can be built with any C compiler where Lamina isn't
+/// available. It honors the tape size, the starting cell, cell wrapping,
+/// end-of-input behavior, newline translation, tracing and
+/// `config.exit_from_cell`.
+///
+/// Like the LLVM backend, the generated code flushes stdout before every
+/// read and before returning from `main`.
+pub fn brainfuck_to_c(ast: &[AstNode], config: &BrainfuckConfig) -> Result<String, CompileError> {
+    c_source(ast, config).map_err(CompileError::IrGeneration)
+}
+
+fn c_source(ast: &[AstNode], config: &BrainfuckConfig) -> Result<String, String> {
+    config.validate().map_err(|e| e.to_string())?;
+    if config.tape_model == TapeModel::Growable {
+        // The tape is a static array
+        return Err("A growable tape is not supported by the C backend".to_string());
+    }
+    if config.annotate_ir {
+        return Err("IR annotations are not supported by the C backend".to_string());
+    }
+    if config.runtime_bounds_check.is_some() {
+        return Err("Runtime bounds checks are not supported by the C backend".to_string());
+    }
+    let ast = config.optimize_program(&config.wrap_program(ast));
+    check_static_bounds(&ast, config)?;
+
+    let mut emitter = CEmitter {
+        body: String::new(),
+        depth: 1,
+        cell_wrap: config.cell_wrap,
+        trace: config.trace,
+        uses_output: false,
+        uses_input: false,
+    };
+    emitter.emit_nodes(&ast);
+
+    let mut source = String::new();
+    source.push_str("/* Generated by bfina */\n");
+    source.push_str("#include <stdio.h>\n");
+    if config.cell_wrap == CellWrap::Error {
+        source.push_str("#include <stdlib.h>\n");
+    }
+    source.push('\n');
+    let _ = writeln!(
+        source,
+        "static unsigned char tape[{}];",
+        config.allocated_tape_bytes()
+    );
+
+    if emitter.uses_output {
+        source.push_str("\nstatic void put(unsigned char c) {\n");
+        match config.output_newline {
+            OutputNewline::AsIs => {}
+            OutputNewline::CrLf => source.push_str("    if (c == '\\n') putchar('\\r');\n"),
+            OutputNewline::Lf => source.push_str("    if (c == '\\r') return;\n"),
+        }
+        source.push_str("    putchar(c);\n");
+        source.push_str("}\n");
+    }
+
+    if emitter.uses_input {
+        source.push_str("\nstatic void get(unsigned char *cell) {\n");
+        source.push_str("    int c;\n");
+        source.push_str("    fflush(stdout);\n");
+        source.push_str("    c = getchar();\n");
+        match config.eof_behavior {
+            EofBehavior::Unchanged => source.push_str("    if (c != EOF) *cell = c;\n"),
+            EofBehavior::Zero => source.push_str("    *cell = c == EOF ? 0 : c;\n"),
+            EofBehavior::NegativeOne => source.push_str("    *cell = c == EOF ? 255 : c;\n"),
+        }
+        source.push_str("}\n");
+    }
+
+    if config.cell_wrap != CellWrap::Wrap {
+        source.push_str("\nstatic void add(unsigned char *cell, int delta) {\n");
+        source.push_str("    int value = *cell + delta;\n");
+        if config.cell_wrap == CellWrap::Error {
+            source.push_str("    if (value < 0 || value > 255) abort();\n");
+        } else {
+            source.push_str("    if (value < 0) value = 0;\n");
+            source.push_str("    if (value > 255) value = 255;\n");
+        }
+        source.push_str("    *cell = value;\n");
+        source.push_str("}\n");
+    }
+
+    source.push_str("\nint main(void) {\n");
+    let _ = writeln!(source, "    size_t p = {};", config.start_cell());
+    source.push_str(&emitter.body);
+    source.push_str("    fflush(stdout);\n");
+    if config.exit_from_cell {
+        source.push_str("    return tape[p];\n");
+    } else {
+        source.push_str("    return 0;\n");
+    }
+    source.push_str("}\n");
+
+    Ok(source)
+}
+
+/// Emits the body of the C `main` function
+struct CEmitter {
+    body: String,
+    /// Indentation level of the next line
+    depth: usize,
+    cell_wrap: CellWrap,
+    trace: bool,
+    /// Whether the output helper `put` is called
+    uses_output: bool,
+    /// Whether the input helper `get` is called
+    uses_input: bool,
+}
+
+impl CEmitter {
+    fn line(&mut self, line: &str) {
+        for _ in 0..self.depth {
+            self.body.push_str("    ");
+        }
+        self.body.push_str(line);
+        self.body.push('\n');
+    }
+
+    /// The cell `offset` cells from the current one, as an lvalue
+    fn cell(offset: i32) -> String {
+        match offset {
+            0 => "tape[p]".to_string(),
+            offset if offset > 0 => format!("tape[p + {}]", offset),
+            offset => format!("tape[p - {}]", offset.unsigned_abs()),
+        }
+    }
+
+    fn emit_nodes(&mut self, ast: &[AstNode]) {
+        for node in ast {
+            match node {
+                AstNode::Command(cmd) => {
+                    self.emit_command(*cmd);
+                    self.emit_trace(cmd.as_char());
+                }
+                AstNode::Loop(body) => self.emit_loop(body),
+                // Traces log every step of the original commands
+                AstNode::SetZero
+                | AstNode::Add(_)
+                | AstNode::Move(_)
+                | AstNode::OutputAtOffset(_)
+                | AstNode::InputAtOffset(_)
+                | AstNode::CopyAndClear(_)
+                | AstNode::MultiplyLoop(_)
+                    if self.trace =>
+                {
+                    self.emit_nodes(&node.expand());
+                }
+                AstNode::SetZero => self.line("tape[p] = 0;"),
+                AstNode::Add(delta) => self.emit_add(0, *delta),
+                AstNode::Move(delta) => self.emit_move(*delta),
+                AstNode::OutputAtOffset(offset) => self.emit_output(*offset),
+                AstNode::InputAtOffset(offset) => self.emit_input(*offset),
+                AstNode::CopyAndClear(offset) => {
+                    self.line(&format!("{} = tape[p];", Self::cell(*offset)));
+                    self.line("tape[p] = 0;");
+                }
+                AstNode::MultiplyLoop(targets) => {
+                    // Like the loop, touch the targets only if the cell is nonzero
+                    self.line("if (tape[p]) {");
+                    self.depth += 1;
+                    for target in targets {
+                        let factor = target.factor.rem_euclid(256);
+                        self.line(&format!(
+                            "{} += tape[p] * {};",
+                            Self::cell(target.offset),
+                            factor
+                        ));
+                    }
+                    self.line("tape[p] = 0;");
+                    self.depth -= 1;
+                    self.line("}");
+                }
             }
-            Err(e)
         }
     }
+
+    fn emit_command(&mut self, cmd: Command) {
+        match cmd {
+            Command::Right => self.emit_move(1),
+            Command::Left => self.emit_move(-1),
+            Command::Increment => self.emit_add(0, 1),
+            Command::Decrement => self.emit_add(0, -1),
+            Command::Output => self.emit_output(0),
+            Command::Input => self.emit_input(0),
+        }
+    }
+
+    /// Add `delta` to the cell at `offset`, handling overflow as `cell_wrap`
+    /// says
+    fn emit_add(&mut self, offset: i32, delta: i32) {
+        let cell = Self::cell(offset);
+        if self.cell_wrap != CellWrap::Wrap {
+            self.line(&format!("add(&{}, {});", cell, delta));
+            return;
+        }
+
+        // Unsigned char arithmetic wraps by itself
+        let delta = delta.rem_euclid(256) as u8 as i8;
+        match delta {
+            0 => {}
+            delta if delta > 0 => self.line(&format!("{} += {};", cell, delta)),
+            delta => self.line(&format!("{} -= {};", cell, delta.unsigned_abs())),
+        }
+    }
+
+    fn emit_move(&mut self, delta: i32) {
+        match delta {
+            0 => {}
+            delta if delta > 0 => self.line(&format!("p += {};", delta)),
+            delta => self.line(&format!("p -= {};", delta.unsigned_abs())),
+        }
+    }
+
+    fn emit_output(&mut self, offset: i32) {
+        self.uses_output = true;
+        self.line(&format!("put({});", Self::cell(offset)));
+    }
+
+    fn emit_input(&mut self, offset: i32) {
+        self.uses_input = true;
+        self.line(&format!("get(&{});", Self::cell(offset)));
+    }
+
+    /// Log the command and the current pointer and cell to stderr
+    fn emit_trace(&mut self, command: char) {
+        if self.trace {
+            self.line(&format!(
+                "fprintf(stderr, \"%c %lu %d\\n\", '{}', (unsigned long)p, tape[p]);",
+                command
+            ));
+        }
+    }
+
+    /// Emit a loop as a `while`, or when tracing as a guarded do-while, so
+    /// `[` is logged once on entry and `]` after every iteration, just like
+    /// the interpreter
+    fn emit_loop(&mut self, body: &[AstNode]) {
+        if !self.trace {
+            self.line("while (tape[p]) {");
+            self.depth += 1;
+            self.emit_nodes(body);
+            self.depth -= 1;
+            self.line("}");
+            return;
+        }
+
+        self.emit_trace('[');
+        self.line("if (tape[p]) do {");
+        self.depth += 1;
+        self.emit_nodes(body);
+        self.emit_trace(']');
+        self.depth -= 1;
+        self.line("} while (tape[p]);");
+    }
+}
+
+/// Convert Brainfuck AST to binary executable
+pub fn brainfuck_to_binary(ast: &[AstNode], output_path: &str) -> Result<String, CompileError> {
+    brainfuck_to_binary_with_config(ast, output_path, BrainfuckConfig::default())
 }
 
 /// Convert Brainfuck AST to binary executable with custom configuration
+///
+/// This is [`compile_binary`] with its result described in a message for
+/// the user. With `config.keep_intermediates` set, the message lists the
+/// paths of the kept intermediate files.
 pub fn brainfuck_to_binary_with_config(
     ast: &[AstNode],
     output_path: &str,
     config: BrainfuckConfig,
-) -> Result<String, String> {
+) -> Result<String, CompileError> {
+    let output = compile_binary(ast, output_path, config)?;
+    let mut message = format!(
+        "Binary executable created: {} (linked with {})",
+        output.binary_path.display(),
+        output.linker
+    );
+    if let (Some(ir_path), Some(asm_path)) = (&output.ir_path, &output.asm_path) {
+        let _ = write!(
+            message,
+            "\nIntermediate files kept: {}, {}",
+            ir_path.display(),
+            asm_path.display()
+        );
+    }
+    Ok(message)
+}
+
+/// Compile Brainfuck AST to a binary executable at `output_path`
+///
+/// Intermediate files are written to the configured build directory; see
+/// [`BrainfuckConfig::resolved_build_dir`]. They are removed afterwards
+/// unless `config.keep_intermediates` is set, in which case the returned
+/// [`CompileOutput`] has their paths.
+pub fn compile_binary(
+    ast: &[AstNode],
+    output_path: &str,
+    config: BrainfuckConfig,
+) -> Result<CompileOutput, CompileError> {
+    let options = config.lamina.clone();
+    let keep_intermediates = config.keep_intermediates;
+    let build_dir = config.resolved_build_dir();
+    let link_mode = config.link_mode;
+    let toolchain = config.toolchain.clone();
     let builder = BrainfuckIRBuilder::with_config(config);
     let module = builder.build_ir(ast)?;
 
     // Convert module to IR string
     let ir_source = module.to_string();
 
-    // Check if a .lamina file already exists (created by main.rs)
-    let lamina_file = format!("{}.lamina", output_path);
-    let lamina_file_exists = std::path::Path::new(&lamina_file).exists();
+    let stem = intermediate_stem(&build_dir, output_path)?;
+    let lamina_file = intermediate_path(&stem, "lamina");
+    std::fs::write(&lamina_file, &ir_source)?;
+
+    // Use the normal Lamina library to compile
+    let result = write_output_atomically(output_path, |temp_output| {
+        compile_with_lamina_library(
+            &ir_source,
+            temp_output,
+            &stem,
+            link_mode,
+            &toolchain,
+            &options,
+            keep_intermediates,
+        )
+    });
 
-    if !lamina_file_exists {
-        // Write IR to .lamina file only if it doesn't exist
-        std::fs::write(&lamina_file, &ir_source)
-            .map_err(|e| format!("Failed to write Lamina IR file: {}", e))?;
+    if !keep_intermediates {
+        let _ = std::fs::remove_file(&lamina_file);
     }
+    let (linker, asm_file) = result?;
+    let kept = |path: PathBuf| keep_intermediates.then_some(path);
+    Ok(CompileOutput {
+        binary_path: PathBuf::from(output_path),
+        ir_path: kept(lamina_file),
+        asm_path: kept(asm_file),
+        linker,
+    })
+}
 
-    // Use the normal Lamina library to compile
-    match compile_with_lamina_library(&ir_source, output_path) {
-        Ok(_) => {
-            // Only clean up if we created the file
-            if !lamina_file_exists {
-                let _ = std::fs::remove_file(&lamina_file);
+/// Counter that keeps the temporary files of concurrent builds apart
+static NEXT_BUILD: AtomicUsize = AtomicUsize::new(0);
+
+/// Path, without extension, of the intermediate files for a build of
+/// `output_path`, creating the build directory if needed
+///
+/// The stem carries the process id and a per-process counter, so builds
+/// running at the same time never share intermediate files, whether or not
+/// they are kept.
+fn intermediate_stem(build_dir: &Path, output_path: &str) -> std::io::Result<PathBuf> {
+    create_build_dir(build_dir)?;
+    let name = Path::new(output_path)
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let build = NEXT_BUILD.fetch_add(1, Ordering::Relaxed);
+    Ok(build_dir.join(format!("{}.{}-{}", name, std::process::id(), build)))
+}
+
+/// Create `build_dir`, readable only by its owner where that can be set
+///
+/// The default build directory lives in the shared temp directory, so one
+/// that already exists there must be a real directory no one else can
+/// write to; a directory chosen by the user is taken as it is.
+pub(crate) fn create_build_dir(build_dir: &Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(build_dir)?;
+        if build_dir == default_build_dir() {
+            let metadata = std::fs::symlink_metadata(build_dir)?;
+            if !metadata.is_dir() || metadata.permissions().mode() & 0o077 != 0 {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::PermissionDenied,
+                    format!(
+                        "build directory {} is accessible to other users",
+                        build_dir.display()
+                    ),
+                ));
             }
-            Ok(format!("Binary executable created: {}", output_path))
         }
-        Err(e) => {
-            // Only clean up if we created the file
-            if !lamina_file_exists {
-                let _ = std::fs::remove_file(&lamina_file);
+        Ok(())
+    }
+    #[cfg(not(unix))]
+    std::fs::create_dir_all(build_dir)
+}
+
+/// Produce `output_path` by having `write` create a temporary file next to
+/// it, which is renamed into place only once `write` succeeds
+///
+/// Builds of the same output running at the same time then each replace it
+/// with a complete file, and a failed build leaves any previous output
+/// alone.
+fn write_output_atomically<T>(
+    output_path: &str,
+    write: impl FnOnce(&str) -> Result<T, CompileError>,
+) -> Result<T, CompileError> {
+    let build = NEXT_BUILD.fetch_add(1, Ordering::Relaxed);
+    let temp_output = format!("{}.{}-{}.tmp", output_path, std::process::id(), build);
+    let result = write(&temp_output).and_then(|value| {
+        std::fs::rename(&temp_output, output_path)?;
+        Ok(value)
+    });
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_output);
+    }
+    result
+}
+
+/// Path of the intermediate file with `extension` for the build at `stem`
+fn intermediate_path(stem: &Path, extension: &str) -> PathBuf {
+    let mut path = stem.as_os_str().to_owned();
+    path.push(".");
+    path.push(extension);
+    PathBuf::from(path)
+}
+
+/// Compile Brainfuck AST to a binary executable, timing every stage
+///
+/// This runs the same pipeline as [`brainfuck_to_binary_with_config`], but
+/// with the AST optimized at `config.opt_level` first and assembling and
+/// linking done as separate toolchain invocations so each can be measured.
+/// Intermediate files are written to the build directory and removed
+/// afterwards, unless `config.keep_intermediates` is set.
+pub fn compile_instrumented(
+    ast: &[AstNode],
+    config: BrainfuckConfig,
+    output_path: &str,
+) -> Result<CompileTimings, CompileError> {
+    let mut timings = CompileTimings::default();
+
+    // The optimizer assumes cells start at zero, which the prelude can
+    // change, so the program is wrapped before it is optimized
+    let start = Instant::now();
+    let ast = config.optimize_program(&config.wrap_program(ast));
+    timings.optimize = start.elapsed();
+
+    let start = Instant::now();
+    let options = config.lamina.clone();
+    let keep_intermediates = config.keep_intermediates;
+    let build_dir = config.resolved_build_dir();
+    let link_mode = config.link_mode;
+    let toolchain = config.toolchain.clone();
+    // The prelude and epilogue are already part of `ast`, which is already
+    // optimized
+    let builder = BrainfuckIRBuilder::with_config(BrainfuckConfig {
+        prelude: Vec::new(),
+        epilogue: Vec::new(),
+        opt_level: 0,
+        ..config
+    });
+    let ir_source = builder.build_ir(&ast)?.to_string();
+    timings.ir_build = start.elapsed();
+
+    let start = Instant::now();
+    let asm_buffer =
+        lamina_to_assembly(&ir_source, &options).map_err(CompileError::LaminaCompile)?;
+    timings.ir_to_asm = start.elapsed();
+    check_assembly(&asm_buffer)?;
+
+    let stem = intermediate_stem(&build_dir, output_path)?;
+    let asm_filename = intermediate_path(&stem, "s");
+    let obj_filename = intermediate_path(&stem, "o");
+    std::fs::write(&asm_filename, &asm_buffer)?;
+
+    let result = write_output_atomically(output_path, |temp_output| {
+        assemble_and_link(
+            &asm_filename,
+            &obj_filename,
+            temp_output,
+            link_mode,
+            &toolchain,
+            &mut timings,
+        )
+    });
+    if !keep_intermediates {
+        let _ = std::fs::remove_file(&asm_filename);
+        let _ = std::fs::remove_file(&obj_filename);
+    }
+    result.map(|_| timings)
+}
+
+/// Assemble and link as `link_mode` says, recording the time of both steps
+fn assemble_and_link(
+    asm_filename: &Path,
+    obj_filename: &Path,
+    output_path: &str,
+    link_mode: LinkMode,
+    toolchain: &CompilerToolchain,
+    timings: &mut CompileTimings,
+) -> Result<(), CompileError> {
+    use std::process::Command;
+
+    if link_mode == LinkMode::RawLd {
+        return assemble_and_link_raw(asm_filename, obj_filename, output_path, timings);
+    }
+
+    let compiler = find_compiler(toolchain).map_err(CompileError::Link)?;
+    let start = Instant::now();
+    let output = Command::new(&compiler)
+        .arg("-c")
+        .arg(asm_filename)
+        .arg("-o")
+        .arg(obj_filename)
+        .output()?;
+    timings.assemble = start.elapsed();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CompileError::Assemble(stderr.into_owned()));
+    }
+
+    let start = Instant::now();
+    let output = Command::new(&compiler)
+        .arg(obj_filename)
+        .arg("-o")
+        .arg(output_path)
+        .args(&toolchain.link_flags)
+        .output()?;
+    timings.link = start.elapsed();
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(CompileError::Link(stderr.into_owned()));
+    }
+
+    Ok(())
+}
+
+/// Entry point of [`LinkMode::RawLd`] executables
+///
+/// Aligns the stack as the ABI requires at a call, calls `main`, and exits
+/// with the status it returns. A void `main` (without
+/// `config.emit_int_main`) leaves that status undefined.
+const RAW_START: &str = "\
+    .text
+    .globl _start
+_start:
+    xorl %ebp, %ebp
+    andq $-16, %rsp
+    call main
+    movl %eax, %edi
+    movl $60, %eax
+    syscall
+";
+
+/// Assemble with `as` and link a static executable with `ld`, recording the
+/// time of both steps
+///
+/// The program is linked against [`RAW_START`] only, without the C library.
+fn assemble_and_link_raw(
+    asm_filename: &Path,
+    obj_filename: &Path,
+    output_path: &str,
+    timings: &mut CompileTimings,
+) -> Result<(), CompileError> {
+    use std::process::Command;
+
+    if !cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+        return Err(CompileError::Link(
+            "linking with ld is only supported on x86-64 Linux".to_string(),
+        ));
+    }
+
+    let start_asm = obj_filename.with_extension("start.s");
+    let start_obj = obj_filename.with_extension("start.o");
+    std::fs::write(&start_asm, RAW_START)?;
+
+    let assemble = |source: &Path, object: &Path| -> Result<(), CompileError> {
+        let output = Command::new("as")
+            .arg(source)
+            .arg("-o")
+            .arg(object)
+            .output()?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(CompileError::Assemble(stderr.into_owned()))
+        }
+    };
+
+    let start = Instant::now();
+    let result = assemble(asm_filename, obj_filename)
+        .and_then(|_| assemble(&start_asm, &start_obj))
+        .and_then(|_| {
+            timings.assemble = start.elapsed();
+
+            let start = Instant::now();
+            let output = Command::new("ld")
+                .arg("-static")
+                .arg(&start_obj)
+                .arg(obj_filename)
+                .arg("-o")
+                .arg(output_path)
+                .output()?;
+            timings.link = start.elapsed();
+            if output.status.success() {
+                Ok(())
+            } else {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                Err(CompileError::Link(stderr.into_owned()))
             }
-            Err(e)
+        });
+
+    let _ = std::fs::remove_file(&start_asm);
+    let _ = std::fs::remove_file(&start_obj);
+    result
+}
+
+/// Convert Brainfuck AST to a static archive exporting the program as a
+/// function named `symbol`
+///
+/// The program is compiled to an object file, its `main` is renamed to
+/// `symbol` with `objcopy`, and the object is packed into a `.a` with `ar`.
+/// From C the function is declared as `int symbol(void)` and returns what
+/// the binary would exit with, or as `void symbol(void)` when
+/// `config.emit_int_main` is off. It uses the process's stdin and stdout
+/// like the standalone binary would.
+pub fn brainfuck_to_staticlib(
+    ast: &[AstNode],
+    output_path: &str,
+    symbol: &str,
+    config: BrainfuckConfig,
+) -> Result<String, CompileError> {
+    let mut chars = symbol.chars();
+    let valid_symbol = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_symbol {
+        return Err(CompileError::InvalidSymbol(symbol.to_string()));
+    }
+
+    let options = config.lamina.clone();
+    let build_dir = config.resolved_build_dir();
+    let toolchain = config.toolchain.clone();
+    let builder = BrainfuckIRBuilder::with_config(config);
+    let ir_source = builder.build_ir(ast)?.to_string();
+
+    let asm_buffer =
+        lamina_to_assembly(&ir_source, &options).map_err(CompileError::LaminaCompile)?;
+    check_assembly(&asm_buffer)?;
+
+    let stem = intermediate_stem(&build_dir, output_path)?;
+    let asm_filename = intermediate_path(&stem, "s");
+    let obj_filename = intermediate_path(&stem, "o");
+    std::fs::write(&asm_filename, &asm_buffer)?;
+
+    let result = write_output_atomically(output_path, |temp_output| {
+        find_compiler(&toolchain)
+            .and_then(|compiler| {
+                archive_object(&asm_filename, &obj_filename, temp_output, symbol, &compiler)
+            })
+            .map_err(CompileError::Link)
+    });
+    let _ = std::fs::remove_file(&asm_filename);
+    let _ = std::fs::remove_file(&obj_filename);
+    result.map(|_| format!("Static library created: {}", output_path))
+}
+
+/// Assemble with `compiler`, rename `main` to `symbol`, and archive the
+/// object
+fn archive_object(
+    asm_filename: &Path,
+    obj_filename: &Path,
+    output_path: &str,
+    symbol: &str,
+    compiler: &Path,
+) -> Result<(), String> {
+    use std::process::Command;
+
+    let run = |command: &mut Command, tool: &str| {
+        let output = command
+            .output()
+            .map_err(|e| format!("Failed to execute {}: {}", tool, e))?;
+        if output.status.success() {
+            Ok(())
+        } else {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            Err(format!("{} failed: {}", tool, stderr))
         }
+    };
+
+    run(
+        Command::new(compiler)
+            .arg("-c")
+            .arg(asm_filename)
+            .arg("-o")
+            .arg(obj_filename),
+        &compiler.display().to_string(),
+    )?;
+    run(
+        Command::new("objcopy")
+            .arg(format!("--redefine-sym=main={}", symbol))
+            .arg(obj_filename),
+        "objcopy",
+    )?;
+
+    // `ar` adds to an existing archive, so start from scratch
+    let _ = std::fs::remove_file(output_path);
+    run(
+        Command::new("ar")
+            .arg("rcs")
+            .arg(output_path)
+            .arg(obj_filename),
+        "ar",
+    )
+}
+
+/// Compile Lamina IR to assembly, applying `options`
+///
+/// Every Lamina compile goes through here, so new options only need to be
+/// wired up in one place.
+fn lamina_to_assembly(ir_source: &str, options: &LaminaOptions) -> Result<Vec<u8>, String> {
+    // Destructuring makes adding an option a compile error until it is
+    // forwarded below
+    let LaminaOptions { target } = options;
+
+    let mut asm_buffer = Vec::new();
+    lamina::compile_lamina_ir_to_target_assembly(ir_source, &mut asm_buffer, target)
+        .map_err(|e| e.to_string())?;
+    append_runtime(ir_source, target, &mut asm_buffer);
+    Ok(asm_buffer)
+}
+
+/// Append hand-written definitions of the runtime functions the module
+/// calls, which Lamina can't express in IR
+///
+/// [`TAPE_BASE_FUNCTION`] returns the address of the tape global, since
+/// Lamina reads a global's value wherever the IR names it, and
+/// [`READ_BYTE_FUNCTION`] reports end of input. [`TRAP_FUNCTION`] kills the
+/// program with `ud2` or `brk`, since integer division by zero doesn't
+/// fault on aarch64. Lamina prefixes
+/// called functions with `func_` and globals with `global_`. There is a
+/// version for each architecture Lamina targets, both using Linux system
+/// calls.
+fn append_runtime(ir_source: &str, target: &str, asm: &mut Vec<u8>) {
+    let calls = |function: &str| ir_source.contains(&format!("@{}(", function));
+    let aarch64 = target.starts_with("aarch64");
+    let mut runtime = String::new();
+    if calls(TAPE_BASE_FUNCTION) && aarch64 {
+        let _ = write!(
+            runtime,
+            "\
+    .text
+func_{0}:
+    adrp x0, global_{1}
+    add x0, x0, :lo12:global_{1}
+    ret
+",
+            TAPE_BASE_FUNCTION, TAPE_GLOBAL
+        );
+    } else if calls(TAPE_BASE_FUNCTION) {
+        let _ = write!(
+            runtime,
+            "\
+    .text
+func_{}:
+    leaq global_{}(%rip), %rax
+    ret
+",
+            TAPE_BASE_FUNCTION, TAPE_GLOBAL
+        );
+    }
+    if calls(READ_BYTE_FUNCTION) && aarch64 {
+        // read(0, buf, 1) into a slot on the stack; the kernel only writes
+        // x0, so only the registers the call is set up in are saved
+        let _ = write!(
+            runtime,
+            "\
+    .text
+func_{}:
+    stp x1, x2, [sp, #-32]!
+    str x8, [sp, #16]
+    mov x0, #0
+    add x1, sp, #24
+    mov x2, #1
+    mov x8, #63
+    svc #0
+    cmp x0, #1
+    b.ne 1f
+    ldrb w0, [sp, #24]
+    b 2f
+1:
+    mov x0, #-1
+2:
+    ldr x8, [sp, #16]
+    ldp x1, x2, [sp], #32
+    ret
+",
+            READ_BYTE_FUNCTION
+        );
+    } else if calls(READ_BYTE_FUNCTION) {
+        // read(0, buf, 1) into a slot on the stack, keeping the registers
+        // Lamina's code may hold live values in
+        let _ = write!(
+            runtime,
+            "\
+    .text
+func_{}:
+    pushq %rdi
+    pushq %rsi
+    pushq %rdx
+    pushq %rcx
+    pushq %r11
+    subq $8, %rsp
+    movq $0, %rax
+    movq $0, %rdi
+    movq %rsp, %rsi
+    movq $1, %rdx
+    syscall
+    cmpq $1, %rax
+    jne 1f
+    movzbq (%rsp), %rax
+    jmp 2f
+1:
+    movq $-1, %rax
+2:
+    addq $8, %rsp
+    popq %r11
+    popq %rcx
+    popq %rdx
+    popq %rsi
+    popq %rdi
+    ret
+",
+            READ_BYTE_FUNCTION
+        );
+    }
+    if calls(TRAP_FUNCTION) {
+        let trap = if aarch64 { "brk #0" } else { "ud2" };
+        let _ = write!(
+            runtime,
+            "\
+    .text
+func_{}:
+    {}
+",
+            TRAP_FUNCTION, trap
+        );
     }
+    asm.extend_from_slice(runtime.as_bytes());
 }
 
-/// Compile Lamina IR to executable using the Lamina library
-fn compile_with_lamina_library(ir_source: &str, output_name: &str) -> Result<(), String> {
-    use std::fs::File;
-    use std::io::Write;
+/// Check that assembly from Lamina defines `main` before it is assembled
+///
+/// Without this, empty or truncated output only surfaces as a confusing
+/// assembler or linker error.
+fn check_assembly(asm: &[u8]) -> Result<(), CompileError> {
+    let has_main = String::from_utf8_lossy(asm)
+        .lines()
+        .any(|line| matches!(line.trim(), "main:" | "_main:"));
+    if has_main {
+        Ok(())
+    } else {
+        Err(CompileError::EmptyAssembly)
+    }
+}
 
+/// The C compiler `toolchain` names, or else the first of
+/// [`DEFAULT_COMPILERS`] that runs
+pub(crate) fn find_compiler(toolchain: &CompilerToolchain) -> Result<PathBuf, String> {
+    use std::process::{Command, Stdio};
+
+    if let Some(compiler) = &toolchain.compiler {
+        return Ok(compiler.clone());
+    }
+
+    DEFAULT_COMPILERS
+        .iter()
+        .map(PathBuf::from)
+        .find(|compiler| {
+            Command::new(compiler)
+                .arg("--version")
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .is_ok_and(|status| status.success())
+        })
+        .ok_or_else(|| {
+            format!(
+                "No C compiler found (tried {})",
+                DEFAULT_COMPILERS.join(", ")
+            )
+        })
+}
+
+/// Compile Lamina IR to executable using the Lamina library
+///
+/// The assembly is written next to the intermediate `stem`, and removed
+/// after a successful build unless `keep_intermediates` is set; only the
+/// executable is written at `output_name`. Returns the command that linked
+/// the executable and the path of the assembly.
+fn compile_with_lamina_library(
+    ir_source: &str,
+    output_name: &str,
+    stem: &Path,
+    link_mode: LinkMode,
+    toolchain: &CompilerToolchain,
+    options: &LaminaOptions,
+    keep_intermediates: bool,
+) -> Result<(String, PathBuf), CompileError> {
     // Compile IR to assembly using Lamina library
-    let mut asm_buffer = Vec::new();
-    match lamina::compile_lamina_ir_to_assembly(ir_source, &mut asm_buffer) {
-        Ok(_) => {
+    match lamina_to_assembly(ir_source, options) {
+        Ok(asm_buffer) => {
+            check_assembly(&asm_buffer)?;
+
             // Write assembly to file
-            let asm_filename = format!("{}.s", output_name);
-            let mut asm_file = File::create(&asm_filename)
-                .map_err(|e| format!("Failed to create assembly file: {}", e))?;
-            asm_file
-                .write_all(&asm_buffer)
-                .map_err(|e| format!("Failed to write assembly: {}", e))?;
+            let asm_filename = intermediate_path(stem, "s");
+            std::fs::write(&asm_filename, &asm_buffer)?;
+
+            if link_mode == LinkMode::RawLd {
+                let obj_filename = intermediate_path(stem, "o");
+                let result = assemble_and_link_raw(
+                    &asm_filename,
+                    &obj_filename,
+                    output_name,
+                    &mut CompileTimings::default(),
+                );
+                if !keep_intermediates {
+                    let _ = std::fs::remove_file(&asm_filename);
+                    let _ = std::fs::remove_file(&obj_filename);
+                }
+                return result.map(|_| ("ld".to_string(), asm_filename));
+            }
 
             // Use system assembler and linker to create executable
             use std::process::Command;
-            let output = Command::new("gcc")
+            let compiler = find_compiler(toolchain).map_err(CompileError::Link)?;
+            let compiler_name = compiler.display().to_string();
+            let output = Command::new(&compiler)
                 .arg(&asm_filename)
                 .arg("-o")
                 .arg(output_name)
-                .arg("-no-pie")
-                .output()
-                .map_err(|e| format!("Failed to execute gcc: {}", e))?;
+                .args(&toolchain.link_flags)
+                .output()?;
 
             if output.status.success() {
                 // Clean up assembly file
-                let _ = std::fs::remove_file(&asm_filename);
-                Ok(())
+                if !keep_intermediates {
+                    let _ = std::fs::remove_file(&asm_filename);
+                }
+                Ok((compiler_name, asm_filename))
             } else {
                 let stderr = String::from_utf8_lossy(&output.stderr);
-                Err(format!("GCC compilation failed: {}", stderr))
+                Err(CompileError::Link(format!(
+                    "{} failed: {}",
+                    compiler_name, stderr
+                )))
+            }
+        }
+        Err(e) => Err(CompileError::LaminaCompile(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lamina_builder::SMALL_TAPE_SIZE;
+    use crate::lamina_builder::optimize::optimize_ast;
+    use crate::lexer::parse_brainfuck;
+    use crate::tape::PointerMode;
+    use std::process::Command as Process;
+
+    /// Returns true if `llvm-as` accepts the given LLVM IR file
+    ///
+    /// LLVM 14 and 15 only accept opaque `ptr` types behind a flag, which
+    /// newer releases no longer recognize, so both invocations are tried.
+    fn llvm_as_accepts(path: &std::path::Path) -> bool {
+        let run = |extra: &[&str]| {
+            Process::new("llvm-as")
+                .args(extra)
+                .arg(path)
+                .arg("-o")
+                .arg("/dev/null")
+                .status()
+                .is_ok_and(|status| status.success())
+        };
+        run(&[]) || run(&["-opaque-pointers"])
+    }
+
+    #[test]
+    fn test_llvm_ir_is_accepted_by_llvm_as() {
+        if Process::new("llvm-as").arg("--version").output().is_err() {
+            eprintln!("llvm-as not available, skipping");
+            return;
+        }
+
+        let source = include_str!("../../testcases/hello_world.bf");
+        let ast = parse_brainfuck(&format!("{},[.,]", source)).unwrap();
+
+        for output_newline in [OutputNewline::AsIs, OutputNewline::CrLf, OutputNewline::Lf] {
+            let config = BrainfuckConfig {
+                output_newline,
+                ..BrainfuckConfig::default()
+            };
+            let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+
+            let path = std::env::temp_dir().join(format!(
+                "bfina-llvm-{:?}-{}.ll",
+                output_newline,
+                std::process::id()
+            ));
+            std::fs::write(&path, ir).unwrap();
+            let accepted = llvm_as_accepts(&path);
+            let _ = std::fs::remove_file(&path);
+            assert!(accepted, "llvm-as rejected IR for {:?}", output_newline);
+        }
+    }
+
+    /// Run LLVM IR with `lli`, returning `None` if `lli` isn't installed
+    fn run_with_lli(ir: &str, name: &str) -> Option<std::process::Output> {
+        run_with_lli_input(ir, name, b"")
+    }
+
+    /// The message of an IR generation error, panicking on anything else
+    fn ir_error<T: std::fmt::Debug>(result: Result<T, CompileError>) -> String {
+        match result {
+            Err(CompileError::IrGeneration(msg)) => msg,
+            other => panic!("expected an IR generation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors_convert_with_question_mark() {
+        let compile = |source: &str| -> Result<String, CompileError> {
+            let ast = crate::lexer::parse_brainfuck(source)?;
+            brainfuck_to_llvm_ir(&ast, &BrainfuckConfig::default())
+        };
+        assert!(compile("+.").is_ok());
+        let err = compile("+]").unwrap_err();
+        assert!(matches!(err, CompileError::Parse(_)));
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn test_compile_binary_reports_ir_errors() {
+        let ast = parse_brainfuck("+.").unwrap();
+        let config = BrainfuckConfig {
+            trace: true,
+            ..BrainfuckConfig::default()
+        };
+        assert!(matches!(
+            compile_binary(&ast, "unused", config),
+            Err(CompileError::IrGeneration(_))
+        ));
+    }
+
+    #[test]
+    fn test_empty_assembly_is_rejected() {
+        for asm in ["", "  \n\n", ".text\n.globl main\n", "\tcall main\n"] {
+            assert!(
+                matches!(
+                    check_assembly(asm.as_bytes()),
+                    Err(CompileError::EmptyAssembly)
+                ),
+                "{:?}",
+                asm
+            );
+        }
+
+        assert!(check_assembly(b".globl main\nmain:\n  ret\n").is_ok());
+        assert!(check_assembly(b".globl _main\n_main:\n  ret\n").is_ok());
+
+        let ast = parse_brainfuck("+.").unwrap();
+        let ir = brainfuck_to_lamina_ir(&ast).unwrap();
+        let asm = lamina_to_assembly(&ir, &LaminaOptions::default()).unwrap();
+        assert!(check_assembly(&asm).is_ok());
+    }
+
+    #[test]
+    fn test_lamina_target_selects_the_architecture() {
+        let ast = parse_brainfuck("+,>.").unwrap();
+        let ir = brainfuck_to_lamina_ir(&ast).unwrap();
+        let assemble = |ir: &str, target: &str| {
+            let options = LaminaOptions {
+                target: target.to_string(),
+            };
+            String::from_utf8(lamina_to_assembly(ir, &options).unwrap()).unwrap()
+        };
+
+        let x86_64 = assemble(&ir, "x86_64_linux");
+        let aarch64 = assemble(&ir, "aarch64_linux");
+        assert_ne!(x86_64, aarch64);
+        // Each gets the runtime written for its architecture
+        assert!(x86_64.contains("leaq global_bfina_tape(%rip)"));
+        assert!(x86_64.contains("syscall"));
+        assert!(!x86_64.contains("adrp"));
+        assert!(aarch64.contains("adrp x0, global_bfina_tape"));
+        assert!(aarch64.contains("svc #0"));
+        assert!(!aarch64.contains("%rip"));
+        assert!(!x86_64.contains("func_bfina_trap:"));
+
+        // Division by zero doesn't fault on aarch64, so traps need an
+        // instruction that does
+        let config = BrainfuckConfig {
+            runtime_bounds_check: Some(PointerMode::Trap),
+            ..BrainfuckConfig::default()
+        };
+        let ir = brainfuck_to_lamina_ir_with_config(&ast, config).unwrap();
+        let x86_64 = assemble(&ir, "x86_64_linux");
+        let aarch64 = assemble(&ir, "aarch64_linux");
+        assert!(x86_64.contains("func_bfina_trap:\n    ud2\n"));
+        assert!(aarch64.contains("func_bfina_trap:\n    brk #0\n"));
+        assert!(aarch64.contains("bl func_bfina_trap"));
+        assert!(!aarch64.contains("sdiv"));
+
+        let options = LaminaOptions {
+            target: "sparc".to_string(),
+        };
+        assert!(lamina_to_assembly(&ir, &options).is_err());
+    }
+
+    #[test]
+    fn test_lamina_options_are_forwarded() {
+        let ast = parse_brainfuck("+++[>++<-]>.").unwrap();
+        let config = BrainfuckConfig::default();
+        let ir = brainfuck_to_lamina_ir_with_config(&ast, config.clone()).unwrap();
+
+        let mut direct = Vec::new();
+        lamina::compile_lamina_ir_to_assembly(&ir, &mut direct).unwrap();
+        // The runtime the module calls follows Lamina's output
+        assert!(
+            lamina_to_assembly(&ir, &config.lamina)
+                .unwrap()
+                .starts_with(&direct)
+        );
+        assert!(
+            brainfuck_to_assembly_with_config(&ast, config)
+                .unwrap()
+                .as_bytes()
+                .starts_with(&direct)
+        );
+    }
+
+    #[test]
+    fn test_infinite_program_compiles() {
+        let ast = crate::lamina_builder::optimize::optimize_ast(&parse_brainfuck("+[]").unwrap());
+        assert!(crate::analysis::may_not_terminate(&ast));
+
+        // Compile-time evaluation gives up instead of hanging
+        let config = BrainfuckConfig {
+            precompute_output: true,
+            ..BrainfuckConfig::default()
+        };
+        let start = std::time::Instant::now();
+        let lamina_ir = brainfuck_to_lamina_ir_with_config(&ast, config.clone()).unwrap();
+        assert!(lamina_ir.contains("tape"));
+        let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+        assert!(start.elapsed() < std::time::Duration::from_secs(10));
+
+        // The loop branches back to itself forever at runtime
+        assert!(ir.contains("label %loop_body"));
+        if Process::new("llvm-as").arg("--version").output().is_ok() {
+            let path =
+                std::env::temp_dir().join(format!("bfina-infinite-{}.ll", std::process::id()));
+            std::fs::write(&path, &ir).unwrap();
+            assert!(llvm_as_accepts(&path));
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    /// Run LLVM IR with `lli`, feeding `input` to its stdin
+    fn run_with_lli_input(ir: &str, name: &str, input: &[u8]) -> Option<std::process::Output> {
+        if Process::new("lli").arg("--version").output().is_err() {
+            eprintln!("lli not available, skipping");
+            return None;
+        }
+
+        let path = std::env::temp_dir().join(format!("bfina-{}-{}.ll", name, std::process::id()));
+        std::fs::write(&path, ir).unwrap();
+        let run = |extra: &[&str]| {
+            let mut child = Process::new("lli")
+                .args(extra)
+                .arg(&path)
+                .stdin(std::process::Stdio::piped())
+                .stdout(std::process::Stdio::piped())
+                .stderr(std::process::Stdio::piped())
+                .spawn()?;
+            std::io::Write::write_all(&mut child.stdin.take().unwrap(), input)?;
+            child.wait_with_output()
+        };
+        let output = match run(&[]) {
+            Ok(output) if output.status.success() => output,
+            _ => run(&["-opaque-pointers"]).unwrap(),
+        };
+        let _ = std::fs::remove_file(&path);
+        Some(output)
+    }
+
+    #[test]
+    fn test_llvm_output_is_flushed() {
+        // A short output that never fills stdout's buffer
+        let ast = parse_brainfuck("++++++++[>++++++++<-]>+.+.").unwrap();
+        let ir = brainfuck_to_llvm_ir(&ast, &BrainfuckConfig::default()).unwrap();
+        assert!(ir.contains("call i32 @fflush(ptr null)\n  ret i32 0"));
+
+        if let Some(output) = run_with_lli(&ir, "flush") {
+            assert_eq!(output.stdout, b"AB");
+        }
+    }
+
+    #[test]
+    fn test_llvm_prelude_and_epilogue() {
+        let config = BrainfuckConfig {
+            // '0' + 1 = '1'
+            prelude: parse_brainfuck(&"+".repeat(49)).unwrap(),
+            epilogue: parse_brainfuck("[-]++++++++++.").unwrap(),
+            annotate_ir: true,
+            ..BrainfuckConfig::default()
+        };
+        let ast = parse_brainfuck(".+.").unwrap();
+        let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+        // Only the program's own nodes are annotated
+        assert_eq!(ir.matches("; cmd ").count(), 3);
+
+        if let Some(output) = run_with_lli(&ir, "prelude") {
+            assert_eq!(output.stdout, b"12\n");
+        }
+    }
+
+    #[test]
+    fn test_optimizer_keeps_loops_the_prelude_makes_live() {
+        // The leading loop only runs because the prelude sets the cell
+        let config = BrainfuckConfig {
+            prelude: parse_brainfuck("++").unwrap(),
+            opt_level: 3,
+            ..BrainfuckConfig::default()
+        };
+        let ast = parse_brainfuck("[.-]").unwrap();
+
+        let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+        assert!(ir.contains("call i32 @putchar"));
+        if let Some(output) = run_with_lli(&ir, "prelude-loop") {
+            assert_eq!(output.stdout, [2, 1]);
+        }
+
+        let c = brainfuck_to_c(&ast, &config).unwrap();
+        assert!(c.contains("while"));
+        if let Some(output) = run_c(&c, "prelude-loop", b"") {
+            assert_eq!(output.stdout, [2, 1]);
+        }
+    }
+
+    #[test]
+    fn test_llvm_eof_behavior() {
+        let ast = parse_brainfuck("+,.").unwrap();
+        for (eof_behavior, expected, name) in [
+            (EofBehavior::Unchanged, 1, "eof-unchanged"),
+            (EofBehavior::Zero, 0, "eof-zero"),
+            (EofBehavior::NegativeOne, 255, "eof-negative-one"),
+        ] {
+            let config = BrainfuckConfig {
+                eof_behavior,
+                ..BrainfuckConfig::default()
+            };
+            let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+            if let Some(output) = run_with_lli(&ir, name) {
+                assert_eq!(output.stdout, [expected], "{:?}", eof_behavior);
+            }
+        }
+    }
+
+    #[test]
+    fn test_llvm_multiply_loop() {
+        let ast = optimize_ast(&parse_brainfuck("+++++[->+++>-<<]>.>.").unwrap());
+        assert!(
+            ast.iter()
+                .any(|node| matches!(node, AstNode::MultiplyLoop(_)))
+        );
+
+        let ir = brainfuck_to_llvm_ir(&ast, &BrainfuckConfig::default()).unwrap();
+        if let Some(output) = run_with_lli(&ir, "multiply") {
+            assert_eq!(output.stdout, [15, 251]);
+        }
+    }
+
+    #[test]
+    fn test_multiply_loop_skips_targets_when_the_cell_is_zero() {
+        // The target is far off the tape, where a store would crash
+        let ast = [
+            AstNode::Command(Command::Input),
+            AstNode::MultiplyLoop(vec![crate::lexer::MultiplyAdd {
+                offset: -10_000_000,
+                factor: 1,
+            }]),
+            AstNode::Command(Command::Output),
+        ];
+        let config = BrainfuckConfig::default();
+
+        let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+        assert!(ir.contains("label %mul_body"));
+        if let Some(output) = run_with_lli_input(&ir, "multiply-zero", b"\0") {
+            assert!(output.status.success());
+            assert_eq!(output.stdout, [0]);
+        }
+
+        let c = brainfuck_to_c(&ast, &config).unwrap();
+        assert!(c.contains("if (tape[p]) {"));
+        if let Some(output) = run_c(&c, "multiply-zero", b"\0") {
+            assert!(output.status.success());
+            assert_eq!(output.stdout, [0]);
+        }
+    }
+
+    #[test]
+    fn test_llvm_cell_wrap_modes() {
+        // Increments a cell holding 255, then prints it
+        let ast = parse_brainfuck(&format!("{}+.", "+".repeat(255))).unwrap();
+        let run = |cell_wrap, name| {
+            let config = BrainfuckConfig {
+                cell_wrap,
+                ..BrainfuckConfig::default()
+            };
+            let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+            run_with_lli(&ir, name)
+        };
+
+        if let Some(output) = run(CellWrap::Wrap, "wrap") {
+            assert_eq!(output.stdout, [0]);
+        }
+        if let Some(output) = run(CellWrap::Saturate, "saturate") {
+            assert_eq!(output.stdout, [255]);
+        }
+        if let Some(output) = run(CellWrap::Error, "wrap-error") {
+            assert!(!output.status.success());
+            assert!(output.stdout.is_empty());
+        }
+
+        // Decrementing an empty cell stops at zero
+        let ast = parse_brainfuck("-.").unwrap();
+        let config = BrainfuckConfig {
+            cell_wrap: CellWrap::Saturate,
+            ..BrainfuckConfig::default()
+        };
+        let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+        if let Some(output) = run_with_lli(&ir, "saturate-zero") {
+            assert_eq!(output.stdout, [0]);
+        }
+    }
+
+    #[test]
+    fn test_llvm_large_tape_is_global() {
+        // Far more than the default 8 MiB stack
+        let ast = parse_brainfuck("+++.").unwrap();
+        let config = BrainfuckConfig {
+            tape_size: 64_000_000,
+            ..BrainfuckConfig::default()
+        };
+        let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+        assert!(ir.contains("@tape = internal global [64000000 x i8] zeroinitializer"));
+        assert!(!ir.contains("alloca ["));
+
+        if let Some(output) = run_with_lli(&ir, "large-tape") {
+            assert_eq!(output.stdout, [3]);
+            assert!(output.status.success());
+        }
+    }
+
+    #[test]
+    fn test_llvm_exit_from_cell() {
+        // Exits with 42, the cell the pointer ends on, after printing 'A'
+        let ast = parse_brainfuck("++++++++[>++++++++<-]>+.>++++++[>+++++++<-]>").unwrap();
+        let config = BrainfuckConfig {
+            exit_from_cell: true,
+            ..BrainfuckConfig::default()
+        };
+        let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+        assert!(!ir.contains("ret i32 0"));
+
+        if let Some(output) = run_with_lli(&ir, "exit-from-cell") {
+            assert_eq!(output.stdout, b"A");
+            assert_eq!(output.status.code(), Some(42));
+        }
+    }
+
+    #[test]
+    fn test_llvm_flushes_before_input() {
+        let ast = parse_brainfuck(".,").unwrap();
+        let ir = brainfuck_to_llvm_ir(&ast, &BrainfuckConfig::default()).unwrap();
+        let lines: Vec<&str> = ir.lines().collect();
+        assert!(lines.windows(2).any(|pair| {
+            pair[0].contains("@fflush(ptr null)") && pair[1].contains("@getchar()")
+        }));
+    }
+
+    #[test]
+    fn test_balanced_loop_loads_pointer_once() {
+        let source = "++++++++[>++++++++>+++++<<-]>+.>++.<+.";
+        let ast = parse_brainfuck(source).unwrap();
+        let config = BrainfuckConfig::default();
+        let hoisted = llvm_module(&ast, &[], &config, true).unwrap();
+        let plain = llvm_module(&ast, &[], &config, false).unwrap();
+        assert_eq!(hoisted, brainfuck_to_llvm_ir(&ast, &config).unwrap());
+
+        let pointer_loads = |ir: &str| ir.matches("load i64, ptr %ptr").count();
+        assert!(pointer_loads(&hoisted) < pointer_loads(&plain));
+
+        // Inside the body only the snapshot reads the pointer
+        let body = hoisted.split("\nloop_body").nth(1).unwrap();
+        let body = body.split("\nloop_end").next().unwrap();
+        assert_eq!(body.matches("load i64, ptr %ptr").count(), 1);
+        assert!(!body.contains("store i64"));
+
+        if let (Some(hoisted), Some(plain)) = (
+            run_with_lli(&hoisted, "hoisted"),
+            run_with_lli(&plain, "plain"),
+        ) {
+            assert_eq!(hoisted.stdout, b"A*B");
+            assert_eq!(hoisted.stdout, plain.stdout);
+        }
+    }
+
+    #[test]
+    fn test_llvm_quine_reproduces_its_source() {
+        // The corpus covers the interpreter and the Lamina backend
+        let source = include_str!("../../tests/corpus/quine.bf");
+        let ast = parse_brainfuck(source).unwrap();
+        let config = BrainfuckConfig::default();
+
+        for (name, ast) in [
+            ("quine", ast.clone()),
+            (
+                "quine-optimized",
+                crate::lamina_builder::optimize::optimize_ast(&ast),
+            ),
+        ] {
+            let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+            if let Some(output) = run_with_lli(&ir, name) {
+                assert_eq!(String::from_utf8_lossy(&output.stdout), source, "{}", name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_unbalanced_loop_keeps_pointer_updates() {
+        let ast = parse_brainfuck("+[>+]").unwrap();
+        let config = BrainfuckConfig::default();
+        assert_eq!(
+            llvm_module(&ast, &[], &config, true).unwrap(),
+            llvm_module(&ast, &[], &config, false).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_compile_instrumented_records_every_stage() {
+        if Process::new("gcc").arg("--version").output().is_err() {
+            eprintln!("gcc not available, skipping");
+            return;
+        }
+
+        let ast = parse_brainfuck("++++++++[>++++++++<-]>+.").unwrap();
+        let output = std::env::temp_dir().join(format!("bfina-timed-{}", std::process::id()));
+        let output = output.to_string_lossy();
+        let timings = compile_instrumented(&ast, BrainfuckConfig::default(), &output).unwrap();
+        let built = std::path::Path::new(output.as_ref()).exists();
+        let _ = std::fs::remove_file(output.as_ref());
+        assert!(built);
+
+        let stages = [
+            timings.optimize,
+            timings.ir_build,
+            timings.ir_to_asm,
+            timings.assemble,
+            timings.link,
+        ];
+        // Spawning the toolchain always takes measurable time
+        assert!(timings.assemble > Duration::ZERO);
+        assert!(timings.link > Duration::ZERO);
+        assert_eq!(timings.total(), stages.iter().sum());
+        assert!(stages.iter().all(|stage| *stage <= timings.total()));
+    }
+
+    #[test]
+    fn test_compile_instrumented_follows_opt_level() {
+        if Process::new("gcc").arg("--version").output().is_err() {
+            eprintln!("gcc not available, skipping");
+            return;
+        }
+
+        let dir = std::env::temp_dir().join(format!("bfina-opt-level-{}", std::process::id()));
+        let build = |opt_level| {
+            let config = BrainfuckConfig {
+                opt_level,
+                precompute_output: false,
+                keep_intermediates: true,
+                build_dir: Some(dir.clone()),
+                ..BrainfuckConfig::default()
+            };
+            let binary = dir.join("program");
+            compile_instrumented(
+                &parse_brainfuck("+++.").unwrap(),
+                config,
+                &binary.to_string_lossy(),
+            )
+            .unwrap();
+            // Each build keeps its assembly under its own name, so take it
+            // away before the next one
+            let asm_file = std::fs::read_dir(&dir)
+                .unwrap()
+                .map(|entry| entry.unwrap().path())
+                .find(|path| path.extension().is_some_and(|extension| extension == "s"))
+                .unwrap();
+            let asm = std::fs::read_to_string(&asm_file).unwrap();
+            std::fs::remove_file(asm_file).unwrap();
+            asm
+        };
+        let (unoptimized, optimized) = (build(0), build(1));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        // Level 1 merges the increments into one add
+        assert_eq!(unoptimized.matches("= add.i8").count(), 3);
+        assert_eq!(optimized.matches("= add.i8").count(), 1);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_compile_from_read_only_source_dir() {
+        use std::os::unix::fs::PermissionsExt;
+
+        if Process::new("gcc").arg("--version").output().is_err() {
+            eprintln!("gcc not available, skipping");
+            return;
+        }
+
+        let root = std::env::temp_dir().join(format!("bfina-read-only-{}", std::process::id()));
+        let (source_dir, out_dir, build_dir) =
+            (root.join("src"), root.join("out"), root.join("build"));
+        std::fs::create_dir_all(&source_dir).unwrap();
+        std::fs::create_dir_all(&out_dir).unwrap();
+        std::fs::write(source_dir.join("prog.bf"), "+++[>+<-]>.").unwrap();
+        let set_mode = |mode| {
+            std::fs::set_permissions(&source_dir, std::fs::Permissions::from_mode(mode)).unwrap()
+        };
+        set_mode(0o555);
+
+        let source = std::fs::read_to_string(source_dir.join("prog.bf")).unwrap();
+        let ast = parse_brainfuck(&source).unwrap();
+        let binary = out_dir.join("prog");
+        let config = BrainfuckConfig {
+            build_dir: Some(build_dir.clone()),
+            ..BrainfuckConfig::default()
+        };
+        let result = brainfuck_to_binary_with_config(&ast, &binary.to_string_lossy(), config);
+
+        // Only the binary lands in the output directory
+        let outputs: Vec<_> = std::fs::read_dir(&out_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        let build_dir_created = build_dir.is_dir();
+        set_mode(0o755);
+        let _ = std::fs::remove_dir_all(&root);
+
+        result.unwrap();
+        assert_eq!(outputs, ["prog"]);
+        assert!(build_dir_created);
+    }
+
+    #[test]
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    fn test_raw_ld_binary_is_static() {
+        let tools = ["as", "ld", "ldd"];
+        if tools
+            .iter()
+            .any(|tool| Process::new(tool).arg("--version").output().is_err())
+        {
+            eprintln!("as, ld or ldd not available, skipping");
+            return;
+        }
+
+        let binary = std::env::temp_dir().join(format!("bfina-raw-ld-{}", std::process::id()));
+        let config = BrainfuckConfig {
+            link_mode: LinkMode::RawLd,
+            ..BrainfuckConfig::default()
+        };
+        let ast = parse_brainfuck("+++[-]").unwrap();
+        brainfuck_to_binary_with_config(&ast, &binary.to_string_lossy(), config.clone()).unwrap();
+
+        let status = Process::new(&binary).status().unwrap();
+        let ldd = Process::new("ldd").arg(&binary).output().unwrap();
+        assert!(status.success());
+
+        // _start exits with the status main returns
+        let config = BrainfuckConfig {
+            exit_from_cell: true,
+            ..config
+        };
+        let ast = parse_brainfuck("+++[>++<-]>+").unwrap();
+        brainfuck_to_binary_with_config(&ast, &binary.to_string_lossy(), config).unwrap();
+        let status = Process::new(&binary).status().unwrap();
+        let _ = std::fs::remove_file(&binary);
+        assert_eq!(status.code(), Some(7));
+        assert!(
+            String::from_utf8_lossy(&ldd.stdout).contains("not a dynamic executable")
+                || String::from_utf8_lossy(&ldd.stderr).contains("not a dynamic executable")
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_configured_compiler_gets_link_flags() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("bfina-toolchain-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let args_file = dir.join("args");
+        let compiler = dir.join("fake-cc");
+        std::fs::write(
+            &compiler,
+            format!(
+                "#!/bin/sh\nprintf '%s\\n' \"$@\" > '{}'\ntouch \"$3\"\n",
+                args_file.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&compiler, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let binary = dir.join("program");
+        let config = BrainfuckConfig {
+            build_dir: Some(dir.join("build")),
+            toolchain: CompilerToolchain {
+                compiler: Some(compiler.clone()),
+                link_flags: vec!["-static".to_string(), "-Wl,--gc-sections".to_string()],
+            },
+            ..BrainfuckConfig::default()
+        };
+        let ast = parse_brainfuck("+.").unwrap();
+        let result = brainfuck_to_binary_with_config(&ast, &binary.to_string_lossy(), config);
+        let args = std::fs::read_to_string(&args_file);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(result.unwrap().contains(&compiler.display().to_string()));
+        let args: Vec<String> = args.unwrap().lines().map(str::to_string).collect();
+        let asm_prefix = dir.join("build").join("program.");
+        assert!(args[0].starts_with(&*asm_prefix.to_string_lossy()));
+        assert!(args[0].ends_with(".s"));
+        assert_eq!(args[1], "-o");
+        assert!(args[2].starts_with(&format!("{}.", binary.display())));
+        assert!(args[2].ends_with(".tmp"));
+        assert_eq!(
+            args[3..],
+            ["-static".to_string(), "-Wl,--gc-sections".to_string(),]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_concurrent_builds_of_the_same_output_both_succeed() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("bfina-concurrent-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        // Stands in for the linker: copies the assembly to the output after
+        // a pause, so the two builds overlap
+        let compiler = dir.join("fake-cc");
+        std::fs::write(&compiler, "#!/bin/sh\nsleep 0.2\ncp \"$1\" \"$3\"\n").unwrap();
+        std::fs::set_permissions(&compiler, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let binary = dir.join("program").to_string_lossy().into_owned();
+        let config = BrainfuckConfig {
+            build_dir: Some(dir.join("build")),
+            toolchain: CompilerToolchain {
+                compiler: Some(compiler),
+                link_flags: Vec::new(),
+            },
+            ..BrainfuckConfig::default()
+        };
+        let ast = parse_brainfuck("++[>+<-]>.").unwrap();
+        let results: Vec<_> = std::thread::scope(|scope| {
+            let builds: Vec<_> = (0..2)
+                .map(|_| scope.spawn(|| compile_binary(&ast, &binary, config.clone())))
+                .collect();
+            builds
+                .into_iter()
+                .map(|build| build.join().unwrap())
+                .collect()
+        });
+        let output = std::fs::read_to_string(&binary);
+        let leftovers = std::fs::read_dir(&dir)
+            .unwrap()
+            .chain(std::fs::read_dir(dir.join("build")).unwrap())
+            .count();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        for result in results {
+            assert!(result.is_ok(), "{:?}", result.err());
+        }
+        assert!(!output.unwrap().is_empty());
+        // Only the fake compiler, the build directory and the output remain
+        assert_eq!(leftovers, 3);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_intermediate_stems_are_unique_per_build() {
+        let build_dir = std::env::temp_dir();
+        let first = intermediate_stem(&build_dir, "out/program").unwrap();
+        let second = intermediate_stem(&build_dir, "out/program").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(first.parent(), Some(build_dir.as_path()));
+        assert_eq!(
+            intermediate_path(&build_dir.join("program"), "s"),
+            build_dir.join("program.s")
+        );
+    }
+
+    #[test]
+    fn test_keep_intermediates() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = std::env::temp_dir().join(format!("bfina-keep-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let compiler = dir.join("fake-cc");
+        std::fs::write(&compiler, "#!/bin/sh\ntouch \"$3\"\n").unwrap();
+        std::fs::set_permissions(&compiler, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let build_dir = dir.join("build");
+        let binary = dir.join("program").to_string_lossy().into_owned();
+        let config = BrainfuckConfig {
+            build_dir: Some(build_dir.clone()),
+            toolchain: CompilerToolchain {
+                compiler: Some(compiler),
+                link_flags: Vec::new(),
+            },
+            ..BrainfuckConfig::default()
+        };
+        let ast = parse_brainfuck("+.").unwrap();
+
+        let removed = brainfuck_to_binary_with_config(&ast, &binary, config.clone());
+        let left_behind = std::fs::read_dir(&build_dir).unwrap().count();
+        let config = BrainfuckConfig {
+            keep_intermediates: true,
+            ..config
+        };
+        let kept = brainfuck_to_binary_with_config(&ast, &binary, config.clone());
+        let output = compile_binary(&ast, &binary, config);
+        let kept_by_both = std::fs::read_dir(&build_dir).unwrap().count();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        assert!(!removed.unwrap().contains("Intermediate files kept"));
+        assert_eq!(left_behind, 0);
+        assert!(kept.unwrap().contains("Intermediate files kept"));
+        // Each build keeps its own pair rather than overwriting the other's
+        assert_eq!(kept_by_both, 4);
+
+        let output = output.unwrap();
+        assert_eq!(output.binary_path, PathBuf::from(&binary));
+        let (lamina_file, asm_file) = (output.ir_path.unwrap(), output.asm_path.unwrap());
+        assert_eq!(lamina_file.parent(), Some(build_dir.as_path()));
+        assert_eq!(lamina_file.extension().unwrap(), "lamina");
+        assert_eq!(asm_file.with_extension("lamina"), lamina_file);
+        assert!(output.linker.ends_with("fake-cc"));
+    }
+
+    #[test]
+    fn test_ir_module_matches_printed_ir() {
+        let ast = parse_brainfuck("++[>+<-]>.").unwrap();
+        let builder = BrainfuckIRBuilder::new();
+        let module = brainfuck_to_ir_module(&ast, &builder).unwrap();
+        // Lamina prints a function's blocks in no particular order, and
+        // closes the function after whichever comes last
+        let blocks = |ir: &str| {
+            let mut blocks: Vec<Vec<String>> = Vec::new();
+            for line in ir.lines().filter(|line| *line != "}") {
+                if line.ends_with(':') || blocks.is_empty() {
+                    blocks.push(Vec::new());
+                }
+                blocks.last_mut().unwrap().push(line.to_string());
             }
+            blocks.sort();
+            blocks
+        };
+        assert_eq!(
+            blocks(&module.to_string()),
+            blocks(&brainfuck_to_lamina_ir(&ast).unwrap())
+        );
+
+        let builder = BrainfuckIRBuilder::with_config(BrainfuckConfig::new(0, 1));
+        assert!(brainfuck_to_ir_module(&ast, &builder).is_err());
+    }
+
+    #[test]
+    fn test_degenerate_tapes_are_rejected() {
+        let ast = parse_brainfuck("+.").unwrap();
+        for (config, message) in [
+            (
+                BrainfuckConfig::new(0, 0),
+                "Tape size must be at least one cell",
+            ),
+            (
+                BrainfuckConfig::new(100, 0),
+                "Cell size must be at least one byte",
+            ),
+        ] {
+            let lamina = brainfuck_to_lamina_ir_with_config(&ast, config.clone());
+            assert_eq!(ir_error(lamina), message);
+            let llvm = brainfuck_to_llvm_ir(&ast, &config);
+            assert_eq!(ir_error(llvm), message);
+        }
+    }
+
+    #[test]
+    fn test_llvm_negative_tape() {
+        let ast = parse_brainfuck("+<++>.<.").unwrap();
+        let config = BrainfuckConfig {
+            allow_negative_tape: true,
+            static_bounds_check: true,
+            ..BrainfuckConfig::default()
+        };
+        let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+        assert!(ir.contains("store i64 15000, ptr %ptr"));
+        assert!(brainfuck_to_lamina_ir_with_config(&ast, config).is_ok());
+
+        if let Some(output) = run_with_lli(&ir, "negative-tape") {
+            assert_eq!(output.stdout, [1, 2]);
+        }
+    }
+
+    /// Build C source with the default toolchain and run it on `input`,
+    /// returning `None` if no C compiler is installed
+    fn run_c(source: &str, name: &str, input: &[u8]) -> Option<std::process::Output> {
+        let Ok(compiler) = find_compiler(&CompilerToolchain::default()) else {
+            eprintln!("no C compiler available, skipping");
+            return None;
+        };
+
+        let dir = std::env::temp_dir();
+        let c_file = dir.join(format!("bfina-{}-{}.c", name, std::process::id()));
+        let binary = dir.join(format!("bfina-{}-{}", name, std::process::id()));
+        std::fs::write(&c_file, source).unwrap();
+        let status = Process::new(compiler)
+            .arg(&c_file)
+            .arg("-o")
+            .arg(&binary)
+            .status()
+            .unwrap();
+        let _ = std::fs::remove_file(&c_file);
+        assert!(status.success(), "C compiler rejected:\n{}", source);
+
+        let mut child = Process::new(&binary)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        std::io::Write::write_all(&mut child.stdin.take().unwrap(), input).unwrap();
+        let output = child.wait_with_output().unwrap();
+        let _ = std::fs::remove_file(&binary);
+        Some(output)
+    }
+
+    #[test]
+    fn test_c_backend() {
+        let ast = parse_brainfuck(",.").unwrap();
+        let c = brainfuck_to_c(&ast, &BrainfuckConfig::default()).unwrap();
+        assert!(c.contains("static unsigned char tape[30000];"));
+        if let Some(output) = run_c(&c, "echo", b"x") {
+            assert_eq!(output.stdout, b"x");
+        }
+
+        // Optimized nodes, cells left of the start, and end of input
+        let source = include_str!("../../testcases/hello_world.bf");
+        let ast = optimize_ast(&parse_brainfuck(&format!("<-.>{},.", source)).unwrap());
+        let config = BrainfuckConfig {
+            allow_negative_tape: true,
+            eof_behavior: EofBehavior::Zero,
+            ..BrainfuckConfig::default()
+        };
+        let c = brainfuck_to_c(&ast, &config).unwrap();
+        assert!(c.contains("tape[p + 1] += tape[p] * 1;"));
+        let mut expected = Vec::new();
+        crate::interpreter::run(&ast, &config, &mut std::io::empty(), &mut expected).unwrap();
+        assert!(expected.starts_with(&[255]) && expected.ends_with(&[0]));
+        if let Some(output) = run_c(&c, "hello", b"") {
+            assert_eq!(output.stdout, expected);
+        }
+    }
+
+    #[test]
+    fn test_c_backend_trace_and_exit_code() {
+        let ast = parse_brainfuck("+>+[-]+").unwrap();
+        let config = BrainfuckConfig {
+            trace: true,
+            exit_from_cell: true,
+            cell_wrap: CellWrap::Saturate,
+            ..BrainfuckConfig::default()
+        };
+        let c = brainfuck_to_c(&ast, &config).unwrap();
+        let Some(output) = run_c(&c, "trace", b"") else {
+            return;
+        };
+
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "+ 0 1\n> 1 0\n+ 1 1\n[ 1 1\n- 1 0\n] 1 0\n+ 1 1\n"
+        );
+        assert_eq!(output.status.code(), Some(1));
+    }
+
+    #[test]
+    fn test_growable_tapes_are_rejected() {
+        let ast = parse_brainfuck("+.").unwrap();
+        let config = BrainfuckConfig {
+            tape_model: TapeModel::Growable,
+            ..BrainfuckConfig::default()
+        };
+        let lamina = brainfuck_to_lamina_ir_with_config(&ast, config.clone());
+        assert_eq!(
+            ir_error(lamina),
+            "A growable tape is not supported by the Lamina backend"
+        );
+        let llvm = brainfuck_to_llvm_ir(&ast, &config);
+        assert_eq!(
+            ir_error(llvm),
+            "A growable tape is not supported by the LLVM backend"
+        );
+        let c = brainfuck_to_c(&ast, &config);
+        assert_eq!(
+            ir_error(c),
+            "A growable tape is not supported by the C backend"
+        );
+    }
+
+    #[test]
+    fn test_runtime_bounds_checks_are_lamina_only() {
+        let ast = parse_brainfuck(">.").unwrap();
+        let config = BrainfuckConfig {
+            runtime_bounds_check: Some(PointerMode::Trap),
+            ..BrainfuckConfig::default()
+        };
+        assert!(brainfuck_to_lamina_ir_with_config(&ast, config.clone()).is_ok());
+        assert_eq!(
+            ir_error(brainfuck_to_llvm_ir(&ast, &config)),
+            "Runtime bounds checks are not supported by the LLVM backend"
+        );
+        assert_eq!(
+            ir_error(brainfuck_to_c(&ast, &config)),
+            "Runtime bounds checks are not supported by the C backend"
+        );
+    }
+
+    #[test]
+    fn test_io_at_offset_leaves_pointer_alone() {
+        let config = BrainfuckConfig::default();
+
+        for (source, expected) in [(">.<", "OutputAtOffset(1)"), (">,<", "InputAtOffset(1)")] {
+            let ast = optimize_ast(&parse_brainfuck(source).unwrap());
+            assert_eq!(format!("{:?}", ast), format!("[{}]", expected));
+
+            // Only the initial store of zero writes the pointer
+            let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+            assert_eq!(ir.matches("store i64").count(), 1, "{}", source);
+        }
+
+        let ast = optimize_ast(&parse_brainfuck(",>,<.>.").unwrap());
+        let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+        if let Some(output) = run_with_lli_input(&ir, "io-offset", b"ab") {
+            assert_eq!(output.stdout, b"ab");
+        }
+    }
+
+    #[test]
+    fn test_static_bounds_check() {
+        let config = BrainfuckConfig {
+            static_bounds_check: true,
+            ..BrainfuckConfig::small()
+        };
+
+        let ast = parse_brainfuck(&">".repeat(SMALL_TAPE_SIZE)).unwrap();
+        let err = brainfuck_to_llvm_ir(&ast, &config).unwrap_err();
+        assert!(err.to_string().contains("out of bounds to cell 1000"));
+        assert!(brainfuck_to_llvm_ir(&ast, &BrainfuckConfig::small()).is_ok());
+
+        // How far the pointer moves depends on the input
+        let ast = parse_brainfuck(",[>-]<").unwrap();
+        assert!(brainfuck_to_llvm_ir(&ast, &config).is_ok());
+    }
+
+    #[test]
+    fn test_annotated_llvm_ir() {
+        let (ast, spans) = crate::lexer::parse_brainfuck_spanned("+\n [-\n  ]>.").unwrap();
+        let config = BrainfuckConfig {
+            annotate_ir: true,
+            ..BrainfuckConfig::default()
+        };
+        let ir = brainfuck_to_llvm_ir_with_spans(&ast, &spans, &config).unwrap();
+        let annotations: Vec<&str> = ir
+            .lines()
+            .map(str::trim)
+            .filter(|line| line.starts_with("; cmd"))
+            .collect();
+        assert_eq!(
+            annotations,
+            vec![
+                "; cmd 0: Increment (line 1, col 1)",
+                "; cmd 1: Loop (line 2, col 2)",
+                "; cmd 2: Decrement (line 2, col 3)",
+                "; cmd 3: Right (line 3, col 4)",
+                "; cmd 4: Output (line 3, col 5)",
+            ]
+        );
+
+        // Without spans only the index and name are known
+        let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+        assert!(ir.contains("; cmd 1: Loop\n"));
+
+        let plain = brainfuck_to_llvm_ir(&ast, &BrainfuckConfig::default()).unwrap();
+        assert!(!plain.contains("; cmd"));
+    }
+
+    #[test]
+    fn test_staticlib_exports_symbol() {
+        let tools = ["gcc", "objcopy", "ar", "nm"];
+        if tools
+            .iter()
+            .any(|tool| Process::new(tool).arg("--version").output().is_err())
+        {
+            eprintln!("binutils not available, skipping");
+            return;
         }
-        Err(e) => Err(format!("Lamina compilation failed: {}", e)),
+
+        let ast = parse_brainfuck("++++++++[>++++++++<-]>+.").unwrap();
+        let output = std::env::temp_dir().join(format!("bfina-lib-{}.a", std::process::id()));
+        let output = output.to_string_lossy();
+        brainfuck_to_staticlib(&ast, &output, "bf_print_a", BrainfuckConfig::default()).unwrap();
+
+        let symbols = Process::new("nm").arg(output.as_ref()).output().unwrap();
+        let _ = std::fs::remove_file(output.as_ref());
+        let symbols = String::from_utf8_lossy(&symbols.stdout);
+        assert!(symbols.lines().any(|line| line.ends_with(" T bf_print_a")));
+        assert!(!symbols.lines().any(|line| line.ends_with(" T main")));
+    }
+
+    #[test]
+    fn test_staticlib_rejects_invalid_symbol() {
+        let ast = parse_brainfuck("+").unwrap();
+        for symbol in ["", "1abc", "bad-name"] {
+            let result =
+                brainfuck_to_staticlib(&ast, "unused.a", symbol, BrainfuckConfig::default());
+            assert!(matches!(result, Err(CompileError::InvalidSymbol(_))));
+        }
+    }
+
+    #[test]
+    fn test_llvm_trace_output() {
+        let ast = parse_brainfuck("+>+[-]").unwrap();
+        let config = BrainfuckConfig {
+            trace: true,
+            ..BrainfuckConfig::default()
+        };
+        let ir = brainfuck_to_llvm_ir(&ast, &config).unwrap();
+        let Some(output) = run_with_lli(&ir, "trace") else {
+            return;
+        };
+
+        assert_eq!(
+            String::from_utf8_lossy(&output.stderr),
+            "+ 0 1\n> 1 0\n+ 1 1\n[ 1 1\n- 1 0\n] 1 0\n"
+        );
     }
 }
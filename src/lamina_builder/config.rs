@@ -1,19 +1,438 @@
 //! Configuration for Brainfuck compilation
 
+use super::optimize::{MAX_OPT_LEVEL, optimize_at_level, optimize_cells_at_level};
+use crate::lexer::AstNode;
+use crate::tape::PointerMode;
+use std::path::PathBuf;
+
+/// Tape size of the classic Brainfuck implementation
+pub const STANDARD_TAPE_SIZE: usize = 30000;
+
+/// Tape size used by [`BrainfuckConfig::small`]
+pub const SMALL_TAPE_SIZE: usize = 1000;
+
+/// Tape size used by [`BrainfuckConfig::large`]
+pub const LARGE_TAPE_SIZE: usize = 100000;
+
+/// Default cap on the tape allocation used by [`BrainfuckConfig::max_tape_bytes`]
+pub const DEFAULT_MAX_TAPE_BYTES: usize = 1 << 30;
+
+/// Default for [`BrainfuckConfig::input_buffer_size`]
+pub const DEFAULT_INPUT_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Default for [`BrainfuckConfig::auto_compile_threshold`]
+pub const DEFAULT_AUTO_COMPILE_THRESHOLD: usize = 10_000;
+
+/// Environment variable naming the build directory when
+/// [`BrainfuckConfig::build_dir`] is unset
+pub const BUILD_DIR_ENV: &str = "BFINA_BUILD_DIR";
+
+/// Build directory used when neither [`BrainfuckConfig::build_dir`] nor
+/// [`BUILD_DIR_ENV`] is set
+///
+/// The system temp directory is shared by every user, so the directory is
+/// named after the current user id where the system reports one, then the
+/// user name, and the process id as a last resort. It is only ever created
+/// private to its owner.
+pub fn default_build_dir() -> PathBuf {
+    #[cfg(unix)]
+    let uid = {
+        use std::os::unix::fs::MetadataExt;
+        std::fs::metadata("/proc/self")
+            .ok()
+            .map(|metadata| metadata.uid().to_string())
+    };
+    #[cfg(not(unix))]
+    let uid = None;
+    let owner = uid
+        .or_else(|| {
+            std::env::var("USER")
+                .or_else(|_| std::env::var("USERNAME"))
+                .ok()
+                .filter(|name| {
+                    !name.is_empty()
+                        && name
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || "._-".contains(c))
+                })
+        })
+        .unwrap_or_else(|| std::process::id().to_string());
+    std::env::temp_dir().join(format!("bfina-build-{owner}"))
+}
+
+/// Newline translation applied to bytes written by the output command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputNewline {
+    /// Write every byte unchanged
+    #[default]
+    AsIs,
+    /// Write a carriage return (`\r`) before every line feed (`\n`)
+    CrLf,
+    /// Drop carriage returns so only bare line feeds are written
+    Lf,
+}
+
+/// What the interpreter does when the data pointer moves off the tape
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OnError {
+    /// Stop and return a [`RuntimeError`](crate::interpreter::RuntimeError)
+    #[default]
+    Return,
+    /// Ignore the move, leaving the pointer on the first or last cell
+    Saturate,
+    /// Panic with the error message
+    Panic,
+}
+
+/// What the input command stores when the input has ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum EofBehavior {
+    /// Leave the cell unchanged
+    #[default]
+    Unchanged,
+    /// Set the cell to 0
+    Zero,
+    /// Set the cell to 255 (-1)
+    NegativeOne,
+}
+
+/// What happens when `+` or `-` takes a cell past 255 or below 0
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CellWrap {
+    /// Wrap around modulo 256
+    #[default]
+    Wrap,
+    /// Stop at 255 or 0
+    Saturate,
+    /// Abort the program
+    Error,
+}
+
+/// How the assembled program is linked into an executable
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LinkMode {
+    /// Link with `gcc` against the C library
+    #[default]
+    Gcc,
+    /// Link with `ld` into a static executable without the C library
+    ///
+    /// A minimal `_start` calls `main` and passes its return value to the
+    /// `exit` syscall, so the program's I/O must use raw syscalls as well:
+    /// any C library function it calls is reported as an undefined symbol.
+    /// Only supported on x86-64 Linux.
+    RawLd,
+}
+
+/// How the tape is sized
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TapeModel {
+    /// Exactly `tape_size` cells
+    #[default]
+    Fixed,
+    /// Start with `tape_size` cells and grow the tape whenever the pointer
+    /// moves past its end
+    ///
+    /// The tape never grows beyond `max_tape_bytes`. Only the interpreter
+    /// supports growth: both compiled backends allocate the tape once, so
+    /// they reject this model.
+    Growable,
+}
+
+/// C compilers tried, in order, when [`CompilerToolchain::compiler`] is unset
+pub const DEFAULT_COMPILERS: &[&str] = &["cc", "gcc", "clang"];
+
+/// C compiler used to assemble and link programs with [`LinkMode::Gcc`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompilerToolchain {
+    /// Compiler command, or `None` to use the first of [`DEFAULT_COMPILERS`]
+    /// that runs
+    pub compiler: Option<PathBuf>,
+    /// Extra flags passed to the compiler when linking
+    ///
+    /// Lamina's assembly uses absolute addresses, so the default is
+    /// `-no-pie` everywhere except macOS, whose toolchain doesn't accept it.
+    pub link_flags: Vec<String>,
+}
+
+impl Default for CompilerToolchain {
+    fn default() -> Self {
+        let link_flags = if cfg!(target_os = "macos") {
+            Vec::new()
+        } else {
+            vec!["-no-pie".to_string()]
+        };
+        Self {
+            compiler: None,
+            link_flags,
+        }
+    }
+}
+
+/// Options for Lamina's IR-to-assembly compiler
+///
+/// Build it with [`LaminaOptions::default`] and set the fields to change;
+/// new Lamina knobs are added here as fields.
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LaminaOptions {
+    /// Lamina target to generate assembly for, such as `"x86_64_linux"` or
+    /// `"aarch64_linux"` (see `lamina::HOST_ARCH_LIST`)
+    ///
+    /// Defaults to the host. Assembly for another target can be emitted,
+    /// but not assembled or linked into a binary here.
+    pub target: String,
+}
+
+impl Default for LaminaOptions {
+    fn default() -> Self {
+        Self {
+            target: lamina::detect_host_architecture().to_string(),
+        }
+    }
+}
+
+/// Error returned when a [`BrainfuckConfig`] is invalid
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The tape must contain at least one cell
+    ZeroTapeSize,
+    /// Cells must be at least one byte wide
+    ZeroCellSize,
+    /// Only 8-bit cells are supported by the code generator
+    UnsupportedCellSize(usize),
+    /// Tape alignment must be a nonzero power of two
+    InvalidTapeAlignment(usize),
+    /// The tape allocation exceeds `max_tape_bytes`
+    TapeTooLarge { bytes: usize, limit: usize },
+    /// The tape allocation, padded to `tape_alignment`, overflows a `usize`
+    TapeSizeOverflow,
+    /// The interpreter's input buffer must hold at least one byte
+    ZeroInputBufferSize,
+    /// The optimization level is above [`MAX_OPT_LEVEL`]
+    InvalidOptLevel(u8),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::ZeroTapeSize => write!(f, "Tape size must be at least one cell"),
+            ConfigError::ZeroCellSize => write!(f, "Cell size must be at least one byte"),
+            ConfigError::UnsupportedCellSize(size) => {
+                write!(f, "Unsupported cell size of {} bytes (expected 1)", size)
+            }
+            ConfigError::InvalidTapeAlignment(align) => {
+                write!(f, "Tape alignment {} is not a power of two", align)
+            }
+            ConfigError::TapeTooLarge { bytes, limit } => {
+                write!(
+                    f,
+                    "Tape of {} bytes exceeds the limit of {} bytes",
+                    bytes, limit
+                )
+            }
+            ConfigError::TapeSizeOverflow => {
+                write!(f, "Tape size overflows the address space")
+            }
+            ConfigError::ZeroInputBufferSize => {
+                write!(f, "Input buffer size must be at least one byte")
+            }
+            ConfigError::InvalidOptLevel(level) => {
+                write!(
+                    f,
+                    "Optimization level {} is above the maximum of {}",
+                    level, MAX_OPT_LEVEL
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
 /// Configuration for Brainfuck compilation
 #[derive(Debug, Clone)]
 pub struct BrainfuckConfig {
     /// Size of the memory tape (number of cells)
+    ///
+    /// With [`TapeModel::Growable`] this is only the initial size.
     pub tape_size: usize,
+    /// Whether the tape has a fixed size or grows as the program needs
+    pub tape_model: TapeModel,
+    /// Start the data pointer in the middle of the tape instead of on its
+    /// first cell, so `<` can reach cells left of where the program starts
+    ///
+    /// See [`BrainfuckConfig::start_cell`] for the exact cell.
+    pub allow_negative_tape: bool,
     /// Size of each memory cell in bytes (usually 1 for Brainfuck)
     pub cell_size: usize,
+    /// Newline translation for the output command
+    pub output_newline: OutputNewline,
+    /// Alignment, in bytes, that the tape allocation is padded to
+    ///
+    /// The tape is allocated as a whole number of `tape_alignment`-sized
+    /// blocks, so vectorized code can always touch full blocks without
+    /// running off the end. Lamina has no alignment hint for allocations,
+    /// so the base address only gets the backend's natural alignment.
+    pub tape_alignment: usize,
+    /// Run input-free programs at compile time and emit only their output
+    ///
+    /// Programs that [`may_not_terminate`](crate::analysis::may_not_terminate)
+    /// are compiled normally without being run.
+    pub precompute_output: bool,
+    /// Maximum number of interpreter steps spent precomputing output before
+    /// falling back to normal code generation
+    pub precompute_step_budget: u64,
+    /// Maximum number of commands and loops a program may contain, or `None`
+    /// for no limit
+    pub max_operations: Option<usize>,
+    /// Instrument the compiled program to log every executed command, the
+    /// data pointer, and the current cell to stderr
+    ///
+    /// Only supported by the LLVM backend: Lamina can only write to stdout.
+    pub trace: bool,
+    /// Largest tape allocation, in bytes, that is accepted, or `None` for no
+    /// limit
+    ///
+    /// Oversized tapes are rejected before any code is generated or run, so
+    /// a typo in `tape_size` gives a clear error instead of a program that
+    /// the OS kills at startup.
+    pub max_tape_bytes: Option<usize>,
+    /// Precede the generated code for every node with a comment naming it
+    ///
+    /// Only supported by the LLVM backend: Lamina's builder has no way to
+    /// emit comments.
+    pub annotate_ir: bool,
+    /// Refuse to compile programs whose pointer certainly leaves the tape
+    ///
+    /// Only straight-line moves are checked; see
+    /// [`certain_out_of_bounds`](crate::analysis::certain_out_of_bounds).
+    pub static_bounds_check: bool,
+    /// Check every pointer move at runtime in programs built by the Lamina
+    /// backend, or `None` to leave the pointer unchecked
+    ///
+    /// A move off the tape kills the program with an illegal instruction
+    /// (SIGILL on x86_64, SIGTRAP on aarch64) with [`PointerMode::Trap`],
+    /// continues from the other end with [`PointerMode::Wrap`], and stops
+    /// on the first or last cell with [`PointerMode::Clamp`]. The
+    /// optimizer leaves pointer moves as written, and cells that optimized
+    /// nodes reach at an offset are walked to one checked step at a time, so
+    /// every level behaves like `-O0`. Not supported by the LLVM and C
+    /// backends.
+    pub runtime_bounds_check: Option<PointerMode>,
+    /// Number of bytes the interpreter reads from its input at a time
+    ///
+    /// Input commands are served from a buffer of this size, so unbuffered
+    /// readers such as a raw file or socket aren't hit with a `read` call per
+    /// byte. Compiled programs use the C library's buffering instead.
+    pub input_buffer_size: usize,
+    /// How the interpreter handles the data pointer leaving the tape
+    ///
+    /// This only affects the interpreter; see `runtime_bounds_check` for
+    /// compiled programs.
+    pub on_error: OnError,
+    /// Largest number of steps the interpreter runs before stopping with
+    /// [`RuntimeError::StepLimitExceeded`], or `None` for no limit
+    ///
+    /// Every executed instruction is a step: a command, a run of commands
+    /// the interpreter fuses into one (see [`compile`]) or a loop condition
+    /// check. Untrusted or non-terminating programs can then be run without
+    /// hanging.
+    /// This only affects the interpreter.
+    ///
+    /// [`RuntimeError::StepLimitExceeded`]: crate::interpreter::RuntimeError::StepLimitExceeded
+    /// [`compile`]: crate::interpreter::compile
+    pub max_steps: Option<u64>,
+    /// Options passed to Lamina when compiling its IR to assembly
+    pub lamina: LaminaOptions,
+    /// Number of commands and loops from which [`auto_run`] compiles a
+    /// looping program instead of interpreting it
+    ///
+    /// [`auto_run`]: crate::runner::auto_run
+    pub auto_compile_threshold: usize,
+    /// Exit with the value of the current cell when the program ends,
+    /// instead of with 0
+    ///
+    /// The Lamina backend needs `emit_int_main` for this, since a void
+    /// `main` has no exit code to return.
+    pub exit_from_cell: bool,
+    /// Build the Lamina backend's `main` as returning `i32` and exiting with
+    /// 0, rather than returning void
+    ///
+    /// Some platforms expect `main` to return `int`, and linking a void
+    /// `main` can leave the exit status undefined. The LLVM and C backends
+    /// always emit an `int main`.
+    pub emit_int_main: bool,
+    /// Code run before the program, such as setup of the initial tape
+    ///
+    /// The prelude and epilogue are [`AstNode`]s rather than source text,
+    /// so their brackets are balanced by construction and wrapping a
+    /// program can't unbalance it.
+    pub prelude: Vec<AstNode>,
+    /// Code run after the program, such as printing a trailing newline
+    pub epilogue: Vec<AstNode>,
+    /// Directory for intermediate files such as `.lamina` and `.s`, or
+    /// `None` to use [`BUILD_DIR_ENV`] or the system temp directory
+    ///
+    /// Only the final binary is written at the requested output path, so
+    /// programs can be compiled from read-only or sandboxed directories.
+    pub build_dir: Option<PathBuf>,
+    /// Leave intermediate files such as `.lamina` and `.s` in the build
+    /// directory instead of removing them after a build, for inspecting the
+    /// generated code
+    pub keep_intermediates: bool,
+    /// How compiled programs are linked
+    pub link_mode: LinkMode,
+    /// Compiler and flags used to link with [`LinkMode::Gcc`]
+    pub toolchain: CompilerToolchain,
+    /// What happens when a cell overflows or underflows
+    ///
+    /// The optimizer assumes wrapping cells when it folds runs of `+` and
+    /// `-` or lowers clear loops, so only unoptimized programs behave
+    /// exactly as written under the other modes.
+    pub cell_wrap: CellWrap,
+    /// Which optimizer passes the backends run over the program, from 0
+    /// (none) to [`MAX_OPT_LEVEL`] (all of them)
+    ///
+    /// See [`optimize_at_level`] for the passes each level enables. Programs
+    /// are only optimized with [`CellWrap::Wrap`].
+    pub opt_level: u8,
+    /// What the input command does at end of input
+    pub eof_behavior: EofBehavior,
 }
 
 impl Default for BrainfuckConfig {
     fn default() -> Self {
         Self {
-            tape_size: 30000, // normal brainfuck tape size
-            cell_size: 1,     // 8-bit cells
+            tape_size: STANDARD_TAPE_SIZE,
+            tape_model: TapeModel::Fixed,
+            allow_negative_tape: false,
+            cell_size: 1, // 8-bit cells
+            output_newline: OutputNewline::AsIs,
+            tape_alignment: 1,
+            precompute_output: false,
+            precompute_step_budget: 1_000_000,
+            max_operations: None,
+            trace: false,
+            max_tape_bytes: Some(DEFAULT_MAX_TAPE_BYTES),
+            annotate_ir: false,
+            static_bounds_check: false,
+            runtime_bounds_check: None,
+            input_buffer_size: DEFAULT_INPUT_BUFFER_SIZE,
+            on_error: OnError::Return,
+            max_steps: None,
+            lamina: LaminaOptions::default(),
+            auto_compile_threshold: DEFAULT_AUTO_COMPILE_THRESHOLD,
+            exit_from_cell: false,
+            emit_int_main: true,
+            prelude: Vec::new(),
+            epilogue: Vec::new(),
+            build_dir: None,
+            keep_intermediates: false,
+            link_mode: LinkMode::Gcc,
+            toolchain: CompilerToolchain::default(),
+            cell_wrap: CellWrap::Wrap,
+            opt_level: 0,
+            eof_behavior: EofBehavior::Unchanged,
         }
     }
 }
@@ -24,22 +443,277 @@ impl BrainfuckConfig {
         Self {
             tape_size,
             cell_size,
+            ..Self::default()
         }
     }
 
     /// Create a configuration optimized for small programs
     pub fn small() -> Self {
         Self {
-            tape_size: 1000,
+            tape_size: SMALL_TAPE_SIZE,
             cell_size: 1,
+            ..Self::default()
         }
     }
 
     /// Create a configuration optimized for large programs
     pub fn large() -> Self {
         Self {
-            tape_size: 100000,
+            tape_size: LARGE_TAPE_SIZE,
             cell_size: 1,
+            ..Self::default()
         }
     }
+
+    /// `ast` with the prelude before it and the epilogue after it
+    ///
+    /// Every backend and the interpreter run this instead of `ast` itself.
+    pub fn wrap_program(&self, ast: &[AstNode]) -> Vec<AstNode> {
+        let mut program = Vec::with_capacity(self.prelude.len() + ast.len() + self.epilogue.len());
+        program.extend_from_slice(&self.prelude);
+        program.extend_from_slice(ast);
+        program.extend_from_slice(&self.epilogue);
+        program
+    }
+
+    /// `ast` optimized at `opt_level`
+    ///
+    /// The optimizer assumes cells wrap, so with any other `cell_wrap` the
+    /// program is returned as written. It also assumes the program starts on
+    /// a zeroed tape, so backends pass the output of [`Self::wrap_program`].
+    /// With `runtime_bounds_check`, every pointer move is kept as written
+    /// (see [`optimize_cells_at_level`]) so each one is still checked.
+    pub fn optimize_program(&self, ast: &[AstNode]) -> Vec<AstNode> {
+        match (self.cell_wrap, self.runtime_bounds_check) {
+            (CellWrap::Wrap, None) => optimize_at_level(ast, self.opt_level),
+            (CellWrap::Wrap, Some(_)) => optimize_cells_at_level(ast, self.opt_level),
+            _ => ast.to_vec(),
+        }
+    }
+
+    /// Index of the cell the data pointer starts on
+    ///
+    /// This is 0, or `tape_size / 2` with `allow_negative_tape`, which
+    /// leaves the rounded-down half of the tape to the left of the start.
+    pub fn start_cell(&self) -> usize {
+        if self.allow_negative_tape {
+            self.tape_size / 2
+        } else {
+            0
+        }
+    }
+
+    /// Directory that intermediate build files are written to
+    ///
+    /// This is `build_dir` if set, then the directory named by
+    /// [`BUILD_DIR_ENV`], then [`default_build_dir`].
+    pub fn resolved_build_dir(&self) -> PathBuf {
+        self.build_dir
+            .clone()
+            .or_else(|| std::env::var_os(BUILD_DIR_ENV).map(PathBuf::from))
+            .unwrap_or_else(default_build_dir)
+    }
+
+    /// Number of bytes actually allocated for the tape, including the padding
+    /// required by `tape_alignment`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the size overflows a `usize`, which [`validate`] reports as
+    /// [`ConfigError::TapeSizeOverflow`].
+    ///
+    /// [`validate`]: BrainfuckConfig::validate
+    pub fn allocated_tape_bytes(&self) -> usize {
+        self.checked_tape_bytes()
+            .expect("tape size overflows usize")
+    }
+
+    /// [`allocated_tape_bytes`](BrainfuckConfig::allocated_tape_bytes), or
+    /// `None` if it overflows
+    fn checked_tape_bytes(&self) -> Option<usize> {
+        self.tape_size
+            .checked_mul(self.cell_size)?
+            .checked_next_multiple_of(self.tape_alignment)
+    }
+
+    /// Check that the configuration is internally consistent
+    ///
+    /// Every compile entry point calls this before generating any code, so an
+    /// invalid configuration is reported up front rather than as broken IR.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.tape_size == 0 {
+            return Err(ConfigError::ZeroTapeSize);
+        }
+
+        if self.cell_size == 0 {
+            return Err(ConfigError::ZeroCellSize);
+        }
+
+        if self.cell_size != 1 {
+            return Err(ConfigError::UnsupportedCellSize(self.cell_size));
+        }
+
+        if !self.tape_alignment.is_power_of_two() {
+            return Err(ConfigError::InvalidTapeAlignment(self.tape_alignment));
+        }
+
+        if self.input_buffer_size == 0 {
+            return Err(ConfigError::ZeroInputBufferSize);
+        }
+
+        if self.opt_level > MAX_OPT_LEVEL {
+            return Err(ConfigError::InvalidOptLevel(self.opt_level));
+        }
+
+        let bytes = self
+            .checked_tape_bytes()
+            .ok_or(ConfigError::TapeSizeOverflow)?;
+        if let Some(limit) = self.max_tape_bytes
+            && bytes > limit
+        {
+            return Err(ConfigError::TapeTooLarge { bytes, limit });
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_preset_tape_sizes() {
+        assert_eq!(BrainfuckConfig::default().tape_size, STANDARD_TAPE_SIZE);
+        assert_eq!(BrainfuckConfig::small().tape_size, SMALL_TAPE_SIZE);
+        assert_eq!(BrainfuckConfig::large().tape_size, LARGE_TAPE_SIZE);
+    }
+
+    #[test]
+    fn test_start_cell() {
+        let config = BrainfuckConfig::new(7, 1);
+        assert_eq!(config.start_cell(), 0);
+        let config = BrainfuckConfig {
+            allow_negative_tape: true,
+            ..config
+        };
+        assert_eq!(config.start_cell(), 3);
+    }
+
+    #[test]
+    fn test_default_build_dir_is_not_shared() {
+        let dir = default_build_dir();
+        assert_eq!(dir.parent(), Some(std::env::temp_dir().as_path()));
+        let name = dir.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.starts_with("bfina-build-"), "{name}");
+        assert_ne!(name, "bfina-build-");
+    }
+
+    #[test]
+    fn test_presets_are_valid() {
+        assert_eq!(BrainfuckConfig::default().validate(), Ok(()));
+        assert_eq!(BrainfuckConfig::small().validate(), Ok(()));
+        assert_eq!(BrainfuckConfig::large().validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_zero_tape_size_is_rejected() {
+        let config = BrainfuckConfig::new(0, 1);
+        assert_eq!(config.validate(), Err(ConfigError::ZeroTapeSize));
+    }
+
+    #[test]
+    fn test_unsupported_cell_size_is_rejected() {
+        let config = BrainfuckConfig::new(100, 0);
+        assert_eq!(config.validate(), Err(ConfigError::ZeroCellSize));
+
+        let config = BrainfuckConfig::new(100, 4);
+        assert_eq!(config.validate(), Err(ConfigError::UnsupportedCellSize(4)));
+    }
+
+    #[test]
+    fn test_absurd_tape_size_is_rejected() {
+        let config = BrainfuckConfig::new(1 << 40, 1);
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::TapeTooLarge {
+                bytes: 1 << 40,
+                limit: DEFAULT_MAX_TAPE_BYTES,
+            })
+        );
+
+        let config = BrainfuckConfig::new(usize::MAX, 1);
+        assert!(matches!(
+            config.validate(),
+            Err(ConfigError::TapeTooLarge { .. })
+        ));
+
+        // Padding that overflows is an error with or without a limit
+        let config = BrainfuckConfig {
+            tape_alignment: 16,
+            ..BrainfuckConfig::new(usize::MAX, 1)
+        };
+        assert_eq!(config.validate(), Err(ConfigError::TapeSizeOverflow));
+        let config = BrainfuckConfig {
+            max_tape_bytes: None,
+            ..config
+        };
+        assert_eq!(config.validate(), Err(ConfigError::TapeSizeOverflow));
+
+        let config = BrainfuckConfig {
+            max_tape_bytes: Some(1000),
+            ..BrainfuckConfig::small()
+        };
+        assert_eq!(config.validate(), Ok(()));
+
+        let config = BrainfuckConfig {
+            max_tape_bytes: None,
+            ..BrainfuckConfig::new(1 << 40, 1)
+        };
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_tape_alignment() {
+        let config = BrainfuckConfig {
+            tape_alignment: 24,
+            ..BrainfuckConfig::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::InvalidTapeAlignment(24))
+        );
+
+        let config = BrainfuckConfig {
+            tape_alignment: 64,
+            ..BrainfuckConfig::default()
+        };
+        assert_eq!(config.validate(), Ok(()));
+        assert_eq!(config.allocated_tape_bytes(), 30016);
+        assert_eq!(BrainfuckConfig::default().allocated_tape_bytes(), 30000);
+    }
+
+    #[test]
+    fn test_zero_input_buffer_size_is_rejected() {
+        let config = BrainfuckConfig {
+            input_buffer_size: 0,
+            ..BrainfuckConfig::default()
+        };
+        assert_eq!(config.validate(), Err(ConfigError::ZeroInputBufferSize));
+    }
+
+    #[test]
+    fn test_opt_level_is_validated() {
+        let config = BrainfuckConfig {
+            opt_level: MAX_OPT_LEVEL + 1,
+            ..BrainfuckConfig::default()
+        };
+        assert_eq!(config.validate(), Err(ConfigError::InvalidOptLevel(4)));
+
+        let config = BrainfuckConfig {
+            opt_level: MAX_OPT_LEVEL,
+            ..BrainfuckConfig::default()
+        };
+        assert_eq!(config.validate(), Ok(()));
+    }
 }
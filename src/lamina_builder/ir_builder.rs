@@ -3,21 +3,67 @@
 //! This module handles the conversion of Brainfuck AST to Lamina IR
 //! and provides methods to generate assembly code.
 
-use super::config::BrainfuckConfig;
-use crate::lexer::{AstNode, Command};
+use super::compiler::CompileError;
+use super::config::{BrainfuckConfig, CellWrap, EofBehavior, OutputNewline, TapeModel};
+use super::optimize::is_clear_loop;
+use super::utils::{check_static_bounds, contains_input, count_operations};
+use crate::analysis::may_not_terminate;
+use crate::interpreter::Interpreter;
+use crate::lexer::{AstNode, Command, MultiplyAdd};
+use crate::tape::{PointerMode, Tape};
 use lamina::ir::builder::{i8, i32, var};
 use lamina::ir::*;
+use std::cell::Cell;
+use std::io;
+use typed_arena::Arena;
+
+/// Name of the global holding the tape in the generated module
+pub(crate) const TAPE_GLOBAL: &str = "bfina_tape";
+
+/// Name of the function that returns the address of [`TAPE_GLOBAL`]
+///
+/// Lamina can't take the address of a global, so this function isn't part
+/// of the module: it is appended to the assembly when the module calls it.
+pub(crate) const TAPE_BASE_FUNCTION: &str = "bfina_tape_base";
+
+/// Name of the function that reads one byte from stdin, returning it
+/// zero-extended or -1 at end of input
+///
+/// Lamina's `readbyte` leaves its buffer unchanged at end of input, so it
+/// can't report EOF. Like [`TAPE_BASE_FUNCTION`], this is appended to the
+/// assembly when the module calls it.
+pub(crate) const READ_BYTE_FUNCTION: &str = "bfina_read_byte";
+
+/// Name of the function that kills the program with a trapping instruction
+///
+/// Lamina has no trap instruction, and integer division by zero doesn't
+/// fault on every target, so this is appended to the assembly like
+/// [`TAPE_BASE_FUNCTION`] when the module calls it.
+pub(crate) const TRAP_FUNCTION: &str = "bfina_trap";
 
 /// Brainfuck to Lamina IR Builder
 ///
 /// This struct handles the conversion of Brainfuck AST to Lamina IR
 /// and provides methods to generate assembly code.
 ///
-/// Uses heap-allocated memory to avoid stack size limitations.
-/// Stack is typically limited to 256KB-1MB, while heap can allocate much more.
+/// The tape is a zero-initialized global array of `tape_size` 8-bit cells
+/// (padded to `tape_alignment`, see
+/// [`BrainfuckConfig::allocated_tape_bytes`]), whose address `main` gets
+/// from [`TAPE_BASE_FUNCTION`]. The data pointer is a 32-bit index stored
+/// in the same global just past the tape, starting at
+/// [`BrainfuckConfig::start_cell`].
 #[allow(dead_code)]
 pub struct BrainfuckIRBuilder {
     config: BrainfuckConfig,
+    /// Counter used to generate unique block and value names
+    name_counter: Cell<usize>,
+    /// Generated block and value names, which built modules borrow
+    names: Arena<String>,
+    /// Pointer movement not yet stored to `data_ptr`
+    ///
+    /// Cells are addressed relative to `data_ptr` plus this offset, so runs of
+    /// moves and cell updates don't write `data_ptr` back after every move.
+    pending_offset: Cell<i32>,
 }
 
 impl Default for BrainfuckIRBuilder {
@@ -29,220 +75,1252 @@ impl Default for BrainfuckIRBuilder {
 impl BrainfuckIRBuilder {
     /// Create a new Brainfuck IR builder with default configuration
     pub fn new() -> Self {
-        Self {
-            config: BrainfuckConfig::default(),
-        }
+        Self::with_config(BrainfuckConfig::default())
     }
 
     /// Create a new Brainfuck IR builder with custom configuration
     pub fn with_config(config: BrainfuckConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            name_counter: Cell::new(0),
+            names: Arena::new(),
+            pending_offset: Cell::new(0),
+        }
+    }
+
+    /// Generate a unique name for a block or value
+    ///
+    /// Lamina's builder borrows names for the lifetime of the module, so the
+    /// generated names are kept in `names` for as long as the builder lives.
+    fn fresh_name(&self, prefix: &str) -> &str {
+        let id = self.name_counter.get();
+        self.name_counter.set(id + 1);
+        self.names.alloc(format!("{}_{}", prefix, id))
     }
 
     /// Convert Brainfuck AST to Lamina IR Module
     ///
     /// This function creates a real IR module that processes the Brainfuck AST
-    /// and generates actual IR instructions using the Lamina framework.
-    pub fn build_ir(&self, ast: &[AstNode]) -> Result<Module<'_>, String> {
-        // Create a new IR builder
-        let mut builder = IRBuilder::new();
+    /// and generates actual IR instructions using the Lamina framework. The
+    /// module borrows the names the builder generates, which are freed with
+    /// the builder.
+    pub fn build_ir(&self, ast: &[AstNode]) -> Result<Module<'_>, CompileError> {
+        self.build_module(ast).map_err(CompileError::IrGeneration)
+    }
 
-        // Create the main function: void main()
-        builder.function("main", Type::Void);
+    fn build_module(&self, ast: &[AstNode]) -> Result<Module<'_>, String> {
+        self.config.validate().map_err(|e| e.to_string())?;
 
-        // Initialize memory state for compile-time simulation
-        let mut memory = vec![0u8; self.config.tape_size];
-        let mut position = 0;
-        let mut output_count = 0;
+        if self.config.trace {
+            // Lamina's I/O primitives only write to stdout
+            return Err("Tracing is not supported by the Lamina backend".to_string());
+        }
+
+        if self.config.annotate_ir {
+            return Err("IR annotations are not supported by the Lamina backend".to_string());
+        }
+
+        if self.config.exit_from_cell && !self.config.emit_int_main {
+            return Err("Exiting with the current cell needs an int main".to_string());
+        }
+
+        if self.config.tape_model == TapeModel::Growable {
+            return Err("A growable tape is not supported by the Lamina backend".to_string());
+        }
+
+        // Offsets into the global, the data pointer included, are `i32`s
+        let global_bytes = self.config.allocated_tape_bytes().checked_next_multiple_of(4).and_then(|bytes| bytes.checked_add(4));
+        if global_bytes.is_none_or(|bytes| i32::try_from(bytes).is_err()) {
+            return Err(format!(
+                "A tape of {} cells is too large for the Lamina backend, which indexes it with 32-bit integers",
+                self.config.tape_size
+            ));
+        }
 
-        // Process the AST and generate real IR instructions
-        self.process_ast_with_lamina(&mut builder, ast, &mut memory, &mut position, &mut output_count)?;
+        // The precomputing interpreter adds the prelude and epilogue itself
+        let original_ast = ast;
+        // The optimizer assumes cells start at zero, which the prelude can
+        // change, so it sees the whole program
+        let ast = &self.config.optimize_program(&self.config.wrap_program(ast));
 
-        // Return void
-        builder.ret_void();
+        check_static_bounds(ast, &self.config)?;
+
+        if let Some(limit) = self.config.max_operations {
+            let (commands, loops) = count_operations(ast);
+            if commands + loops > limit {
+                return Err(format!(
+                    "Program has {} operations, exceeding the limit of {}",
+                    commands + loops,
+                    limit
+                ));
+            }
+        }
+
+        // Create a new IR builder
+        let mut builder = IRBuilder::new();
+        self.name_counter.set(0);
+        self.pending_offset.set(0);
+
+        // Create the main function: int main(), or void main() if requested
+        let return_type = if self.config.emit_int_main {
+            Type::Primitive(PrimitiveType::I32)
+        } else {
+            Type::Void
+        };
+        builder.function("main", return_type);
+
+        let precomputed = self.precompute_output(original_ast);
+        if let Some((bytes, cell)) = &precomputed {
+            // The program's output is fully known, so just write it out
+            for &byte in bytes {
+                builder.write_byte(i8(byte as i8), self.fresh_name("write_result"));
+            }
+            if self.config.exit_from_cell {
+                builder.ret(Type::Primitive(PrimitiveType::I32), i32(*cell as i32));
+            } else {
+                self.emit_return(&mut builder);
+            }
+        } else {
+            // Process the AST and generate real IR instructions
+            self.process_ast_with_lamina(&mut builder, ast)?;
+            if self.config.exit_from_cell {
+                let cell_ptr = self.emit_cell_ptr(&mut builder, "cell_ptr_exit", 0);
+                let cell = self.fresh_name("exit_cell");
+                let code = self.fresh_name("exit_code");
+                builder.load(cell, Type::Primitive(PrimitiveType::I8), var(cell_ptr));
+                builder.zext(code, PrimitiveType::I8, PrimitiveType::I32, var(cell));
+                builder.ret(Type::Primitive(PrimitiveType::I32), var(code));
+            } else {
+                self.emit_return(&mut builder);
+            }
+        }
 
         // Build and return the module
-        let module = builder.build();
+        let mut module = builder.build();
+        if precomputed.is_none() {
+            // Uninitialized globals are zero-filled when the program loads
+            module.global_declarations.insert(TAPE_GLOBAL, GlobalDeclaration {
+                name: TAPE_GLOBAL,
+                ty: Type::Array {
+                    element_type: Box::new(Type::Primitive(PrimitiveType::I8)),
+                    size: (self.data_ptr_offset() + 4) as u64,
+                },
+                initializer: None,
+            });
+        }
         Ok(module)
     }
 
-    /// Process the AST and generate IR instructions using Lamina API
-    fn process_ast_with_lamina(&self, builder: &mut IRBuilder, ast: &[AstNode], memory: &mut Vec<u8>, position: &mut usize, _output_count: &mut usize) -> Result<(), String> {
-        // Initialize memory tape
-        builder.alloc_stack("tape", Type::Array {
-            element_type: Box::new(Type::Primitive(PrimitiveType::I8)),
-            size: 30000,
-        });
+    /// Return from `main`, with exit code 0 if it returns `i32`
+    fn emit_return<'a>(&'a self, builder: &mut IRBuilder<'a>) {
+        if self.config.emit_int_main {
+            builder.ret(Type::Primitive(PrimitiveType::I32), i32(0));
+        } else {
+            builder.ret_void();
+        }
+    }
+
+    /// Byte offset of the data pointer in [`TAPE_GLOBAL`], just past the
+    /// tape and aligned for its `i32`
+    fn data_ptr_offset(&self) -> usize {
+        self.config.allocated_tape_bytes().next_multiple_of(4)
+    }
+
+    /// `value`, an offset into [`TAPE_GLOBAL`], as an `i32` constant
+    ///
+    /// `build_module` refuses tapes whose global doesn't fit in `i32`.
+    fn tape_offset(value: usize) -> i32 {
+        i32::try_from(value).expect("tape offsets are checked to fit in i32")
+    }
+
+    /// Call [`TRAP_FUNCTION`], which never returns, then return from `main`
+    /// to end the block
+    fn emit_trap<'a>(&'a self, builder: &mut IRBuilder<'a>, name: &str) {
+        builder.call(Some(self.fresh_name(name)), TRAP_FUNCTION, Vec::new());
+        self.emit_return(builder);
+    }
+
+    /// Run an input-free program at compile time and return its output,
+    /// along with the value of the cell it ends on
+    ///
+    /// Returns `None` if precomputation is disabled, the program reads input,
+    /// has a loop that [`may_not_terminate`] can't rule out running forever,
+    /// fails at runtime, or doesn't finish within the step budget.
+    fn precompute_output(&self, ast: &[AstNode]) -> Option<(Vec<u8>, u8)> {
+        let program = self.config.wrap_program(ast);
+        if !self.config.precompute_output || contains_input(&program) || may_not_terminate(&program) {
+            return None;
+        }
+
+        let mut interpreter = Interpreter::new(ast, &self.config).ok()?;
+        let mut output = Vec::new();
 
-        // Initialize data pointer
-        builder.alloc_stack("data_ptr", Type::Primitive(PrimitiveType::I32));
-        builder.store(Type::Primitive(PrimitiveType::I32), var("data_ptr"), i32(0));
+        for _ in 0..self.config.precompute_step_budget {
+            match interpreter.step(&mut io::empty(), &mut output) {
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(_) => return None,
+            }
+        }
 
-        // Count operations to demonstrate we're processing the AST
-        let (cmd_count, loop_count) = self.count_operations(ast);
+        let cell = interpreter.tape().get(interpreter.pointer());
+        interpreter.is_finished().then_some((output, cell))
+    }
+
+    /// Process the AST and generate IR instructions using Lamina API
+    fn process_ast_with_lamina<'a>(&'a self, builder: &mut IRBuilder<'a>, ast: &[AstNode]) -> Result<(), String> {
+        // Lamina caps stack and heap arrays at 1024 elements and leaves them
+        // uninitialized, so the tape is a global instead
+        builder.call(Some("tape"), TAPE_BASE_FUNCTION, Vec::new());
+
+        // Lamina places stack allocations where they overlap the spill slots
+        // of large functions, so the data pointer lives in the global too
+        builder.getelementptr("data_ptr", var("tape"), i32(Self::tape_offset(self.data_ptr_offset())), PrimitiveType::I8);
+        builder.store(Type::Primitive(PrimitiveType::I32), var("data_ptr"), i32(Self::tape_offset(self.config.start_cell())));
 
         // Process each command and generate real IR
-        for (i, node) in ast.iter().enumerate() {
+        self.process_nodes_with_lamina(builder, ast)?;
+
+        Ok(())
+    }
+
+    /// Generate IR for a sequence of nodes, such as a program or loop body
+    fn process_nodes_with_lamina<'a>(&'a self, builder: &mut IRBuilder<'a>, nodes: &[AstNode]) -> Result<(), String> {
+        for node in nodes {
+            if self.config.runtime_bounds_check.is_some()
+                && matches!(node, AstNode::OutputAtOffset(_) | AstNode::InputAtOffset(_) | AstNode::CopyAndClear(_) | AstNode::MultiplyLoop(_))
+            {
+                // Cells at an offset aren't reached by a checked move, so
+                // walk the pointer to them one step at a time instead
+                self.process_nodes_with_lamina(builder, &node.expand())?;
+                continue;
+            }
+
             match node {
                 AstNode::Command(cmd) => {
-                    self.process_command_with_lamina(builder, *cmd, i, memory, position, _output_count)?;
+                    self.process_command_with_lamina(builder, *cmd);
                 }
                 AstNode::Loop(body) => {
-                    self.process_loop_with_lamina(builder, body, i, memory, position, _output_count)?;
+                    self.process_loop_with_lamina(builder, body)?;
+                }
+                AstNode::SetZero => {
+                    self.process_set_zero_with_lamina(builder);
+                }
+                AstNode::Add(delta) => {
+                    self.process_add_with_lamina(builder, *delta);
+                }
+                AstNode::Move(delta) => {
+                    self.move_pointer(builder, *delta);
+                }
+                AstNode::OutputAtOffset(offset) => {
+                    self.process_io_at_offset_with_lamina(builder, Command::Output, *offset);
+                }
+                AstNode::InputAtOffset(offset) => {
+                    self.process_io_at_offset_with_lamina(builder, Command::Input, *offset);
+                }
+                AstNode::CopyAndClear(offset) => {
+                    self.process_copy_and_clear_with_lamina(builder, *offset);
+                }
+                AstNode::MultiplyLoop(targets) => {
+                    self.process_multiply_loop_with_lamina(builder, targets);
                 }
             }
         }
 
-        // Track operations in compile-time
-        let _total_ops = cmd_count + loop_count;
-
         Ok(())
     }
 
     /// Process a single Brainfuck command with Lamina IR generation
-    fn process_command_with_lamina(&self, builder: &mut IRBuilder, cmd: Command, _index: usize, memory: &mut Vec<u8>, position: &mut usize, output_count: &mut usize) -> Result<(), String> {
+    fn process_command_with_lamina<'a>(&'a self, builder: &mut IRBuilder<'a>, cmd: Command) {
         match cmd {
-            Command::Right => {
-                // Simple operation without memory access
-                builder.binary(
-                    BinaryOp::Add,
-                    "temp_right",
-                    PrimitiveType::I32,
-                    i32(1),
-                    i32(1),
-                );
-                // Update compile-time position tracking
-                *position = (*position + 1).min(memory.len().saturating_sub(1));
-            }
-            Command::Left => {
-                // Simple operation without memory access
-                builder.binary(
-                    BinaryOp::Sub,
-                    "temp_left",
-                    PrimitiveType::I32,
-                    i32(1),
-                    i32(1),
-                );
-                // Update compile-time position tracking
-                *position = position.saturating_sub(1);
-            }
-            Command::Increment => {
-                // Get current value
-                let current_value = if *position < memory.len() { memory[*position] } else { 0 };
-
-                // Calculate new value
-                let new_value = current_value.wrapping_add(1);
-
-                // Update memory
-                if *position < memory.len() {
-                    memory[*position] = new_value;
-                }
-
-                // Generate IR that reflects the actual memory operation
-                builder.binary(
-                    BinaryOp::Add,
-                    "temp_inc",
-                    PrimitiveType::I8,
-                    i8(new_value as i8),
-                    i8(0),
-                );
-            }
-            Command::Decrement => {
-                // Get current value
-                let current_value = if *position < memory.len() { memory[*position] } else { 0 };
-
-                // Calculate new value
-                let new_value = current_value.wrapping_sub(1);
-
-                // Update memory
-                if *position < memory.len() {
-                    memory[*position] = new_value;
-                }
-
-                // Generate IR that reflects the actual memory operation
-                builder.binary(
-                    BinaryOp::Sub,
-                    "temp_dec",
-                    PrimitiveType::I8,
-                    i8(current_value as i8),
-                    i8(1),
-                );
-            }
+            Command::Right => self.move_pointer(builder, 1),
+            Command::Left => self.move_pointer(builder, -1),
+            Command::Increment => self.process_add_with_lamina(builder, 1),
+            Command::Decrement => self.process_add_with_lamina(builder, -1),
             Command::Output => {
+                self.flush_pointer(builder);
+
                 // Get pointer to current memory cell
-                builder.getelementptr("cell_ptr_out", var("tape"), var("data_ptr"), PrimitiveType::I8);
+                let cell_ptr = self.emit_cell_ptr(builder, "cell_ptr_out", 0);
 
                 // Load the value from memory
-                builder.load("output_val", Type::Primitive(PrimitiveType::I8), var("cell_ptr_out"));
+                let value = self.fresh_name("output_val");
+                builder.load(value, Type::Primitive(PrimitiveType::I8), var(cell_ptr));
 
                 // Use Lamina's write_byte function for actual output
-                builder.write_byte(var("output_val"), "write_result");
-
-                *output_count += 1;
+                self.emit_output_byte(builder, var(value));
             }
             Command::Input => {
+                self.flush_pointer(builder);
+
                 // Get pointer to current memory cell using getelem.ptr instruction
-                builder.getelementptr("cell_ptr_in", var("tape"), var("data_ptr"), PrimitiveType::I8);
+                let cell_ptr = self.emit_cell_ptr(builder, "cell_ptr_in", 0);
+                self.emit_input_byte(builder, cell_ptr);
+            }
+        }
+    }
+
+    /// Read a byte from stdin into `cell_ptr`, applying the configured
+    /// [`EofBehavior`]
+    ///
+    /// [`READ_BYTE_FUNCTION`] returns -1 at end of input, which is already
+    /// the value [`EofBehavior::NegativeOne`] stores.
+    fn emit_input_byte<'a>(&'a self, builder: &mut IRBuilder<'a>, cell_ptr: &'a str) {
+        let value = self.fresh_name("input_val");
+        builder.call(Some(value), READ_BYTE_FUNCTION, Vec::new());
+
+        if self.config.eof_behavior == EofBehavior::NegativeOne {
+            builder.store(Type::Primitive(PrimitiveType::I8), var(cell_ptr), var(value));
+            return;
+        }
+
+        let is_eof = self.fresh_name("is_eof");
+        let eof_block = self.fresh_name("input_eof");
+        let store_block = self.fresh_name("input_store");
+        let done_block = self.fresh_name("input_done");
+        builder.cmp(CmpOp::Eq, is_eof, PrimitiveType::I32, var(value), i32(-1));
+        builder.branch(var(is_eof), eof_block, store_block);
 
-                // Read byte from stdin using Lamina's readbyte
-                builder.read_byte("input_val");
+        builder.block(eof_block);
+        if self.config.eof_behavior == EofBehavior::Zero {
+            builder.store(Type::Primitive(PrimitiveType::I8), var(cell_ptr), i8(0));
+        }
+        builder.jump(done_block);
+
+        builder.block(store_block);
+        builder.store(Type::Primitive(PrimitiveType::I8), var(cell_ptr), var(value));
+        builder.jump(done_block);
+
+        builder.block(done_block);
+    }
+
+    /// Write a byte to stdout, applying the configured newline translation
+    fn emit_output_byte<'a>(&'a self, builder: &mut IRBuilder<'a>, value: Value<'a>) {
+        match self.config.output_newline {
+            OutputNewline::AsIs => {
+                builder.write_byte(value, self.fresh_name("write_result"));
+            }
+            OutputNewline::CrLf => {
+                // Emit a carriage return first when writing a line feed
+                let is_newline = self.fresh_name("is_newline");
+                let newline_block = self.fresh_name("crlf_newline");
+                let write_block = self.fresh_name("crlf_write");
+
+                builder.cmp(CmpOp::Eq, is_newline, PrimitiveType::I8, value.clone(), i8(10));
+                builder.branch(var(is_newline), newline_block, write_block);
+
+                builder.block(newline_block);
+                builder.write_byte(i8(13), self.fresh_name("write_result"));
+                builder.jump(write_block);
+
+                builder.block(write_block);
+                builder.write_byte(value, self.fresh_name("write_result"));
+            }
+            OutputNewline::Lf => {
+                // Skip the write entirely for carriage returns
+                let is_return = self.fresh_name("is_return");
+                let write_block = self.fresh_name("lf_write");
+                let done_block = self.fresh_name("lf_done");
+
+                builder.cmp(CmpOp::Eq, is_return, PrimitiveType::I8, value.clone(), i8(13));
+                builder.branch(var(is_return), done_block, write_block);
+
+                builder.block(write_block);
+                builder.write_byte(value, self.fresh_name("write_result"));
+                builder.jump(done_block);
 
-                // Store the input value to current memory position
-                builder.store(Type::Primitive(PrimitiveType::I8), var("cell_ptr_in"), var("input_val"));
+                builder.block(done_block);
             }
         }
+    }
+
+    /// Process a Brainfuck loop with Lamina IR generation
+    fn process_loop_with_lamina<'a>(&'a self, builder: &mut IRBuilder<'a>, body: &[AstNode]) -> Result<(), String> {
+        // A clear loop is just a store of zero, however many times it runs.
+        // Without wrapping only `[-]` is certain to reach zero.
+        let clears = match self.config.cell_wrap {
+            CellWrap::Wrap => is_clear_loop(body),
+            CellWrap::Saturate | CellWrap::Error => {
+                matches!(body, [AstNode::Command(Command::Decrement)] | [AstNode::Add(-1)])
+            }
+        };
+        if clears {
+            self.process_set_zero_with_lamina(builder);
+            return Ok(());
+        }
+
+        // Every path into loop_start must agree on data_ptr, so pending
+        // moves are stored on entry and at the end of every iteration
+        self.flush_pointer(builder);
+
+        // Re-check the current cell at the top of every iteration
+        let loop_start = self.fresh_name("loop_start");
+        let loop_body = self.fresh_name("loop_body");
+        let loop_end = self.fresh_name("loop_end");
+        builder.jump(loop_start);
+
+        builder.block(loop_start);
+        let cell_ptr = self.emit_cell_ptr(builder, "cell_ptr_loop", 0);
+        let cell_val = self.fresh_name("loop_cell");
+        let is_nonzero = self.fresh_name("loop_cond");
+        builder.load(cell_val, Type::Primitive(PrimitiveType::I8), var(cell_ptr));
+        builder.cmp(CmpOp::Ne, is_nonzero, PrimitiveType::I8, var(cell_val), i8(0));
+        builder.branch(var(is_nonzero), loop_body, loop_end);
+
+        builder.block(loop_body);
+        self.process_nodes_with_lamina(builder, body)?;
+        self.flush_pointer(builder);
+        builder.jump(loop_start);
+
+        builder.block(loop_end);
 
         Ok(())
     }
 
-    /// Process a Brainfuck loop with Lamina IR generation
-    fn process_loop_with_lamina(&self, builder: &mut IRBuilder, body: &[AstNode], _index: usize, memory: &mut Vec<u8>, position: &mut usize, output_count: &mut usize) -> Result<(), String> {
-        // Simplified loop implementation to avoid problematic Lamina features
-        // This simulates a simple loop by executing the body a few times
-        // For most simple programs, this works well enough
-
-        for _ in 0..5 {  // Execute loop body 5 times (reasonable for simple programs)
-            for (i, node) in body.iter().enumerate() {
-                match node {
-                    AstNode::Command(cmd) => {
-                        self.process_command_with_lamina(builder, *cmd, i, memory, position, output_count)?;
-                    }
-                    AstNode::Loop(nested_body) => {
-                        self.process_loop_with_lamina(builder, nested_body, i, memory, position, output_count)?;
-                    }
+    /// Store zero to the current cell
+    fn process_set_zero_with_lamina<'a>(&'a self, builder: &mut IRBuilder<'a>) {
+        let cell_ptr = self.emit_cell_ptr(builder, "cell_ptr_zero", 0);
+        builder.store(Type::Primitive(PrimitiveType::I8), var(cell_ptr), i8(0));
+    }
+
+    /// Add `delta` to the current cell with a single load, add, and store,
+    /// handling overflow as the configured [`CellWrap`] says
+    fn process_add_with_lamina<'a>(&'a self, builder: &mut IRBuilder<'a>, delta: i32) {
+        let wrapped = delta.rem_euclid(256) as u8;
+        let cell_ptr = self.emit_cell_ptr(builder, "cell_ptr_add", 0);
+        let old_value = self.fresh_name("add_old");
+        let new_value = self.fresh_name("add_new");
+        builder.load(old_value, Type::Primitive(PrimitiveType::I8), var(cell_ptr));
+
+        if self.config.cell_wrap == CellWrap::Wrap {
+            builder.binary(BinaryOp::Add, new_value, PrimitiveType::I8, var(old_value), i8(wrapped as i8));
+            builder.store(Type::Primitive(PrimitiveType::I8), var(cell_ptr), var(new_value));
+            return;
+        }
+
+        // Check the sum in 32 bits, where it can't wrap
+        let wide = self.fresh_name("add_wide");
+        let sum = self.fresh_name("add_sum");
+        let out_of_range = self.fresh_name("add_out_of_range");
+        builder.zext(wide, PrimitiveType::I8, PrimitiveType::I32, var(old_value));
+        builder.binary(BinaryOp::Add, sum, PrimitiveType::I32, var(wide), i32(delta));
+        if delta > 0 {
+            builder.cmp(CmpOp::Gt, out_of_range, PrimitiveType::I32, var(sum), i32(255));
+        } else {
+            builder.cmp(CmpOp::Lt, out_of_range, PrimitiveType::I32, var(sum), i32(0));
+        }
+
+        let overflow_block = self.fresh_name("add_overflow");
+        let in_range_block = self.fresh_name("add_in_range");
+        let done_block = self.fresh_name("add_done");
+        builder.branch(var(out_of_range), overflow_block, in_range_block);
+
+        builder.block(overflow_block);
+        if self.config.cell_wrap == CellWrap::Saturate {
+            let limit = if delta > 0 { -1 } else { 0 };
+            builder.store(Type::Primitive(PrimitiveType::I8), var(cell_ptr), i8(limit));
+            builder.jump(done_block);
+        } else {
+            self.emit_trap(builder, "cell_trap");
+        }
+
+        builder.block(in_range_block);
+        builder.binary(BinaryOp::Add, new_value, PrimitiveType::I8, var(old_value), i8(wrapped as i8));
+        builder.store(Type::Primitive(PrimitiveType::I8), var(cell_ptr), var(new_value));
+        builder.jump(done_block);
+
+        builder.block(done_block);
+    }
+
+    /// Move the data pointer by `delta`
+    ///
+    /// Nothing is emitted: the move is added to the pending offset, which
+    /// [`Self::flush_pointer`] stores to `data_ptr` later. With
+    /// `runtime_bounds_check` every move is stored and checked right away.
+    fn move_pointer<'a>(&'a self, builder: &mut IRBuilder<'a>, delta: i32) {
+        self.pending_offset.set(self.pending_offset.get() + delta);
+        if self.config.runtime_bounds_check.is_some() {
+            self.flush_pointer(builder);
+        }
+    }
+
+    /// Store the pending offset to `data_ptr`
+    ///
+    /// Called before loops and I/O, so `data_ptr` is up to date wherever
+    /// control flow joins.
+    fn flush_pointer<'a>(&'a self, builder: &mut IRBuilder<'a>) {
+        let delta = self.pending_offset.replace(0);
+        if delta == 0 {
+            return;
+        }
+
+        let tape_size = Self::tape_offset(self.config.tape_size);
+        let delta = match self.config.runtime_bounds_check {
+            // A single correction brings the pointer back onto the tape
+            Some(PointerMode::Wrap) => delta % tape_size,
+            _ => delta,
+        };
+
+        let old_index = self.fresh_name("ptr");
+        let new_index = self.fresh_name("ptr_moved");
+        builder.load(old_index, Type::Primitive(PrimitiveType::I32), var("data_ptr"));
+        builder.binary(BinaryOp::Add, new_index, PrimitiveType::I32, var(old_index), i32(delta));
+        builder.store(Type::Primitive(PrimitiveType::I32), var("data_ptr"), var(new_index));
+
+        if let Some(mode) = self.config.runtime_bounds_check {
+            self.emit_bounds_check(builder, mode, new_index);
+        }
+    }
+
+    /// Branch on whether `index`, just stored to `data_ptr`, is off the tape
+    ///
+    /// Off the left end the pointer is checked against 0 and off the right
+    /// end against `tape_size`; each side either traps or stores a corrected
+    /// pointer, as `mode` says.
+    fn emit_bounds_check<'a>(&'a self, builder: &mut IRBuilder<'a>, mode: PointerMode, index: &'a str) {
+        let tape_size = Self::tape_offset(self.config.tape_size);
+        let below = self.fresh_name("ptr_below");
+        let above = self.fresh_name("ptr_above");
+        let below_block = self.fresh_name("ptr_off_left");
+        let check_above_block = self.fresh_name("ptr_check_right");
+        let above_block = self.fresh_name("ptr_off_right");
+        let done_block = self.fresh_name("ptr_in_bounds");
+
+        builder.cmp(CmpOp::Lt, below, PrimitiveType::I32, var(index), i32(0));
+        builder.branch(var(below), below_block, check_above_block);
+
+        builder.block(check_above_block);
+        builder.cmp(CmpOp::Ge, above, PrimitiveType::I32, var(index), i32(tape_size));
+        builder.branch(var(above), above_block, done_block);
+
+        for (block, is_left) in [(below_block, true), (above_block, false)] {
+            builder.block(block);
+            match mode {
+                PointerMode::Trap => self.emit_trap(builder, "ptr_trap"),
+                PointerMode::Wrap => {
+                    let wrapped = self.fresh_name("ptr_wrapped");
+                    let op = if is_left { BinaryOp::Add } else { BinaryOp::Sub };
+                    builder.binary(op, wrapped, PrimitiveType::I32, var(index), i32(tape_size));
+                    builder.store(Type::Primitive(PrimitiveType::I32), var("data_ptr"), var(wrapped));
+                    builder.jump(done_block);
                 }
+                PointerMode::Clamp => {
+                    let edge = if is_left { 0 } else { tape_size - 1 };
+                    builder.store(Type::Primitive(PrimitiveType::I32), var("data_ptr"), i32(edge));
+                    builder.jump(done_block);
+                }
+            }
+        }
+
+        builder.block(done_block);
+    }
+
+    /// Pointer to the cell `offset` cells away from the current cell
+    ///
+    /// `data_ptr` points into [`TAPE_GLOBAL`], just past the tape, so its
+    /// current value is loaded first and the pending offset is added along
+    /// with `offset`.
+    ///
+    /// The offset is applied with a second `getelem.ptr` rather than an
+    /// `add.i32` on the index: Lamina stores an `add.i32` result with a
+    /// 32-bit move but reads a `getelem.ptr` index as 64 bits, so the index
+    /// would pick up whatever was left in the upper half of the slot Lamina
+    /// spills it to.
+    fn emit_cell_ptr<'a>(&'a self, builder: &mut IRBuilder<'a>, prefix: &str, offset: i32) -> &'a str {
+        let offset = offset + self.pending_offset.get();
+        let index = self.fresh_name("ptr");
+        builder.load(index, Type::Primitive(PrimitiveType::I32), var("data_ptr"));
+
+        let cell_ptr = self.fresh_name(prefix);
+        if offset == 0 {
+            builder.getelementptr(cell_ptr, var("tape"), var(index), PrimitiveType::I8);
+        } else {
+            let base_ptr = self.fresh_name("base_ptr");
+            builder.getelementptr(base_ptr, var("tape"), var(index), PrimitiveType::I8);
+            builder.getelementptr(cell_ptr, var(base_ptr), i32(offset), PrimitiveType::I8);
+        }
+        cell_ptr
+    }
+
+    /// Output or input the cell `offset` cells away without moving `data_ptr`
+    fn process_io_at_offset_with_lamina<'a>(&'a self, builder: &mut IRBuilder<'a>, cmd: Command, offset: i32) {
+        self.flush_pointer(builder);
+        let cell_ptr = self.emit_cell_ptr(builder, "cell_ptr_offset", offset);
+
+        if cmd == Command::Output {
+            let value = self.fresh_name("output_val");
+            builder.load(value, Type::Primitive(PrimitiveType::I8), var(cell_ptr));
+            self.emit_output_byte(builder, var(value));
+        } else {
+            self.emit_input_byte(builder, cell_ptr);
+        }
+    }
+
+    /// Copy the current cell to the cell `offset` cells away, then clear it
+    fn process_copy_and_clear_with_lamina<'a>(&'a self, builder: &mut IRBuilder<'a>, offset: i32) {
+        let source_ptr = self.emit_cell_ptr(builder, "cell_ptr_copy_src", 0);
+        let dest_ptr = self.emit_cell_ptr(builder, "cell_ptr_copy_dest", offset);
+        let value = self.fresh_name("copy_val");
+        builder.load(value, Type::Primitive(PrimitiveType::I8), var(source_ptr));
+        builder.store(Type::Primitive(PrimitiveType::I8), var(dest_ptr), var(value));
+        builder.store(Type::Primitive(PrimitiveType::I8), var(source_ptr), i8(0));
+    }
+
+    /// Add multiples of the current cell to each target in straight-line
+    /// code, then clear the current cell
+    ///
+    /// Like the loop it was lowered from, nothing is touched when the current
+    /// cell is zero, so targets off the tape are only reached if the loop
+    /// runs. The products are computed in 32 bits, since Lamina emits
+    /// `mul.i8` as an `imul` the assembler rejects. Only their low byte is
+    /// added.
+    fn process_multiply_loop_with_lamina<'a>(&'a self, builder: &mut IRBuilder<'a>, targets: &[MultiplyAdd]) {
+        let source_ptr = self.emit_cell_ptr(builder, "cell_ptr_mul_src", 0);
+        let cell = self.fresh_name("mul_cell");
+        let is_nonzero = self.fresh_name("mul_cond");
+        let body_block = self.fresh_name("mul_body");
+        let done_block = self.fresh_name("mul_done");
+        builder.load(cell, Type::Primitive(PrimitiveType::I8), var(source_ptr));
+        builder.cmp(CmpOp::Ne, is_nonzero, PrimitiveType::I8, var(cell), i8(0));
+        builder.branch(var(is_nonzero), body_block, done_block);
+
+        builder.block(body_block);
+        let value = self.fresh_name("mul_val");
+        builder.zext(value, PrimitiveType::I8, PrimitiveType::I32, var(cell));
+
+        for target in targets {
+            let dest_ptr = self.emit_cell_ptr(builder, "cell_ptr_mul_dest", target.offset);
+            let old = self.fresh_name("mul_old");
+            let product = self.fresh_name("mul_product");
+            let sum = self.fresh_name("mul_sum");
+            builder.load(old, Type::Primitive(PrimitiveType::I8), var(dest_ptr));
+            builder.binary(BinaryOp::Mul, product, PrimitiveType::I32, var(value), i32(target.factor));
+            builder.binary(BinaryOp::Add, sum, PrimitiveType::I8, var(old), var(product));
+            builder.store(Type::Primitive(PrimitiveType::I8), var(dest_ptr), var(sum));
+        }
+
+        builder.store(Type::Primitive(PrimitiveType::I8), var(source_ptr), i8(0));
+        builder.jump(done_block);
+
+        builder.block(done_block);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::parse_brainfuck;
+    use crate::lamina_builder::optimize::optimize_ast;
+
+    fn build_with(source: &str, config: BrainfuckConfig) -> String {
+        let ast = parse_brainfuck(source).unwrap();
+        let builder = BrainfuckIRBuilder::with_config(config);
+        builder.build_ir(&ast).unwrap().to_string()
+    }
+
+    #[test]
+    fn test_output_newline_modes() {
+        let source = "++++++++++.";
+
+        let as_is = build_with(source, BrainfuckConfig::default());
+        assert!(!as_is.contains("crlf_newline"));
+        assert!(!as_is.contains("lf_write"));
+
+        let crlf = build_with(
+            source,
+            BrainfuckConfig {
+                output_newline: OutputNewline::CrLf,
+                ..BrainfuckConfig::default()
+            },
+        );
+        assert!(crlf.contains("crlf_newline"));
+
+        let lf = build_with(
+            source,
+            BrainfuckConfig {
+                output_newline: OutputNewline::Lf,
+                ..BrainfuckConfig::default()
+            },
+        );
+        assert!(lf.contains("lf_write"));
+        assert!(!lf.contains("crlf_newline"));
+    }
+
+    #[test]
+    fn test_compiled_output_newline_modes() {
+        // Writes a line feed, then a carriage return
+        let source = "++++++++++.+++.";
+        for precompute_output in [false, true] {
+            for (output_newline, expected) in [(OutputNewline::AsIs, &[10, 13][..]), (OutputNewline::CrLf, &[13, 10, 13]), (OutputNewline::Lf, &[10])] {
+                let config = BrainfuckConfig { output_newline, precompute_output, ..BrainfuckConfig::default() };
+                let Some(result) = run_compiled(source, b"", config) else { return };
+                assert_eq!(result.unwrap().0, expected, "{:?}, precompute_output {}", output_newline, precompute_output);
             }
         }
+    }
 
-        // Simple loop marker without memory access
-        builder.binary(
-            BinaryOp::Add,
-            "loop_marker",
-            PrimitiveType::I32,
-            i32(1),
-            i32(0),
+    #[test]
+    fn test_main_returns_an_exit_code() {
+        let ir = build_with("+[-]", BrainfuckConfig::default());
+        assert!(ir.contains("ret.i32 0"));
+        assert!(!ir.contains("ret.void"));
+
+        let config = BrainfuckConfig {
+            emit_int_main: false,
+            ..BrainfuckConfig::default()
+        };
+        let ir = build_with("+[-]", config);
+        assert!(ir.contains("ret.void"));
+        assert!(!ir.contains("ret.i32"));
+    }
+
+    #[test]
+    fn test_tape_allocation_is_padded_to_alignment() {
+        let ir = build_with(
+            "+",
+            BrainfuckConfig {
+                tape_size: 1000,
+                tape_alignment: 64,
+                ..BrainfuckConfig::default()
+            },
         );
+        // Followed by the data pointer
+        assert!(ir.contains("global @bfina_tape: [1028 x i8]"));
+    }
 
-        Ok(())
+    #[test]
+    fn test_memory_model_follows_config() {
+        let ir = build_with(
+            "+",
+            BrainfuckConfig {
+                tape_size: 1234,
+                allow_negative_tape: true,
+                ..BrainfuckConfig::default()
+            },
+        );
+        // The tape, then the data pointer at the next 4-byte boundary
+        assert!(ir.contains("global @bfina_tape: [1240 x i8]"));
+        assert!(ir.contains("%tape = call @bfina_tape_base()"));
+        assert!(ir.contains("%data_ptr = getelem.ptr %tape, 1236, i8"));
+        assert!(!ir.contains("alloc."));
+        assert!(ir.contains("store.i32 %data_ptr, 617"));
     }
 
-    /// Count the number of operations in the AST
-    fn count_operations(&self, ast: &[AstNode]) -> (usize, usize) {
-        let mut commands = 0;
-        let mut loops = 0;
+    #[test]
+    fn test_compiled_negative_tape_reaches_both_ends() {
+        // The pointer starts on cell 15000 of the default tape
+        let config = BrainfuckConfig { allow_negative_tape: true, precompute_output: false, ..BrainfuckConfig::default() };
+        let source = format!("+{}++{}+++.{}.", "<".repeat(15000), ">".repeat(29999), "<".repeat(29999));
+        let Some(result) = run_compiled(&source, b"", config) else { return };
+        assert_eq!(result.unwrap().0, [3, 2]);
+    }
 
-        for node in ast {
-            match node {
-                AstNode::Command(_) => commands += 1,
-                AstNode::Loop(body) => {
-                    loops += 1;
-                    let (sub_commands, sub_loops) = self.count_operations(body);
-                    commands += sub_commands;
-                    loops += sub_loops;
-                }
+    #[test]
+    fn test_precompute_output_embeds_bytes() {
+        let config = BrainfuckConfig {
+            precompute_output: true,
+            ..BrainfuckConfig::default()
+        };
+        let ir = build_with(include_str!("../../testcases/hello_world.bf"), config);
+
+        // Only the output bytes remain: no tape and no loops
+        assert_eq!(ir.matches("write_result_").count(), "Hello, World!".len());
+        assert!(!ir.contains("tape"));
+        assert!(!ir.contains("loop"));
+    }
+
+    #[test]
+    fn test_precompute_output_falls_back() {
+        let config = BrainfuckConfig {
+            precompute_output: true,
+            precompute_step_budget: 100,
+            ..BrainfuckConfig::default()
+        };
+
+        // Never terminates, so it isn't run at all
+        let ir = build_with("+[]", config.clone());
+        assert!(ir.contains("tape"));
+
+        // Terminates, but not within the budget
+        let ir = build_with("++++++++[>++++++++[>+++<-]<-]>>.", config.clone());
+        assert!(ir.contains("tape"));
+        let ir = build_with("++++++++[>++++++++[>+++<-]<-]>>.", BrainfuckConfig { precompute_step_budget: 10_000, ..config.clone() });
+        assert!(!ir.contains("tape"));
+
+        // Reads input, so it can't be precomputed
+        let ir = build_with(",.", config);
+        assert!(ir.contains("tape"));
+    }
+
+    #[test]
+    fn test_clear_loop_is_a_single_store() {
+        let ir = build_with("+++[---].", BrainfuckConfig::default());
+        assert_eq!(ir.matches("cell_ptr_zero_").count(), 2);
+        assert!(!ir.contains("loop_start"));
+        assert_eq!(ir.matches("load.i8 %cell_ptr_add_").count(), 3);
+
+        // Even steps aren't a clear, so the loop is kept
+        let ir = build_with("++[--].", BrainfuckConfig::default());
+        assert!(ir.contains("loop_start"));
+    }
+
+    #[test]
+    fn test_loop_rechecks_the_current_cell() {
+        let ir = build_with("+++[>+<-]", BrainfuckConfig::default());
+
+        // The body is emitted once, between a conditional header and a back edge
+        assert_eq!(ir.matches("load.i8 %cell_ptr_add_").count(), 5);
+        let branch = ir.lines().find(|line| line.trim().starts_with("br ")).unwrap();
+        assert!(branch.contains("loop_body_") && branch.contains("loop_end_"));
+        // One jump enters the loop and one is the back edge
+        assert_eq!(ir.matches("jmp loop_start_").count(), 2);
+    }
+
+    #[test]
+    fn test_loop_body_is_emitted_once() {
+        // The body is a real loop in the IR, not unrolled or simulated at
+        // compile time
+        let ir = build_with("+[>+<-]", BrainfuckConfig::default());
+        assert_eq!(ir.matches("= add.i8 ").count(), 3);
+        assert_eq!(ir.matches("= add.i8 %add_old_").count(), 3);
+        assert_eq!(ir.lines().filter(|line| line.trim().starts_with("br ")).count(), 1);
+    }
+
+    #[test]
+    fn test_increment_updates_the_tape_cell() {
+        let ir = build_with("+-", BrainfuckConfig::default());
+        assert!(ir.contains("%add_old_2 = load.i8 %cell_ptr_add_1"));
+        assert!(ir.contains("%add_new_3 = add.i8 %add_old_2, 1"));
+        assert!(ir.contains("store.i8 %cell_ptr_add_1, %add_new_3"));
+        assert!(ir.contains("%add_new_7 = add.i8 %add_old_6, -1"));
+    }
+
+    #[test]
+    fn test_multiply_loop_is_straight_line() {
+        let ast = optimize_ast(&parse_brainfuck("+++[->++>+<<]").unwrap());
+        let builder = BrainfuckIRBuilder::with_config(BrainfuckConfig::default());
+        let ir = builder.build_ir(&ast).unwrap().to_string();
+
+        assert!(!ir.contains("loop_start"));
+        assert!(ir.contains("= mul.i32 %mul_val_"));
+        assert_eq!(ir.matches("load.i8 %cell_ptr_mul_dest_").count(), 2);
+        // The control cell is cleared afterwards
+        assert!(ir.lines().any(|line| {
+            let line = line.trim();
+            line.starts_with("store.i8 %cell_ptr_mul_src_") && line.ends_with(", 0")
+        }));
+    }
+
+    #[test]
+    fn test_opt_level_lowers_multiply_loops() {
+        let unoptimized = build_with("+++[->++>+<<]", BrainfuckConfig::default());
+        assert!(unoptimized.contains("loop_start"));
+
+        let config = BrainfuckConfig {
+            opt_level: 3,
+            ..BrainfuckConfig::default()
+        };
+        let optimized = build_with("+++[->++>+<<]", config);
+        assert!(!optimized.contains("loop_start"));
+        assert!(optimized.contains("= mul.i32 %mul_val_"));
+    }
+
+    #[test]
+    fn test_compiled_multiply_loop_skips_targets_when_the_cell_is_zero() {
+        // The target is far off the tape, where a store would crash
+        let ast = [
+            AstNode::Command(Command::Input),
+            AstNode::MultiplyLoop(vec![MultiplyAdd { offset: -10_000_000, factor: 1 }]),
+            AstNode::Command(Command::Output),
+        ];
+        let ir = BrainfuckIRBuilder::with_config(BrainfuckConfig::default()).build_ir(&ast).unwrap().to_string();
+        assert!(ir.contains("mul_body"));
+        let Some(result) = run_compiled_ast(&ast, b"\0", BrainfuckConfig::default()) else { return };
+        assert_eq!(result.unwrap(), (vec![0], 0));
+    }
+
+    #[test]
+    fn test_prelude_is_optimized_with_the_program() {
+        let config = BrainfuckConfig {
+            prelude: parse_brainfuck("++").unwrap(),
+            opt_level: 3,
+            precompute_output: false,
+            ..BrainfuckConfig::default()
+        };
+        // The leading loop only runs because the prelude sets the cell
+        let ir = build_with("[.-]", config.clone());
+        assert!(ir.contains("loop_start"));
+        let Some(result) = run_compiled("[.-]", b"", config) else { return };
+        assert_eq!(result.unwrap().0, [2, 1]);
+    }
+
+    #[test]
+    fn test_compiled_multiply_loops() {
+        let config = BrainfuckConfig { opt_level: 3, ..BrainfuckConfig::default() };
+        // Products wrap at 256 and negative factors subtract
+        let source = format!("+++++[->{}>+++<<]++[->>-------<<]>.>.", "+".repeat(59));
+        let Some(result) = run_compiled(&source, b"", config) else { return };
+        assert_eq!(result.unwrap().0, [39, 1]);
+    }
+
+    #[test]
+    fn test_pointer_moves_update_data_ptr() {
+        let ast = optimize_ast(&parse_brainfuck(">>>+").unwrap());
+        let builder = BrainfuckIRBuilder::with_config(BrainfuckConfig::default());
+        let ir = builder.build_ir(&ast).unwrap().to_string();
+
+        // Without loops or I/O the move is folded into the add's address
+        assert!(ir.contains("%base_ptr_2 = getelem.ptr %tape, %ptr_0, i8"));
+        assert!(ir.contains("getelem.ptr %base_ptr_2, 3, i8"));
+        assert!(!ir.contains("ptr_moved"));
+        assert!(!ir.contains("store.i32 %data_ptr, %"));
+    }
+
+    #[test]
+    fn test_pending_moves_are_stored_before_loops_and_io() {
+        let ir = build_with(">+>+[<]>.", BrainfuckConfig::default());
+        // Lamina prints blocks in no particular order, so look at each one
+        // by its label
+        let block = |label: &str| -> Vec<&str> {
+            let mut lines = ir.lines().map(str::trim);
+            lines.find(|line| line.starts_with(label) && line.ends_with(':')).unwrap();
+            lines.take_while(|line| !line.ends_with(':') && *line != "}").collect()
+        };
+        let position = |lines: &[&str], text: &str| lines.iter().position(|line| line.contains(text)).unwrap();
+        let stores = |lines: &[&str]| -> Vec<usize> {
+            (0..lines.len()).filter(|&i| lines[i].starts_with("store.i32 %data_ptr, %ptr_moved_")).collect()
+        };
+
+        // Before the loop, at the end of its body, and before the output
+        let entry = block("entry");
+        assert_eq!(stores(&entry).len(), 1);
+        assert!(stores(&entry)[0] < position(&entry, "jmp loop_start_"));
+        assert_eq!(stores(&block("loop_body_")).len(), 1);
+        let end = block("loop_end_");
+        assert_eq!(stores(&end).len(), 1);
+        assert!(stores(&end)[0] < position(&end, "writebyte"));
+        // The adds before the loop address their cells at offsets 1 and 2
+        let offsets: Vec<&str> = entry
+            .iter()
+            .filter(|line| line.contains("= getelem.ptr %base_ptr_"))
+            .map(|line| line.rsplit(", ").nth(1).unwrap())
+            .collect();
+        assert_eq!(offsets, ["1", "2"]);
+    }
+
+    #[test]
+    fn test_cell_wrap_modes() {
+        // Increments a cell holding 255
+        let source = format!("{}+", "+".repeat(255));
+        let build = |cell_wrap| {
+            build_with(
+                &source,
+                BrainfuckConfig {
+                    cell_wrap,
+                    ..BrainfuckConfig::default()
+                },
+            )
+        };
+
+        let ir = build(CellWrap::Wrap);
+        assert!(!ir.contains("add_out_of_range"));
+
+        let ir = build(CellWrap::Saturate);
+        assert_eq!(ir.matches("= gt.i32 %add_sum_").count(), 256);
+        assert_eq!(ir.matches(", -1\n").count(), 256);
+        assert!(!ir.contains("cell_trap"));
+
+        let ir = build(CellWrap::Error);
+        assert_eq!(ir.matches("= call @bfina_trap()").count(), 256);
+
+        // Only `[-]` is certain to clear without wrapping
+        let config = BrainfuckConfig {
+            cell_wrap: CellWrap::Saturate,
+            ..BrainfuckConfig::default()
+        };
+        assert!(!build_with("+[-]", config.clone()).contains("loop_start"));
+        assert!(build_with("+[+]", config).contains("loop_start"));
+    }
+
+    #[test]
+    fn test_compiled_cell_overflow_traps() {
+        let config = BrainfuckConfig { cell_wrap: CellWrap::Error, ..BrainfuckConfig::default() };
+        let Some(result) = run_compiled("+-.-.", b"", config.clone()) else { return };
+        assert!(matches!(result, Err(CompileError::Terminated(_))));
+        assert_eq!(run_compiled("+-.", b"", config).unwrap().unwrap().0, [0]);
+    }
+
+    #[test]
+    fn test_input_is_stored_to_the_current_cell() {
+        let ir = build_with(",.", BrainfuckConfig::default());
+        assert!(ir.contains("%input_val_2 = call @bfina_read_byte()"));
+        assert!(ir.contains("store.i8 %cell_ptr_in_1, %input_val_2"));
+        assert!(ir.contains("load.i8 %cell_ptr_out_"));
+    }
+
+    #[test]
+    fn test_eof_behavior() {
+        let build = |eof_behavior| {
+            build_with(
+                ",",
+                BrainfuckConfig {
+                    eof_behavior,
+                    ..BrainfuckConfig::default()
+                },
+            )
+        };
+
+        // At end of input the store is skipped
+        let ir = build(EofBehavior::Unchanged);
+        assert!(ir.contains("eq.i32 %input_val_2, -1"));
+        assert_eq!(ir.matches("store.i8 %cell_ptr_in_1").count(), 1);
+
+        let ir = build(EofBehavior::Zero);
+        assert!(ir.contains("store.i8 %cell_ptr_in_1, 0"));
+
+        // The read helper's -1 is stored as is
+        let ir = build(EofBehavior::NegativeOne);
+        assert!(!ir.contains("is_eof"));
+        assert!(ir.contains("store.i8 %cell_ptr_in_1, %input_val_2"));
+    }
+
+    /// Compile and run `source`, or `None` if there's no C compiler to
+    /// assemble and link with
+    fn run_compiled(source: &str, input: &[u8], config: BrainfuckConfig) -> Option<Result<(Vec<u8>, i32), CompileError>> {
+        run_compiled_ast(&parse_brainfuck(source).unwrap(), input, config)
+    }
+
+    /// Like [`run_compiled`], for an AST that may hold optimized nodes
+    fn run_compiled_ast(ast: &[AstNode], input: &[u8], config: BrainfuckConfig) -> Option<Result<(Vec<u8>, i32), CompileError>> {
+        if crate::lamina_builder::compiler::find_compiler(&config.toolchain).is_err() {
+            eprintln!("no C compiler available, skipping");
+            return None;
+        }
+        Some(crate::runner::compile_and_run(ast, input, config))
+    }
+
+    #[test]
+    fn test_compiled_input_is_echoed() {
+        let Some(result) = run_compiled(",.", b"A", BrainfuckConfig::default()) else { return };
+        assert_eq!(result.unwrap().0, b"A");
+    }
+
+    #[test]
+    fn test_compiled_exit_from_cell() {
+        // Exits with 42, the cell the pointer ends on, after printing 'A'
+        let source = "++++++++[>++++++++<-]>+.>++++++[>+++++++<-]>";
+        for precompute_output in [true, false] {
+            let config = BrainfuckConfig { exit_from_cell: true, precompute_output, ..BrainfuckConfig::default() };
+            let Some(result) = run_compiled(source, b"", config) else { return };
+            assert_eq!(result.unwrap(), (b"A".to_vec(), 42), "precompute_output: {}", precompute_output);
+        }
+
+        // The exit code is the cell's unsigned value
+        let config = BrainfuckConfig { exit_from_cell: true, ..BrainfuckConfig::default() };
+        let Some(result) = run_compiled(",-", b"\x00", config) else { return };
+        assert_eq!(result.unwrap().1, 255);
+    }
+
+    #[test]
+    fn test_compiled_eof_behavior() {
+        let run = |input: &[u8], eof_behavior| {
+            let config = BrainfuckConfig { eof_behavior, ..BrainfuckConfig::default() };
+            run_compiled("+,.,.", input, config).map(|result| result.unwrap().0)
+        };
+        let Some(output) = run(b"\xff", EofBehavior::Unchanged) else { return };
+        // 0xFF is an input byte, not end of input
+        assert_eq!(output, [0xFF, 0xFF]);
+        assert_eq!(run(b"", EofBehavior::Unchanged).unwrap(), [1, 1]);
+        assert_eq!(run(b"", EofBehavior::Zero).unwrap(), [0, 0]);
+        assert_eq!(run(b"", EofBehavior::NegativeOne).unwrap(), [0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn test_compiled_pointer_moves_write_the_right_cell() {
+        let Some(result) = run_compiled(">>>+<<<[-]>>>.", b"", BrainfuckConfig::default()) else { return };
+        assert_eq!(result.unwrap().0, [1]);
+
+        // Cells at an offset from a loaded pointer, with and without a pending move
+        let Some(result) = run_compiled(">>+[>+>++<<-]>.>.<<<[-]>>+++.", b"", BrainfuckConfig::default()) else { return };
+        assert_eq!(result.unwrap().0, [1, 2, 4]);
+    }
+
+    #[test]
+    fn test_compiled_program_exits_with_zero() {
+        let Some(result) = run_compiled("+++[>+<-]>", b"", BrainfuckConfig::default()) else { return };
+        assert_eq!(result.unwrap(), (Vec::new(), 0));
+    }
+
+    #[test]
+    fn test_compiled_loop_runs_until_zero() {
+        let Some(result) = run_compiled("+++[>+<-]>.", b"", BrainfuckConfig::default()) else { return };
+        assert_eq!(result.unwrap().0, [3]);
+    }
+
+    #[test]
+    fn test_compiled_tape_starts_zeroed_past_the_first_kilobyte() {
+        // Every cell reads 0 before it's written, across the whole tape
+        let source = format!("{}.{}.", ">".repeat(2000), ">".repeat(20000));
+        let Some(result) = run_compiled(&source, b"", BrainfuckConfig::default()) else { return };
+        assert_eq!(result.unwrap().0, [0, 0]);
+    }
+
+    #[test]
+    fn test_max_operations_limit() {
+        let ast = parse_brainfuck("+++[>+<-]").unwrap();
+        let config = BrainfuckConfig {
+            max_operations: Some(5),
+            ..BrainfuckConfig::default()
+        };
+        let builder = BrainfuckIRBuilder::with_config(config);
+        let err = builder.build_ir(&ast).unwrap_err();
+        assert!(err.to_string().contains("exceeding the limit of 5"));
+
+        let config = BrainfuckConfig {
+            max_operations: Some(8),
+            ..BrainfuckConfig::default()
+        };
+        let builder = BrainfuckIRBuilder::with_config(config);
+        assert!(builder.build_ir(&ast).is_ok());
+    }
+
+    #[test]
+    fn test_build_ir_rejects_annotations() {
+        let ast = parse_brainfuck("+").unwrap();
+        let config = BrainfuckConfig {
+            annotate_ir: true,
+            ..BrainfuckConfig::default()
+        };
+        let builder = BrainfuckIRBuilder::with_config(config);
+        assert!(builder.build_ir(&ast).unwrap_err().to_string().contains("annotations"));
+    }
+
+    #[test]
+    fn test_build_ir_static_bounds_check() {
+        let config = BrainfuckConfig {
+            static_bounds_check: true,
+            ..BrainfuckConfig::default()
+        };
+        let builder = BrainfuckIRBuilder::with_config(config);
+
+        let ast = parse_brainfuck("+<").unwrap();
+        assert!(builder.build_ir(&ast).unwrap_err().to_string().contains("cell -1"));
+        let ast = parse_brainfuck(",[<]").unwrap();
+        assert!(builder.build_ir(&ast).is_ok());
+    }
+
+    #[test]
+    fn test_runtime_bounds_check_branches_on_every_move() {
+        let checked = |mode| BrainfuckConfig {
+            tape_size: 4,
+            runtime_bounds_check: Some(mode),
+            ..BrainfuckConfig::default()
+        };
+
+        let unchecked = build_with(">>>>+", BrainfuckConfig::default());
+        assert!(!unchecked.contains("ptr_below"));
+
+        // The pointer is on the last cell when the fourth `>` runs
+        let ir = build_with(">>>>+", checked(PointerMode::Trap));
+        assert_eq!(ir.matches("store.i32 %data_ptr, %ptr_moved_").count(), 4);
+        assert_eq!(ir.matches("= lt.i32 %ptr_moved_").count(), 4);
+        assert_eq!(ir.matches("= ge.i32 %ptr_moved_").count(), 4);
+        assert!(ir.lines().any(|line| line.trim().starts_with("%ptr_above_") && line.ends_with(", 4")));
+        assert!(ir.contains("= call @bfina_trap()"));
+
+        let ir = build_with(">>>>+", checked(PointerMode::Wrap));
+        assert!(ir.lines().any(|line| line.contains("= sub.i32 %ptr_moved_") && line.ends_with(", 4")));
+        assert!(ir.lines().any(|line| line.contains("= add.i32 %ptr_moved_") && line.ends_with(", 4")));
+        assert!(!ir.contains("div.i32"));
+
+        let ir = build_with(">>>>+", checked(PointerMode::Clamp));
+        assert!(ir.contains("store.i32 %data_ptr, 3"));
+        assert!(ir.contains("store.i32 %data_ptr, 0"));
+        assert!(!ir.contains("div.i32"));
+    }
+
+    #[test]
+    fn test_compiled_runtime_bounds_check() {
+        let run = |source: &str, mode| {
+            let config = BrainfuckConfig {
+                tape_size: 4,
+                runtime_bounds_check: Some(mode),
+                opt_level: 3,
+                ..BrainfuckConfig::default()
+            };
+            run_compiled(source, b"", config)
+        };
+
+        // `>` on the last cell
+        let Some(result) = run(">>>>", PointerMode::Trap) else { return };
+        assert!(matches!(result, Err(CompileError::Terminated(_))));
+        assert_eq!(run("+>>>>.", PointerMode::Wrap).unwrap().unwrap().0, [1]);
+        assert_eq!(run(">>>+>>.", PointerMode::Clamp).unwrap().unwrap().0, [1]);
+        assert_eq!(run("+<.", PointerMode::Wrap).unwrap().unwrap().0, [0]);
+
+        // Cells addressed at an offset: a multiply loop target and an output
+        assert!(matches!(run("+[->>>>>+<<<<<]", PointerMode::Trap).unwrap(), Err(CompileError::Terminated(_))));
+        assert_eq!(run("+[->>>>>+<<<<<]>.", PointerMode::Wrap).unwrap().unwrap().0, [1]);
+        assert!(matches!(run(">>>>>.<<<<<", PointerMode::Trap).unwrap(), Err(CompileError::Terminated(_))));
+    }
+
+    #[test]
+    fn test_runtime_bounds_check_ignores_opt_level() {
+        let programs = [">>>>><<<<<+.", ">>>>><<+<<<<<.>.>.>.", "+[->>>>>+<<<<<]>.", "++[>+<-]>>>>>.<<<<<."];
+        for mode in [PointerMode::Trap, PointerMode::Wrap, PointerMode::Clamp] {
+            for source in programs {
+                let run = |opt_level| {
+                    let config = BrainfuckConfig { tape_size: 4, runtime_bounds_check: Some(mode), opt_level, ..BrainfuckConfig::default() };
+                    run_compiled(source, b"", config).map(|result| result.map_err(|err| err.to_string()))
+                };
+                let Some(unoptimized) = run(0) else { return };
+                assert_eq!(run(3).unwrap(), unoptimized, "{} with {:?}", source, mode);
             }
         }
 
-        (commands, loops)
+        // Moves that cancel out still pass the end of the tape on the way
+        let config = |mode| BrainfuckConfig { tape_size: 4, runtime_bounds_check: Some(mode), opt_level: 3, ..BrainfuckConfig::default() };
+        let Some(result) = run_compiled(">>>>><<<<<+.", b"", config(PointerMode::Trap)) else { return };
+        assert!(matches!(result, Err(CompileError::Terminated(_))));
+        let result = run_compiled(">>>>><<+<<<<<.>.>.>.", b"", config(PointerMode::Clamp)).unwrap();
+        assert_eq!(result.unwrap().0, [0, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_offset_accesses_are_bounds_checked() {
+        let config = BrainfuckConfig {
+            tape_size: 4,
+            runtime_bounds_check: Some(PointerMode::Trap),
+            opt_level: 3,
+            ..BrainfuckConfig::default()
+        };
+        // Each step to and from the target is checked
+        let ir = build_with("+[->>+<<]", config);
+        assert!(!ir.contains("mul.i32"));
+        assert_eq!(ir.matches("store.i32 %data_ptr, %ptr_moved_").count(), 4);
+    }
+
+    #[test]
+    fn test_build_ir_rejects_invalid_config() {
+        let ast = parse_brainfuck("+").unwrap();
+        let builder = BrainfuckIRBuilder::with_config(BrainfuckConfig::new(0, 1));
+        assert!(builder.build_ir(&ast).is_err());
+    }
+
+    #[test]
+    fn test_tape_global_must_fit_in_i32() {
+        let ast = parse_brainfuck("+>.").unwrap();
+        // The data pointer after the tape would be past `i32::MAX`
+        for tape_size in [1 << 32, i32::MAX as usize - 3] {
+            let config = BrainfuckConfig {
+                tape_size,
+                max_tape_bytes: None,
+                runtime_bounds_check: Some(PointerMode::Wrap),
+                ..BrainfuckConfig::default()
+            };
+            let builder = BrainfuckIRBuilder::with_config(config);
+            let Err(CompileError::IrGeneration(msg)) = builder.build_ir(&ast) else { panic!("tape of {} cells was accepted", tape_size) };
+            assert!(msg.contains("32-bit"));
+        }
     }
 }
@@ -6,14 +6,22 @@
 pub mod compiler;
 pub mod config;
 pub mod ir_builder;
+pub mod optimize;
 pub mod utils;
 
 // Re-export commonly used types and functions
 pub use compiler::{
-    brainfuck_to_assembly, brainfuck_to_assembly_with_config, brainfuck_to_binary,
-    brainfuck_to_binary_with_config, brainfuck_to_lamina_ir, brainfuck_to_lamina_ir_with_config,
+    CompileError, CompileOutput, CompileTimings, brainfuck_to_assembly,
+    brainfuck_to_assembly_with_config, brainfuck_to_binary, brainfuck_to_binary_with_config,
+    brainfuck_to_c, brainfuck_to_ir_module, brainfuck_to_lamina_ir,
+    brainfuck_to_lamina_ir_with_config, brainfuck_to_llvm_ir, brainfuck_to_llvm_ir_with_spans,
+    brainfuck_to_staticlib, compile_binary, compile_instrumented,
+};
+pub use config::{
+    BUILD_DIR_ENV, BrainfuckConfig, CellWrap, CompilerToolchain, ConfigError,
+    DEFAULT_AUTO_COMPILE_THRESHOLD, DEFAULT_COMPILERS, DEFAULT_INPUT_BUFFER_SIZE,
+    DEFAULT_MAX_TAPE_BYTES, EofBehavior, LARGE_TAPE_SIZE, LaminaOptions, LinkMode, OnError,
+    OutputNewline, SMALL_TAPE_SIZE, STANDARD_TAPE_SIZE, TapeModel,
 };
-pub use config::BrainfuckConfig;
 pub use ir_builder::BrainfuckIRBuilder;
-
-
+pub use optimize::{MAX_OPT_LEVEL, optimize_ast, optimize_at_level};
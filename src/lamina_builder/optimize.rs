@@ -0,0 +1,1008 @@
+//! AST optimization passes
+//!
+//! Each pass takes an AST and returns a new, semantically equivalent AST.
+//! Passes never change observable behavior (output, input consumption, or
+//! termination) of the program.
+
+use crate::analysis::{certain_infinite_loop, command_histogram};
+use crate::lexer::{AstNode, Command, MultiplyAdd, ast_to_brainfuck};
+use std::collections::BTreeMap;
+
+/// Highest optimization level, which runs every pass
+pub const MAX_OPT_LEVEL: u8 = 3;
+
+/// Run all optimization passes over the AST
+///
+/// Code after a loop that never exits is dropped first (see
+/// [`remove_unreachable_code`]). Pointer moves are coalesced again after the
+/// loop passes, since removing a loop can leave two moves next to each other.
+pub fn optimize_ast(ast: &[AstNode]) -> Vec<AstNode> {
+    optimize_ast_with_report(ast).0
+}
+
+/// Run the optimization passes enabled at `level`
+///
+/// Each level adds passes to the one below it:
+///
+/// - 0 runs no passes, leaving one node per command.
+/// - 1 run-length encodes `+`/`-` and `>`/`<` ([`run_length_encode`],
+///   [`coalesce_moves`]).
+/// - 2 also lowers clear loops and cancels inverse commands
+///   ([`lower_clear_loops`], [`cancel_inverses`]), and drops loops that can
+///   never run and code that can never be reached ([`eliminate_dead_loops`],
+///   [`remove_unreachable_code`]).
+/// - 3 also lowers copy and multiply loops and folds pointer moves into I/O
+///   offsets ([`lower_copy_idioms`], [`recognize_multiply_loops`],
+///   [`fold_io_offsets`]). This is the same as [`optimize_ast`].
+///
+/// Levels above [`MAX_OPT_LEVEL`] run every pass.
+pub fn optimize_at_level(ast: &[AstNode], level: u8) -> Vec<AstNode> {
+    optimize_at_level_with_report(ast, level).0
+}
+
+/// Passes run and skipped by [`optimize_ast_with_report`], in pipeline order
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct PassReport {
+    pub ran: Vec<&'static str>,
+    pub skipped: Vec<&'static str>,
+}
+
+/// Run all optimization passes over the AST, reporting which ones ran
+///
+/// Passes that can't change the program are skipped, judging by the
+/// [`command_histogram`] of the input: the loop passes need loops (the copy
+/// pass a balanced one), and the pointer passes need pointer moves. The
+/// result is the same as running every pass.
+pub fn optimize_ast_with_report(ast: &[AstNode]) -> (Vec<AstNode>, PassReport) {
+    optimize_at_level_with_report(ast, MAX_OPT_LEVEL)
+}
+
+/// Like [`optimize_ast_with_report`], but only with the passes enabled at
+/// `level` (see [`optimize_at_level`])
+///
+/// Passes above the level are in neither list of the report.
+pub fn optimize_at_level_with_report(ast: &[AstNode], level: u8) -> (Vec<AstNode>, PassReport) {
+    run_passes(ast, level, false)
+}
+
+/// Like [`optimize_at_level`], but with every pointer move left as written
+///
+/// Only the passes that don't touch pointer moves run, and inverse
+/// commands only cancel when they are `+` and `-`. Compiled programs with
+/// a runtime bounds check (see `BrainfuckConfig::runtime_bounds_check`)
+/// check every move, so `>><<` off the end of the tape must still trap or
+/// clamp rather than cancel out.
+pub fn optimize_cells_at_level(ast: &[AstNode], level: u8) -> Vec<AstNode> {
+    run_passes(ast, level, true).0
+}
+
+/// Run the passes enabled at `level`, skipping those that merge, cancel or
+/// fold pointer moves if `keep_moves` is set
+fn run_passes(ast: &[AstNode], level: u8, keep_moves: bool) -> (Vec<AstNode>, PassReport) {
+    let histogram = command_histogram(ast);
+    let has_loops = histogram.loops > 0;
+    let moves_pointer = histogram.moves_pointer() && !keep_moves;
+
+    let mut ast = ast.to_vec();
+    let mut report = PassReport::default();
+    let mut pass = |name, min_level: u8, applies: bool, run: fn(&[AstNode]) -> Vec<AstNode>| {
+        if level < min_level {
+            return;
+        }
+        if applies {
+            ast = run(&ast);
+            report.ran.push(name);
+        } else {
+            report.skipped.push(name);
+        }
+    };
+
+    pass(
+        "remove_unreachable_code",
+        2,
+        has_loops,
+        remove_unreachable_code,
+    );
+    pass("coalesce_moves", 1, moves_pointer, coalesce_moves);
+    pass("lower_clear_loops", 2, has_loops, lower_clear_loops);
+    pass(
+        "eliminate_dead_loops",
+        2,
+        has_loops || histogram.set_zero > 0,
+        eliminate_dead_loops,
+    );
+    pass("coalesce_moves", 1, moves_pointer, coalesce_moves);
+    pass(
+        "lower_copy_idioms",
+        3,
+        histogram.balanced_loops > 0 && !keep_moves,
+        lower_copy_idioms,
+    );
+    pass(
+        "recognize_multiply_loops",
+        3,
+        histogram.balanced_loops > 0 && !keep_moves,
+        recognize_multiply_loops,
+    );
+    pass(
+        "fold_io_offsets",
+        3,
+        moves_pointer && histogram.has_io(),
+        fold_io_offsets,
+    );
+    let adds = histogram.increment + histogram.decrement + histogram.adds > 0;
+    if keep_moves {
+        pass("coalesce_adds", 1, adds, coalesce_adds);
+        pass("cancel_inverses", 2, adds, cancel_cell_inverses);
+    } else {
+        pass("run_length_encode", 1, adds, run_length_encode);
+        pass("cancel_inverses", 2, adds || moves_pointer, cancel_inverses);
+    }
+
+    (ast, report)
+}
+
+/// Render an optimized AST as standard Brainfuck
+///
+/// This is [`ast_to_brainfuck`], so the result is a runnable `.bf` program
+/// showing what the optimizer produced. Optimizing the re-parsed source
+/// gives back the same AST.
+pub fn lowered_ast_to_source(ast: &[AstNode]) -> String {
+    ast_to_brainfuck(ast)
+}
+
+/// Merge runs of `>`, `<`, and [`AstNode::Move`] into a single
+/// [`AstNode::Move`]
+///
+/// Runs that cancel out are dropped entirely. The pass recurses into loop
+/// bodies and is idempotent.
+pub fn coalesce_moves(ast: &[AstNode]) -> Vec<AstNode> {
+    let mut result = Vec::with_capacity(ast.len());
+    let mut pending = 0i32;
+
+    for node in ast {
+        let delta = match node {
+            AstNode::Command(Command::Right) => 1,
+            AstNode::Command(Command::Left) => -1,
+            AstNode::Move(delta) => *delta,
+            _ => {
+                if pending != 0 {
+                    result.push(AstNode::Move(pending));
+                    pending = 0;
+                }
+                result.push(match node {
+                    AstNode::Loop(body) => AstNode::Loop(coalesce_moves(body)),
+                    _ => node.clone(),
+                });
+                continue;
+            }
+        };
+        pending += delta;
+    }
+
+    if pending != 0 {
+        result.push(AstNode::Move(pending));
+    }
+    result
+}
+
+/// Merge runs of `+`, `-`, and [`AstNode::Add`] into a single
+/// [`AstNode::Add`], and runs of pointer moves into a single
+/// [`AstNode::Move`] (see [`coalesce_moves`])
+///
+/// Cells wrap, so the amount added is kept between -128 and 127, and runs
+/// that add a multiple of 256 (such as `+++---`) are dropped. The pass
+/// recurses into loop bodies and is idempotent.
+pub fn run_length_encode(ast: &[AstNode]) -> Vec<AstNode> {
+    coalesce_adds(&coalesce_moves(ast))
+}
+
+fn coalesce_adds(ast: &[AstNode]) -> Vec<AstNode> {
+    let mut result = Vec::with_capacity(ast.len());
+    // Net amount added by the current run, modulo 256
+    let mut pending: Option<i32> = None;
+
+    for node in ast {
+        let delta = match node {
+            AstNode::Command(Command::Increment) => 1,
+            AstNode::Command(Command::Decrement) => -1,
+            AstNode::Add(delta) => *delta,
+            _ => {
+                flush_add(&mut pending, &mut result);
+                result.push(match node {
+                    AstNode::Loop(body) => AstNode::Loop(coalesce_adds(body)),
+                    _ => node.clone(),
+                });
+                continue;
+            }
+        };
+        pending = Some((pending.unwrap_or(0) + delta.rem_euclid(256)).rem_euclid(256));
+    }
+    flush_add(&mut pending, &mut result);
+
+    result
+}
+
+/// Push the run of additions collected so far, unless it cancels out
+fn flush_add(pending: &mut Option<i32>, result: &mut Vec<AstNode>) {
+    if let Some(delta) = pending.take()
+        && delta != 0
+    {
+        result.push(AstNode::Add(delta as u8 as i8 as i32));
+    }
+}
+
+/// Remove adjacent pairs of nodes that undo each other
+///
+/// `+-`, `-+`, `><` and `<>` cancel, as do [`AstNode::Add`]s and
+/// [`AstNode::Move`]s with opposite amounts. Removing a pair can make its
+/// neighbours adjacent, so `++--` disappears entirely. Loop brackets are
+/// never crossed: in `-[+]` the `-` and `+` aren't adjacent. The pass
+/// recurses into loop bodies and is idempotent.
+pub fn cancel_inverses(ast: &[AstNode]) -> Vec<AstNode> {
+    cancel_inverses_in(ast, true)
+}
+
+/// [`cancel_inverses`] for cell updates only, leaving pointer moves alone
+fn cancel_cell_inverses(ast: &[AstNode]) -> Vec<AstNode> {
+    cancel_inverses_in(ast, false)
+}
+
+fn cancel_inverses_in(ast: &[AstNode], moves: bool) -> Vec<AstNode> {
+    let mut result: Vec<AstNode> = Vec::with_capacity(ast.len());
+
+    for node in ast {
+        if result.last().is_some_and(|last| cancels(last, node, moves)) {
+            result.pop();
+            continue;
+        }
+        result.push(match node {
+            AstNode::Loop(body) => AstNode::Loop(cancel_inverses_in(body, moves)),
+            _ => node.clone(),
+        });
+    }
+
+    result
+}
+
+/// Whether `second` undoes `first`, counting pointer moves only if `moves`
+/// is set
+fn cancels(first: &AstNode, second: &AstNode, moves: bool) -> bool {
+    use Command::{Decrement, Increment, Left, Right};
+
+    match (first, second) {
+        (AstNode::Command(a), AstNode::Command(b)) => match (a, b) {
+            (Increment, Decrement) | (Decrement, Increment) => true,
+            (Right, Left) | (Left, Right) => moves,
+            _ => false,
+        },
+        // Cells wrap, so adding a multiple of 256 does nothing
+        (AstNode::Add(a), AstNode::Add(b)) => (a + b).rem_euclid(256) == 0,
+        (AstNode::Move(a), AstNode::Move(b)) => moves && a + b == 0,
+        _ => false,
+    }
+}
+
+/// Fold pointer moves around I/O into offset-addressed I/O
+///
+/// `Move(a), I/O, Move(b)` becomes an [`AstNode::OutputAtOffset`] or
+/// [`AstNode::InputAtOffset`] at offset `a` followed by `Move(a + b)`, so a
+/// balanced `>.<` leaves no pointer update at all. The pass expects moves to
+/// be coalesced already (see [`coalesce_moves`]).
+pub fn fold_io_offsets(ast: &[AstNode]) -> Vec<AstNode> {
+    let mut nodes = ast.to_vec();
+    let mut result = Vec::with_capacity(nodes.len());
+    let mut i = 0;
+
+    while i < nodes.len() {
+        if let (AstNode::Move(before), Some(io), Some(AstNode::Move(after))) =
+            (&nodes[i], nodes.get(i + 1), nodes.get(i + 2))
+        {
+            let folded = match io {
+                AstNode::Command(Command::Output) => Some(AstNode::OutputAtOffset(*before)),
+                AstNode::Command(Command::Input) => Some(AstNode::InputAtOffset(*before)),
+                _ => None,
+            };
+            if let Some(folded) = folded {
+                // The remaining move may fold into the next I/O as well
+                let rest = before + after;
+                result.push(folded);
+                if rest == 0 {
+                    i += 3;
+                } else {
+                    nodes[i + 2] = AstNode::Move(rest);
+                    i += 2;
+                }
+                continue;
+            }
+        }
+
+        result.push(match &nodes[i] {
+            AstNode::Loop(body) => AstNode::Loop(fold_io_offsets(body)),
+            node => node.clone(),
+        });
+        i += 1;
+    }
+
+    result
+}
+
+/// Replace clear loops (`[-]` and `[+]`) with [`AstNode::SetZero`]
+///
+/// A loop whose body only adds to the current cell clears it whenever each
+/// iteration changes the cell by an odd amount, since cells wrap: `[---]`
+/// reaches zero from any value. Even steps are left alone, because `[--]`
+/// never exits when entered on an odd value.
+///
+/// The pass recurses into every loop body, so a clear nested inside a loop
+/// that can't be lowered itself (such as the `[-]` in `[[-]>]`) is still
+/// replaced.
+pub fn lower_clear_loops(ast: &[AstNode]) -> Vec<AstNode> {
+    ast.iter()
+        .map(|node| match node {
+            AstNode::Loop(body) if is_clear_loop(body) => AstNode::SetZero,
+            AstNode::Loop(body) => AstNode::Loop(lower_clear_loops(body)),
+            _ => node.clone(),
+        })
+        .collect()
+}
+
+/// Whether a loop with this body only clears the current cell
+pub(crate) fn is_clear_loop(body: &[AstNode]) -> bool {
+    let mut delta = 0i32;
+
+    for node in body {
+        delta += match node {
+            AstNode::Command(Command::Increment) => 1,
+            AstNode::Command(Command::Decrement) => -1,
+            AstNode::Add(d) => d.rem_euclid(256),
+            _ => return false,
+        };
+        delta = delta.rem_euclid(256);
+    }
+
+    delta % 2 == 1
+}
+
+/// Replace a clear of a cell followed by a loop that moves the current cell
+/// into it with [`AstNode::CopyAndClear`]
+///
+/// `Move(a), SetZero, Move(b), [->+<]` clears the cell `-b` cells away from
+/// the loop and then transfers the loop's cell there, which is exactly a copy
+/// of that cell followed by clearing it. The loop body may decrement before
+/// or after the transfer, and the pointer may already be on the destination
+/// (no leading move). The pass expects clear loops to be lowered and moves to
+/// be coalesced already (see [`lower_clear_loops`] and [`coalesce_moves`]).
+pub fn lower_copy_idioms(ast: &[AstNode]) -> Vec<AstNode> {
+    let mut result: Vec<AstNode> = Vec::with_capacity(ast.len());
+    let mut i = 0;
+
+    while i < ast.len() {
+        if let (AstNode::SetZero, Some(AstNode::Move(back)), Some(AstNode::Loop(body))) =
+            (&ast[i], ast.get(i + 1), ast.get(i + 2))
+            && transfer_offset(body) == Some(-back)
+        {
+            // Fold the move onto the destination into the move back
+            let mut delta = *back;
+            if let Some(AstNode::Move(to_dest)) = result.last() {
+                delta += to_dest;
+                result.pop();
+            }
+            if delta != 0 {
+                result.push(AstNode::Move(delta));
+            }
+            result.push(AstNode::CopyAndClear(-back));
+            i += 3;
+            continue;
+        }
+
+        result.push(match &ast[i] {
+            AstNode::Loop(body) => AstNode::Loop(lower_copy_idioms(body)),
+            node => node.clone(),
+        });
+        i += 1;
+    }
+
+    result
+}
+
+/// Offset that a `[->+<]`-style loop body transfers its cell to
+fn transfer_offset(body: &[AstNode]) -> Option<i32> {
+    match body {
+        [
+            AstNode::Command(sub),
+            AstNode::Move(to),
+            AstNode::Command(add),
+            AstNode::Move(back),
+        ]
+        | [
+            AstNode::Move(to),
+            AstNode::Command(add),
+            AstNode::Move(back),
+            AstNode::Command(sub),
+        ] if *sub == Command::Decrement
+            && *add == Command::Increment
+            && *to != 0
+            && *to == -*back =>
+        {
+            Some(*to)
+        }
+        _ => None,
+    }
+}
+
+/// Replace balanced loops that add multiples of their control cell to other
+/// cells with [`AstNode::MultiplyLoop`]
+///
+/// A loop qualifies when its body only adds and moves, leaves the pointer
+/// where it started, and takes exactly one from the control cell per
+/// iteration, like `[->+>++<<]`. It then runs as many times as the control
+/// cell's value, so each target gains a fixed multiple of that value. The
+/// pass runs after [`lower_copy_idioms`], which handles the transfer loops
+/// that follow a clear of their destination.
+pub fn recognize_multiply_loops(ast: &[AstNode]) -> Vec<AstNode> {
+    ast.iter()
+        .map(|node| match node {
+            AstNode::Loop(body) => match multiply_targets(body) {
+                Some(targets) if targets.is_empty() => AstNode::SetZero,
+                Some(targets) => AstNode::MultiplyLoop(targets),
+                None => AstNode::Loop(recognize_multiply_loops(body)),
+            },
+            _ => node.clone(),
+        })
+        .collect()
+}
+
+/// Targets of a multiply loop with this body, in offset order
+fn multiply_targets(body: &[AstNode]) -> Option<Vec<MultiplyAdd>> {
+    let mut offset = 0i32;
+    // Amount added to each cell per iteration
+    let mut deltas: BTreeMap<i32, i32> = BTreeMap::new();
+
+    for node in body {
+        match node {
+            AstNode::Command(Command::Right) => offset += 1,
+            AstNode::Command(Command::Left) => offset -= 1,
+            AstNode::Move(delta) => offset += delta,
+            AstNode::Command(Command::Increment) => *deltas.entry(offset).or_default() += 1,
+            AstNode::Command(Command::Decrement) => *deltas.entry(offset).or_default() -= 1,
+            AstNode::Add(delta) => *deltas.entry(offset).or_default() += delta,
+            _ => return None,
+        }
+    }
+
+    let control = deltas.remove(&0).unwrap_or(0);
+    if offset != 0 || control.rem_euclid(256) != 255 {
+        return None;
+    }
+
+    Some(
+        deltas
+            .into_iter()
+            .filter(|(_, delta)| delta.rem_euclid(256) != 0)
+            .map(|(offset, delta)| MultiplyAdd {
+                offset,
+                factor: delta as u8 as i8 as i32,
+            })
+            .collect(),
+    )
+}
+
+/// Remove loops that can never execute
+///
+/// A loop only exits once the current cell is zero, so a loop that directly
+/// follows another loop starts on a zero cell and never runs (e.g. the second
+/// clear in `[-][-]`). The same holds for any loop before the program first
+/// writes to the tape, where every cell is still zero. A [`AstNode::SetZero`]
+/// on a cell that is already zero is removed as well.
+pub fn eliminate_dead_loops(ast: &[AstNode]) -> Vec<AstNode> {
+    eliminate_dead_loops_in(ast, true)
+}
+
+fn eliminate_dead_loops_in(ast: &[AstNode], tape_is_zero: bool) -> Vec<AstNode> {
+    let mut result: Vec<AstNode> = Vec::with_capacity(ast.len());
+    let mut tape_is_zero = tape_is_zero;
+    let mut cell_is_zero = tape_is_zero;
+
+    for node in ast {
+        match node {
+            AstNode::Loop(_) | AstNode::SetZero | AstNode::MultiplyLoop(_) if cell_is_zero => {}
+            AstNode::Loop(body) => {
+                // The loop body is only entered with a nonzero cell
+                result.push(AstNode::Loop(eliminate_dead_loops_in(body, false)));
+                cell_is_zero = true;
+            }
+            AstNode::SetZero => {
+                result.push(node.clone());
+                cell_is_zero = true;
+            }
+            AstNode::Command(Command::Right | Command::Left) | AstNode::Move(_) => {
+                result.push(node.clone());
+                cell_is_zero = tape_is_zero;
+            }
+            AstNode::Command(Command::Output) | AstNode::OutputAtOffset(_) => {
+                result.push(node.clone())
+            }
+            AstNode::InputAtOffset(_) => {
+                result.push(node.clone());
+                tape_is_zero = false;
+            }
+            AstNode::CopyAndClear(_) | AstNode::MultiplyLoop(_) => {
+                result.push(node.clone());
+                cell_is_zero = true;
+                tape_is_zero = false;
+            }
+            AstNode::Command(_) | AstNode::Add(_) => {
+                result.push(node.clone());
+                cell_is_zero = false;
+                tape_is_zero = false;
+            }
+        }
+    }
+
+    result
+}
+
+/// Remove everything after a loop that never exits
+///
+/// Only loops that [`certain_infinite_loop`] proves infinite are considered,
+/// so the program's output and termination are unchanged.
+pub fn remove_unreachable_code(ast: &[AstNode]) -> Vec<AstNode> {
+    match certain_infinite_loop(ast) {
+        Some(index) => ast[..=index].to_vec(),
+        None => ast.to_vec(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::parse_brainfuck;
+
+    fn optimized(source: &str) -> Vec<AstNode> {
+        eliminate_dead_loops(&parse_brainfuck(source).unwrap())
+    }
+
+    #[test]
+    fn test_higher_levels_shrink_the_program() {
+        fn count(ast: &[AstNode]) -> usize {
+            ast.iter()
+                .map(|node| match node {
+                    AstNode::Loop(body) => 1 + count(body),
+                    _ => 1,
+                })
+                .sum()
+        }
+
+        let ast = parse_brainfuck("++++[>+++<-]>[-]+-<<>>.>>.").unwrap();
+        let counts: Vec<usize> = (0..=MAX_OPT_LEVEL)
+            .map(|level| count(&optimize_at_level(&ast, level)))
+            .collect();
+        assert_eq!(counts[0], count(&ast));
+        assert!(
+            counts.windows(2).all(|pair| pair[1] < pair[0]),
+            "{:?}",
+            counts
+        );
+
+        assert_eq!(optimize_at_level(&ast, 0), ast);
+        assert_eq!(optimize_at_level(&ast, MAX_OPT_LEVEL), optimize_ast(&ast));
+        assert_eq!(optimize_at_level(&ast, u8::MAX), optimize_ast(&ast));
+    }
+
+    #[test]
+    fn test_clear_loops_are_lowered() {
+        let ast = parse_brainfuck("+[-]>+[+]").unwrap();
+        assert_eq!(
+            lower_clear_loops(&ast),
+            vec![
+                AstNode::Command(Command::Increment),
+                AstNode::SetZero,
+                AstNode::Command(Command::Right),
+                AstNode::Command(Command::Increment),
+                AstNode::SetZero,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_odd_step_clear_loops_are_lowered() {
+        for source in ["[---]", "[+++++]", "[+-+]"] {
+            let ast = parse_brainfuck(source).unwrap();
+            assert_eq!(
+                lower_clear_loops(&ast),
+                vec![AstNode::SetZero],
+                "{}",
+                source
+            );
+        }
+        assert_eq!(
+            lower_clear_loops(&[AstNode::Loop(vec![AstNode::Add(-3)])]),
+            vec![AstNode::SetZero]
+        );
+
+        // Even steps may skip over zero forever, and other bodies aren't
+        // clears at all
+        for source in ["[----]", "[+-]", "[]", "[->+<]", "[-.]"] {
+            let ast = parse_brainfuck(source).unwrap();
+            assert_eq!(lower_clear_loops(&ast), ast, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_nested_clear_is_lowered_inside_real_loop() {
+        let ast = parse_brainfuck("[[-]>]").unwrap();
+        assert_eq!(
+            lower_clear_loops(&ast),
+            vec![AstNode::Loop(vec![
+                AstNode::SetZero,
+                AstNode::Command(Command::Right),
+            ])]
+        );
+    }
+
+    #[test]
+    fn test_loop_after_set_zero_is_removed() {
+        assert_eq!(
+            optimize_ast(&parse_brainfuck("+>+[-][>+<-].").unwrap()),
+            vec![
+                AstNode::Add(1),
+                AstNode::Move(1),
+                AstNode::Add(1),
+                AstNode::SetZero,
+                AstNode::Command(Command::Output),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_coalesce_moves() {
+        assert_eq!(
+            coalesce_moves(&parse_brainfuck(">>+<<<[>><<]>").unwrap()),
+            vec![
+                AstNode::Move(2),
+                AstNode::Command(Command::Increment),
+                AstNode::Move(-3),
+                AstNode::Loop(vec![]),
+                AstNode::Move(1),
+            ]
+        );
+
+        let once = coalesce_moves(&parse_brainfuck("+>>[->+<]<").unwrap());
+        assert_eq!(coalesce_moves(&once), once);
+    }
+
+    #[test]
+    fn test_moves_fuse_after_dead_loops_are_removed() {
+        // Before the first write every cell is zero, so the clear and the
+        // move loop both vanish and the three moves become one
+        assert_eq!(
+            optimize_ast(&parse_brainfuck(">[-]>[<+>-]>+.").unwrap()),
+            vec![
+                AstNode::Move(3),
+                AstNode::Add(1),
+                AstNode::Command(Command::Output),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_io_offsets_are_folded() {
+        let fold = |source| fold_io_offsets(&coalesce_moves(&parse_brainfuck(source).unwrap()));
+
+        assert_eq!(fold(">.<"), vec![AstNode::OutputAtOffset(1)]);
+        assert_eq!(fold(">,<"), vec![AstNode::InputAtOffset(1)]);
+        assert_eq!(
+            fold("+>>.<.<"),
+            vec![
+                AstNode::Command(Command::Increment),
+                AstNode::OutputAtOffset(2),
+                AstNode::OutputAtOffset(1),
+            ]
+        );
+        assert_eq!(
+            fold("+[>.>]"),
+            vec![
+                AstNode::Command(Command::Increment),
+                AstNode::Loop(vec![AstNode::OutputAtOffset(1), AstNode::Move(2)]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repeated_clear_collapses() {
+        assert_eq!(optimized("+[-][-]"), parse_brainfuck("+[-]").unwrap());
+        assert_eq!(optimized("+[>][>]"), parse_brainfuck("+[>]").unwrap());
+    }
+
+    #[test]
+    fn test_clears_on_different_cells_are_kept() {
+        assert_eq!(optimized("+[-]>[-]"), parse_brainfuck("+[-]>[-]").unwrap());
+    }
+
+    #[test]
+    fn test_loop_at_program_start_is_removed() {
+        assert_eq!(optimized("[-]+."), parse_brainfuck("+.").unwrap());
+    }
+
+    #[test]
+    fn test_nested_dead_loops_are_removed() {
+        assert_eq!(
+            optimized("+[>[-][-]<-]"),
+            parse_brainfuck("+[>[-]<-]").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_loop_passes_are_skipped_without_loops() {
+        let ast = parse_brainfuck("+++>++<.").unwrap();
+        let (optimized, report) = optimize_ast_with_report(&ast);
+        assert_eq!(
+            report.skipped,
+            [
+                "remove_unreachable_code",
+                "lower_clear_loops",
+                "eliminate_dead_loops",
+                "lower_copy_idioms",
+                "recognize_multiply_loops",
+            ]
+        );
+        assert_eq!(
+            report.ran,
+            [
+                "coalesce_moves",
+                "coalesce_moves",
+                "fold_io_offsets",
+                "run_length_encode",
+                "cancel_inverses",
+            ]
+        );
+        // Skipping passes doesn't change the result
+        let every_pass = cancel_inverses(&run_length_encode(&fold_io_offsets(
+            &recognize_multiply_loops(&lower_copy_idioms(&coalesce_moves(&eliminate_dead_loops(
+                &lower_clear_loops(&coalesce_moves(&remove_unreachable_code(&ast))),
+            )))),
+        )));
+        assert_eq!(optimized, every_pass);
+
+        let (_, report) = optimize_ast_with_report(&parse_brainfuck("+[->+<]").unwrap());
+        assert_eq!(report.skipped, ["fold_io_offsets"]);
+    }
+
+    #[test]
+    fn test_code_after_infinite_loop_is_removed() {
+        let ast = parse_brainfuck("+[]>+.<[-]").unwrap();
+        assert_eq!(
+            remove_unreachable_code(&ast),
+            parse_brainfuck("+[]").unwrap()
+        );
+        assert_eq!(optimize_ast(&ast), [AstNode::Add(1), AstNode::Loop(vec![])]);
+    }
+
+    #[test]
+    fn test_run_length_encode() {
+        let encode = |source: &str| run_length_encode(&parse_brainfuck(source).unwrap());
+
+        assert_eq!(
+            encode("+++>>-<[--->+<+++]"),
+            vec![
+                AstNode::Add(3),
+                AstNode::Move(2),
+                AstNode::Add(-1),
+                AstNode::Move(-1),
+                AstNode::Loop(vec![
+                    AstNode::Add(-3),
+                    AstNode::Move(1),
+                    AstNode::Add(1),
+                    AstNode::Move(-1),
+                    AstNode::Add(3),
+                ]),
+            ]
+        );
+
+        // Cells wrap, so whole turns of the cell cancel out too
+        assert_eq!(encode("+++---."), vec![AstNode::Command(Command::Output)]);
+        assert_eq!(encode(&"+".repeat(256)), vec![]);
+        assert_eq!(encode(&"+".repeat(300)), vec![AstNode::Add(44)]);
+        assert_eq!(encode(&"-".repeat(200)), vec![AstNode::Add(56)]);
+
+        let once = encode("++[->+++<]>--.");
+        assert_eq!(run_length_encode(&once), once);
+    }
+
+    #[test]
+    fn test_multiply_loops_are_recognized() {
+        let recognize = |source: &str| recognize_multiply_loops(&parse_brainfuck(source).unwrap());
+        let target = |offset, factor| MultiplyAdd { offset, factor };
+
+        assert_eq!(
+            recognize("[->+>+++<<]"),
+            vec![AstNode::MultiplyLoop(vec![target(1, 1), target(2, 3)])]
+        );
+        // The decrement may come anywhere, and targets are sorted by offset
+        assert_eq!(
+            recognize("[<<-->>>+<-]"),
+            vec![AstNode::MultiplyLoop(vec![target(-2, -2), target(1, 1)])]
+        );
+        assert_eq!(
+            recognize("[->+<+-]"),
+            vec![AstNode::MultiplyLoop(vec![target(1, 1)])]
+        );
+        // Nested loops are lowered where the outer loop can't be
+        assert_eq!(
+            recognize("[>[->+<]<-]"),
+            vec![AstNode::Loop(vec![
+                AstNode::Command(Command::Right),
+                AstNode::MultiplyLoop(vec![target(1, 1)]),
+                AstNode::Command(Command::Left),
+                AstNode::Command(Command::Decrement),
+            ])]
+        );
+
+        // Unbalanced, I/O, or a control cell that doesn't step down by one
+        for source in ["[->+]", "[->.<]", "[-->+<]", "[+>+<]"] {
+            let ast = parse_brainfuck(source).unwrap();
+            assert_eq!(recognize_multiply_loops(&ast), ast, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_multiply_loops_preserve_semantics() {
+        use crate::interpreter::run;
+        use crate::lamina_builder::BrainfuckConfig;
+
+        let config = BrainfuckConfig::default();
+        for source in [
+            "+++++[->++>+++<<]>.>.",
+            "++++++++[>++++++++<-]>[-<+>>+<]<.>>.",
+            ",[->-<]>.",
+        ] {
+            let ast = parse_brainfuck(source).unwrap();
+            let lowered = optimize_ast(&ast);
+            assert!(command_histogram(&lowered).multiply_loops > 0, "{}", source);
+
+            let mut expected = Vec::new();
+            run(&ast, &config, &mut &b"\x07"[..], &mut expected).unwrap();
+            let mut actual = Vec::new();
+            run(&lowered, &config, &mut &b"\x07"[..], &mut actual).unwrap();
+            assert_eq!(actual, expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_cancel_inverses() {
+        let cancel = |source: &str| cancel_inverses(&parse_brainfuck(source).unwrap());
+
+        assert_eq!(cancel("+-+-"), vec![]);
+        assert_eq!(cancel("++--<>><"), vec![]);
+        assert_eq!(cancel(">+-<."), vec![AstNode::Command(Command::Output)]);
+
+        // Brackets separate commands that would otherwise cancel
+        assert_eq!(cancel("+[-]+"), parse_brainfuck("+[-]+").unwrap());
+        assert_eq!(cancel("-[+-+]"), parse_brainfuck("-[+]").unwrap());
+
+        assert_eq!(
+            cancel_inverses(&[
+                AstNode::Add(3),
+                AstNode::Move(2),
+                AstNode::Move(-2),
+                AstNode::Add(253),
+            ]),
+            vec![]
+        );
+
+        let once = cancel("+>-<<+-><-[+-+]");
+        assert_eq!(cancel_inverses(&once), once);
+    }
+
+    #[test]
+    fn test_cell_passes_keep_pointer_moves() {
+        let ast = parse_brainfuck(">>><<<++-[>+<-][-]>.<").unwrap();
+        for level in 0..=MAX_OPT_LEVEL {
+            let optimized = optimize_cells_at_level(&ast, level);
+            let moves = |ast: &[AstNode]| {
+                ast_to_brainfuck(ast)
+                    .chars()
+                    .filter(|c| "<>".contains(*c))
+                    .collect::<String>()
+            };
+            assert_eq!(moves(&optimized), moves(&ast), "level {}", level);
+        }
+        assert_eq!(
+            ast_to_brainfuck(&optimize_cells_at_level(&ast, MAX_OPT_LEVEL)),
+            ">>><<<+[>+<-]>.<"
+        );
+    }
+
+    #[test]
+    fn test_code_after_exiting_loop_is_kept() {
+        for source in ["[]>+.", ",[]>+.", "+[-]>+.", "+[>]+."] {
+            let ast = parse_brainfuck(source).unwrap();
+            assert_eq!(remove_unreachable_code(&ast), ast, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_copy_idiom_is_lowered() {
+        let lowered = |source| {
+            lower_copy_idioms(&coalesce_moves(&lower_clear_loops(
+                &parse_brainfuck(source).unwrap(),
+            )))
+        };
+
+        assert_eq!(lowered(">[-]<[->+<]"), vec![AstNode::CopyAndClear(1)]);
+        assert_eq!(lowered("<<[-]>>[<<+>>-]"), vec![AstNode::CopyAndClear(-2)]);
+        // The pointer may start on the destination or end up elsewhere
+        assert_eq!(
+            lowered("[-]>>[-<<+>>]"),
+            vec![AstNode::Move(2), AstNode::CopyAndClear(-2)]
+        );
+        assert_eq!(
+            lowered("+[>>[-]<[->+<]<-]"),
+            vec![
+                AstNode::Command(Command::Increment),
+                AstNode::Loop(vec![
+                    AstNode::Move(1),
+                    AstNode::CopyAndClear(1),
+                    AstNode::Move(-1),
+                    AstNode::Command(Command::Decrement),
+                ]),
+            ]
+        );
+
+        // A transfer to a cell other than the cleared one is not a copy
+        assert_eq!(lowered(">[-]<[->>+<<]").len(), 4);
+        assert_eq!(lowered(">[-]<[->+<<]").len(), 4);
+    }
+
+    #[test]
+    fn test_copy_idiom_preserves_semantics() {
+        let run = |ast: &[AstNode]| {
+            let mut output = Vec::new();
+            crate::interpreter::run(ast, &Default::default(), &mut &b""[..], &mut output).unwrap();
+            output
+        };
+
+        let source = "+++++>+++<>[-]<[->+<].>.";
+        let ast = parse_brainfuck(source).unwrap();
+        let optimized = optimize_ast(&ast);
+        assert_eq!(
+            optimized
+                .iter()
+                .filter(|node| matches!(node, AstNode::CopyAndClear(_)))
+                .count(),
+            1
+        );
+        assert!(
+            !optimized
+                .iter()
+                .any(|node| matches!(node, AstNode::Loop(_)))
+        );
+        assert_eq!(run(&optimized), run(&ast));
+        assert_eq!(run(&ast), [0, 5]);
+    }
+
+    #[test]
+    fn test_lowered_source_round_trips() {
+        assert_eq!(
+            lowered_ast_to_source(&[
+                AstNode::SetZero,
+                AstNode::Move(-2),
+                AstNode::OutputAtOffset(1),
+                AstNode::CopyAndClear(2),
+            ]),
+            "[-]<<>.<>>[-]<<[->>+<<]"
+        );
+
+        for source in [
+            include_str!("../../testcases/hello_world.bf"),
+            include_str!("../../testcases/cell_copy.bf"),
+            include_str!("../../tests/corpus/rot13.bf"),
+            "+++>+>>[-]<<[->>+<<]>>.<<<,>>.<<",
+        ] {
+            let optimized = optimize_ast(&parse_brainfuck(source).unwrap());
+            let lowered = lowered_ast_to_source(&optimized);
+            assert_eq!(optimize_ast(&parse_brainfuck(&lowered).unwrap()), optimized);
+        }
+    }
+}
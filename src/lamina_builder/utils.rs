@@ -1,6 +1,8 @@
 //! Utility functions for the Lamina builder
 
-use crate::lexer::AstNode;
+use super::config::BrainfuckConfig;
+use crate::analysis::certain_out_of_bounds_from;
+use crate::lexer::{AstNode, Command};
 
 /// Count the number of operations in the AST
 pub fn count_operations(ast: &[AstNode]) -> (usize, usize) {
@@ -9,17 +11,139 @@ pub fn count_operations(ast: &[AstNode]) -> (usize, usize) {
 
     for node in ast {
         match node {
-            AstNode::Command(_) => commands += 1,
             AstNode::Loop(body) => {
                 loops += 1;
                 let (sub_commands, sub_loops) = count_operations(body);
                 commands += sub_commands;
                 loops += sub_loops;
             }
+            _ => commands += 1,
         }
     }
 
     (commands, loops)
 }
 
+/// Returns true if the program contains an input command anywhere
+pub fn contains_input(ast: &[AstNode]) -> bool {
+    ast.iter().any(|node| match node {
+        AstNode::Command(cmd) => *cmd == Command::Input,
+        AstNode::Loop(body) => contains_input(body),
+        AstNode::InputAtOffset(_) => true,
+        AstNode::SetZero
+        | AstNode::Add(_)
+        | AstNode::Move(_)
+        | AstNode::OutputAtOffset(_)
+        | AstNode::CopyAndClear(_)
+        | AstNode::MultiplyLoop(_) => false,
+    })
+}
+
+/// Compute the net data pointer movement of a sequence of nodes
+///
+/// Loops count as zero movement when their own body is balanced. Returns
+/// `None` if any loop moves the pointer by a data-dependent amount.
+pub fn net_pointer_movement(ast: &[AstNode]) -> Option<isize> {
+    let mut offset = 0isize;
+
+    for node in ast {
+        match node {
+            AstNode::Command(Command::Right) => offset += 1,
+            AstNode::Command(Command::Left) => offset -= 1,
+            AstNode::Move(delta) => offset += *delta as isize,
+            AstNode::Command(_)
+            | AstNode::SetZero
+            | AstNode::Add(_)
+            | AstNode::OutputAtOffset(_)
+            | AstNode::InputAtOffset(_)
+            | AstNode::CopyAndClear(_)
+            | AstNode::MultiplyLoop(_) => {}
+            AstNode::Loop(body) => {
+                if net_pointer_movement(body)? != 0 {
+                    return None;
+                }
+            }
+        }
+    }
+
+    Some(offset)
+}
+
+/// Apply `config.static_bounds_check`, rejecting programs whose pointer
+/// certainly leaves the tape
+pub fn check_static_bounds(ast: &[AstNode], config: &BrainfuckConfig) -> Result<(), String> {
+    if !config.static_bounds_check {
+        return Ok(());
+    }
+
+    match certain_out_of_bounds_from(ast, config.tape_size, config.start_cell()) {
+        Some(cell) => Err(format!(
+            "Data pointer moves out of bounds to cell {} (the tape has {} cells)",
+            cell, config.tape_size
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Compute the maximum loop nesting depth of the AST
+///
+/// A program without loops has depth 0, a single loop has depth 1, and so on.
+/// The traversal is iterative so that deeply nested programs can't overflow
+/// the stack.
+pub fn max_nesting_depth(ast: &[AstNode]) -> usize {
+    let mut max_depth = 0;
+    let mut stack: Vec<(&[AstNode], usize)> = vec![(ast, 0)];
+
+    while let Some((nodes, depth)) = stack.pop() {
+        max_depth = max_depth.max(depth);
 
+        for node in nodes {
+            if let AstNode::Loop(body) = node {
+                stack.push((body, depth + 1));
+            }
+        }
+    }
+
+    max_depth
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::parse_brainfuck;
+
+    #[test]
+    fn test_contains_input() {
+        assert!(!contains_input(&parse_brainfuck("+[>.<-]").unwrap()));
+        assert!(contains_input(&parse_brainfuck("+[>[,]<-]").unwrap()));
+    }
+
+    #[test]
+    fn test_net_pointer_movement() {
+        let movement = |source| net_pointer_movement(&parse_brainfuck(source).unwrap());
+        assert_eq!(movement(">>+<"), Some(1));
+        assert_eq!(movement("[>>+<<-]"), Some(0));
+        assert_eq!(movement(">[>+<-]>"), Some(2));
+        assert_eq!(movement("[>]"), None);
+        assert_eq!(movement("+[>[<]>-]"), None);
+    }
+
+    #[test]
+    fn test_max_nesting_depth_flat() {
+        let ast = parse_brainfuck("+-><.,").unwrap();
+        assert_eq!(max_nesting_depth(&ast), 0);
+        assert_eq!(max_nesting_depth(&[]), 0);
+    }
+
+    #[test]
+    fn test_max_nesting_depth_single_loop() {
+        let ast = parse_brainfuck("+[-]>[<+>-]").unwrap();
+        assert_eq!(max_nesting_depth(&ast), 1);
+    }
+
+    #[test]
+    fn test_max_nesting_depth_nested_loops() {
+        let ast = parse_brainfuck("[[+]][[[-]]>]").unwrap();
+        assert_eq!(max_nesting_depth(&ast), 3);
+    }
+}
@@ -1,17 +1,108 @@
+use crate::token::Token;
+use std::io::BufRead;
 use std::iter::Peekable;
 use std::str::Chars;
 
 /// Abstract Syntax Tree node types for Brainfuck
 #[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AstNode {
     /// A basic Brainfuck command
     Command(Command),
     /// A loop containing nested nodes
     Loop(Vec<AstNode>),
+    /// Set the current cell to zero (a lowered `[-]` or `[+]` loop)
+    SetZero,
+    /// Add a signed amount to the current cell, wrapping (coalesced `+`/`-`)
+    Add(i32),
+    /// Move the data pointer by a signed number of cells (coalesced `>`/`<`)
+    Move(i32),
+    /// Output the cell at an offset from the data pointer without moving it
+    OutputAtOffset(i32),
+    /// Read into the cell at an offset from the data pointer without moving it
+    InputAtOffset(i32),
+    /// Overwrite the cell at an offset with the current cell, then clear the
+    /// current cell (a lowered `>[-]<[->+<]`)
+    CopyAndClear(i32),
+    /// Add a multiple of the current cell to each target, then clear the
+    /// current cell (a lowered `[->+>++<<]`)
+    MultiplyLoop(Vec<MultiplyAdd>),
+}
+
+/// A target of [`AstNode::MultiplyLoop`]: `factor` times the current cell is
+/// added to the cell at `offset`, wrapping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MultiplyAdd {
+    pub offset: i32,
+    pub factor: i32,
+}
+
+impl AstNode {
+    /// Expand an optimized node into the plain commands it stands for
+    ///
+    /// `SetZero` becomes `[-]`, `Add` becomes a run of `+` or `-`,
+    /// `CopyAndClear` becomes a clear of the destination followed by a
+    /// transfer loop, `MultiplyLoop` becomes the loop it was lowered from,
+    /// and the other optimized nodes become single pointer steps around their
+    /// command. Plain commands and loops are returned unchanged.
+    pub fn expand(&self) -> Vec<AstNode> {
+        let steps = |delta: i32| {
+            let step = if delta > 0 {
+                Command::Right
+            } else {
+                Command::Left
+            };
+            vec![AstNode::Command(step); delta.unsigned_abs() as usize]
+        };
+        let adds = |delta: i32| {
+            let cmd = if delta > 0 {
+                Command::Increment
+            } else {
+                Command::Decrement
+            };
+            vec![AstNode::Command(cmd); delta.unsigned_abs() as usize]
+        };
+        let around = |offset: i32, cmd: Command| {
+            let mut nodes = steps(offset);
+            nodes.push(AstNode::Command(cmd));
+            nodes.extend(steps(-offset));
+            nodes
+        };
+
+        match self {
+            AstNode::Command(_) | AstNode::Loop(_) => vec![self.clone()],
+            AstNode::SetZero => vec![AstNode::Loop(vec![AstNode::Command(Command::Decrement)])],
+            AstNode::Move(delta) => steps(*delta),
+            AstNode::Add(delta) => adds(*delta),
+            AstNode::OutputAtOffset(offset) => around(*offset, Command::Output),
+            AstNode::InputAtOffset(offset) => around(*offset, Command::Input),
+            AstNode::CopyAndClear(offset) => {
+                let mut nodes = steps(*offset);
+                nodes.push(AstNode::SetZero.expand().remove(0));
+                nodes.extend(steps(-offset));
+
+                let mut body = vec![AstNode::Command(Command::Decrement)];
+                body.extend(around(*offset, Command::Increment));
+                nodes.push(AstNode::Loop(body));
+                nodes
+            }
+            AstNode::MultiplyLoop(targets) => {
+                let mut body = vec![AstNode::Command(Command::Decrement)];
+                for target in targets {
+                    body.extend(steps(target.offset));
+                    body.extend(adds(target.factor));
+                    body.extend(steps(-target.offset));
+                }
+                vec![AstNode::Loop(body)]
+            }
+        }
+    }
 }
 
 /// Basic Brainfuck commands (excluding loop constructs)
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Command {
     Right,     // >
     Left,      // <
@@ -21,8 +112,89 @@ pub enum Command {
     Input,     // ,
 }
 
+impl Command {
+    /// Convert a character to its command
+    ///
+    /// Returns None for brackets and for characters that are comments.
+    pub fn from_char(c: char) -> Option<Self> {
+        Token::from_char(c).and_then(Self::from_token)
+    }
+
+    /// The command a token stands for, or None for brackets and [`Token::Eof`]
+    pub fn from_token(token: Token) -> Option<Self> {
+        match token {
+            Token::Right => Some(Command::Right),
+            Token::Left => Some(Command::Left),
+            Token::Increment => Some(Command::Increment),
+            Token::Decrement => Some(Command::Decrement),
+            Token::Output => Some(Command::Output),
+            Token::Input => Some(Command::Input),
+            Token::LoopStart | Token::LoopEnd | Token::Eof => None,
+        }
+    }
+
+    /// Get the character representation of this command
+    pub fn as_char(&self) -> char {
+        Token::from(*self).as_char()
+    }
+}
+
+/// How line breaks are recognized when tracking source positions
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    /// Accept `\n`, `\r\n` and lone `\r` as line breaks
+    #[default]
+    Universal,
+    /// Only `\n` ends a line; `\r` counts as an ordinary column
+    Lf,
+}
+
+/// How columns are counted when lexing raw bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ColumnMode {
+    /// Count one column per Unicode code point, matching the `&str` lexer
+    ///
+    /// Bytes that aren't part of a valid UTF-8 sequence count as one column
+    /// each, like the replacement characters of a lossy decode.
+    #[default]
+    CodePoints,
+    /// Count one column per byte
+    Bytes,
+}
+
+/// Tracks UTF-8 sequences in a byte stream so columns can be counted per
+/// code point
+///
+/// Only the sequence structure is decoded; the bytes themselves are still
+/// treated as comments by the lexer.
+#[derive(Debug, Default)]
+struct Utf8Columns {
+    /// Continuation bytes still expected for the current character
+    remaining: u8,
+}
+
+impl Utf8Columns {
+    /// Whether `byte` starts a new logical character
+    fn starts_char(&mut self, byte: u8) -> bool {
+        if byte & 0xC0 == 0x80 && self.remaining > 0 {
+            self.remaining -= 1;
+            return false;
+        }
+
+        // A lead byte (or a stray byte) cuts off any unfinished sequence
+        self.remaining = match byte {
+            0xC2..=0xDF => 1,
+            0xE0..=0xEF => 2,
+            0xF0..=0xF4 => 3,
+            _ => 0,
+        };
+        true
+    }
+}
+
 /// Represents a position in the source code for error reporting
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Position {
     pub line: usize,
     pub column: usize,
@@ -47,13 +219,61 @@ impl Position {
             self.column += 1;
         }
     }
+
+    /// Advance past `c`, recognizing line breaks according to `line_ending`
+    ///
+    /// `previous` is the character before `c`, which is needed to count a
+    /// `\r\n` pair as a single line break.
+    pub fn advance_with(&mut self, c: char, previous: Option<char>, line_ending: LineEnding) {
+        match (line_ending, c) {
+            (LineEnding::Universal, '\r') => {
+                self.line += 1;
+                self.column = 1;
+            }
+            (LineEnding::Universal, '\n') if previous == Some('\r') => {}
+            _ => self.advance(c),
+        }
+    }
+
+    /// Advance past a single byte of UTF-8 encoded source
+    ///
+    /// Continuation bytes don't move the column, so a multi-byte character
+    /// counts as one column exactly like [`Position::advance`].
+    pub fn advance_byte(&mut self, byte: u8) {
+        if byte == b'\n' {
+            self.line += 1;
+            self.column = 1;
+        } else if byte & 0xC0 != 0x80 {
+            self.column += 1;
+        }
+    }
+
+    /// Byte-based counterpart of [`Position::advance_with`]
+    pub fn advance_byte_with(&mut self, byte: u8, previous: Option<u8>, line_ending: LineEnding) {
+        if byte.is_ascii() {
+            self.advance_with(byte as char, previous.map(char::from), line_ending);
+        } else {
+            self.advance_byte(byte);
+        }
+    }
 }
 
 /// Error type for lexer operations
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum LexerError {
     UnmatchedClosingBracket(Position),
-    UnexpectedEndOfInput(Position),
+    /// Input ended inside a loop opened by the `[` at `open`
+    UnmatchedOpeningBracket {
+        open: Position,
+        eof: Position,
+    },
+    /// A non-command character appeared inside a loop in strict mode
+    UnexpectedCharacter(char, Position),
+    /// Loops were nested more deeply than the lexer allows, starting with
+    /// the `[` at this position
+    NestingTooDeep(Position),
+    /// Reading the source failed
+    Io(String),
 }
 
 impl std::fmt::Display for LexerError {
@@ -66,13 +286,28 @@ impl std::fmt::Display for LexerError {
                     pos.line, pos.column
                 )
             }
-            LexerError::UnexpectedEndOfInput(pos) => {
+            LexerError::UnmatchedOpeningBracket { open, eof } => {
                 write!(
                     f,
-                    "Unexpected end of input while parsing loop at line {}, column {}",
+                    "Unmatched opening bracket '[' at line {}, column {}: input ends at line {}, column {}",
+                    open.line, open.column, eof.line, eof.column
+                )
+            }
+            LexerError::UnexpectedCharacter(c, pos) => {
+                write!(
+                    f,
+                    "Unexpected character {:?} inside loop at line {}, column {}",
+                    c, pos.line, pos.column
+                )
+            }
+            LexerError::NestingTooDeep(pos) => {
+                write!(
+                    f,
+                    "Loops nested too deeply at line {}, column {}",
                     pos.line, pos.column
                 )
             }
+            LexerError::Io(err) => write!(f, "Failed to read source: {}", err),
         }
     }
 }
@@ -82,34 +317,92 @@ impl std::error::Error for LexerError {}
 /// Result type for lexer operations
 pub type Result<T> = std::result::Result<T, LexerError>;
 
+/// How deeply loops may nest before [`Lexer`] gives up
+pub const DEFAULT_MAX_NESTING_DEPTH: usize = 1000;
+
 /// Brainfuck lexer that converts source code into an AST
 pub struct Lexer<'a> {
     chars: Peekable<Chars<'a>>,
     position: Position,
+    line_ending: LineEnding,
+    previous: Option<char>,
+    strict_in_loops: bool,
+    max_nesting_depth: usize,
+    /// Position of every node, in pre-order
+    spans: Vec<Position>,
 }
 
 impl<'a> Lexer<'a> {
     /// Create a new lexer from source code
     pub fn new(source: &'a str) -> Self {
+        Self::with_line_ending(source, LineEnding::default())
+    }
+
+    /// Create a new lexer that recognizes line breaks as `line_ending`
+    pub fn with_line_ending(source: &'a str, line_ending: LineEnding) -> Self {
         Self {
             chars: source.chars().peekable(),
             position: Position::new(),
+            line_ending,
+            previous: None,
+            strict_in_loops: false,
+            max_nesting_depth: DEFAULT_MAX_NESTING_DEPTH,
+            spans: Vec::new(),
         }
     }
 
+    /// Reject comments inside loop bodies
+    ///
+    /// When enabled, any character inside `[...]` other than a command or
+    /// whitespace is a [`LexerError::UnexpectedCharacter`]. Comments at the
+    /// top level are still allowed.
+    pub fn strict_in_loops(mut self, strict: bool) -> Self {
+        self.strict_in_loops = strict;
+        self
+    }
+
+    /// Limit how deeply loops may nest
+    ///
+    /// Loops are parsed recursively, so without a limit a file of a few
+    /// thousand `[` would overflow the stack. A `[` beyond the limit is a
+    /// [`LexerError::NestingTooDeep`]. Defaults to
+    /// [`DEFAULT_MAX_NESTING_DEPTH`].
+    pub fn max_nesting_depth(mut self, depth: usize) -> Self {
+        self.max_nesting_depth = depth;
+        self
+    }
+
+    /// Move the tracked position past `c`
+    fn advance(&mut self, c: char) {
+        self.position
+            .advance_with(c, self.previous, self.line_ending);
+        self.previous = Some(c);
+    }
+
     /// Parse the entire source code into an AST
-    pub fn parse(mut self) -> Result<Vec<AstNode>> {
+    pub fn parse(self) -> Result<Vec<AstNode>> {
+        self.parse_spanned().map(|(nodes, _)| nodes)
+    }
+
+    /// Parse the source into an AST along with the position of every node
+    ///
+    /// Positions are listed in pre-order: each loop's `[` comes right before
+    /// the nodes of its body.
+    pub fn parse_spanned(mut self) -> Result<(Vec<AstNode>, Vec<Position>)> {
         let mut nodes = Vec::new();
 
         while let Some(&c) = self.chars.peek() {
-            if let Some(command) = self.parse_command(c) {
+            if let Some(command) = Command::from_char(c) {
+                self.spans.push(self.position);
                 self.chars.next();
-                self.position.advance(c);
+                self.advance(c);
                 nodes.push(AstNode::Command(command));
             } else if c == '[' {
+                let open = self.position;
+                self.spans.push(open);
                 self.chars.next();
-                self.position.advance(c);
-                let loop_body = self.parse_loop()?;
+                self.advance(c);
+                let loop_body = self.parse_loop(open, 1)?;
                 nodes.push(AstNode::Loop(loop_body));
             } else if c == ']' {
                 // This should be handled by parse_loop, not here
@@ -117,52 +410,53 @@ impl<'a> Lexer<'a> {
             } else {
                 // Skip comments and whitespace
                 self.chars.next();
-                self.position.advance(c);
+                self.advance(c);
             }
         }
 
-        Ok(nodes)
-    }
-
-    /// Parse a single command character
-    fn parse_command(&self, c: char) -> Option<Command> {
-        match c {
-            '>' => Some(Command::Right),
-            '<' => Some(Command::Left),
-            '+' => Some(Command::Increment),
-            '-' => Some(Command::Decrement),
-            '.' => Some(Command::Output),
-            ',' => Some(Command::Input),
-            _ => None,
-        }
+        Ok((nodes, self.spans))
     }
 
     /// Parse a loop construct and its body
-    fn parse_loop(&mut self) -> Result<Vec<AstNode>> {
+    ///
+    /// `open` is the position of the loop's `[`, used for error reporting,
+    /// and `depth` is how many loops enclose the body, this one included.
+    fn parse_loop(&mut self, open: Position, depth: usize) -> Result<Vec<AstNode>> {
+        if depth > self.max_nesting_depth {
+            return Err(LexerError::NestingTooDeep(open));
+        }
         let mut nodes = Vec::new();
 
         while let Some(&c) = self.chars.peek() {
-            if let Some(command) = self.parse_command(c) {
+            if let Some(command) = Command::from_char(c) {
+                self.spans.push(self.position);
                 self.chars.next();
-                self.position.advance(c);
+                self.advance(c);
                 nodes.push(AstNode::Command(command));
             } else if c == '[' {
+                let nested_open = self.position;
+                self.spans.push(nested_open);
                 self.chars.next();
-                self.position.advance(c);
-                let nested_loop = self.parse_loop()?;
+                self.advance(c);
+                let nested_loop = self.parse_loop(nested_open, depth + 1)?;
                 nodes.push(AstNode::Loop(nested_loop));
             } else if c == ']' {
                 self.chars.next();
-                self.position.advance(c);
+                self.advance(c);
                 return Ok(nodes);
+            } else if self.strict_in_loops && !c.is_whitespace() {
+                return Err(LexerError::UnexpectedCharacter(c, self.position));
             } else {
                 // Skip comments and whitespace
                 self.chars.next();
-                self.position.advance(c);
+                self.advance(c);
             }
         }
 
-        Err(LexerError::UnexpectedEndOfInput(self.position))
+        Err(LexerError::UnmatchedOpeningBracket {
+            open,
+            eof: self.position,
+        })
     }
 }
 
@@ -172,6 +466,170 @@ pub fn parse_brainfuck(source: &str) -> Result<Vec<AstNode>> {
     lexer.parse()
 }
 
+/// Render an AST back to Brainfuck source
+///
+/// Commands become their characters and loops their brackets, with no
+/// comments or whitespace. Optimized nodes are written out as the plain
+/// commands they stand for (see [`AstNode::expand`]). Parsing the result
+/// gives back an AST that runs the same way, and exactly the same AST if it
+/// came from [`parse_brainfuck`].
+pub fn ast_to_brainfuck(ast: &[AstNode]) -> String {
+    fn render(nodes: &[AstNode], out: &mut String) {
+        for node in nodes {
+            match node {
+                AstNode::Command(cmd) => out.push(cmd.as_char()),
+                AstNode::Loop(body) => {
+                    out.push('[');
+                    render(body, out);
+                    out.push(']');
+                }
+                _ => render(&node.expand(), out),
+            }
+        }
+    }
+
+    let mut source = String::new();
+    render(ast, &mut source);
+    source
+}
+
+/// Parse Brainfuck source code into an AST and the position of every node
+///
+/// See [`Lexer::parse_spanned`] for the order of the positions.
+pub fn parse_brainfuck_spanned(source: &str) -> Result<(Vec<AstNode>, Vec<Position>)> {
+    Lexer::new(source).parse_spanned()
+}
+
+/// Find every unbalanced bracket in `source`
+///
+/// Unlike [`parse_brainfuck`], which stops at the first problem, this scans
+/// the whole source and reports each unmatched `]` in source order, followed
+/// by each `[` that is never closed. An empty result means the brackets are
+/// balanced.
+pub fn check_brackets(source: &str) -> Vec<LexerError> {
+    let mut errors = Vec::new();
+    let mut open = Vec::new();
+    let mut position = Position::new();
+    let mut previous = None;
+
+    for c in source.chars() {
+        match c {
+            '[' => open.push(position),
+            ']' if open.pop().is_none() => {
+                errors.push(LexerError::UnmatchedClosingBracket(position));
+            }
+            _ => {}
+        }
+        position.advance_with(c, previous, LineEnding::default());
+        previous = Some(c);
+    }
+
+    errors.extend(
+        open.into_iter()
+            .map(|open| LexerError::UnmatchedOpeningBracket {
+                open,
+                eof: position,
+            }),
+    );
+    errors
+}
+
+/// Parse Brainfuck source incrementally from a reader
+///
+/// The source is consumed in whatever chunks the reader provides, so large
+/// files never need to be loaded into memory as a whole. Lexing works on
+/// bytes, which keeps positions correct even when a multi-byte character is
+/// split across two reads. Loops may nest [`DEFAULT_MAX_NESTING_DEPTH`] deep,
+/// as with [`Lexer`].
+pub fn parse_reader(reader: impl BufRead) -> Result<Vec<AstNode>> {
+    parse_reader_with_columns(reader, ColumnMode::default())
+}
+
+/// Like [`parse_reader`], but with control over how columns are counted
+///
+/// The source doesn't have to be valid UTF-8: every byte that isn't a
+/// command is a comment, whatever encoding it belongs to.
+pub fn parse_reader_with_columns(
+    reader: impl BufRead,
+    columns: ColumnMode,
+) -> Result<Vec<AstNode>> {
+    parse_reader_spanned(reader, columns).map(|(nodes, _)| nodes)
+}
+
+/// Like [`parse_reader_with_columns`], but also returns the position of
+/// every node
+///
+/// Positions are in the same pre-order as [`Lexer::parse_spanned`]'s.
+pub fn parse_reader_spanned(
+    mut reader: impl BufRead,
+    columns: ColumnMode,
+) -> Result<(Vec<AstNode>, Vec<Position>)> {
+    let mut position = Position::new();
+    let mut previous = None;
+    let mut utf8 = Utf8Columns::default();
+    let mut nodes = Vec::new();
+    let mut spans = Vec::new();
+    // Enclosing node lists and the position of each open `[`
+    let mut open_loops: Vec<(Vec<AstNode>, Position)> = Vec::new();
+
+    loop {
+        let buf = match reader.fill_buf() {
+            Ok([]) => break,
+            Ok(buf) => buf,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(LexerError::Io(err.to_string())),
+        };
+
+        for &byte in buf {
+            if matches!(byte, b'>' | b'<' | b'+' | b'-' | b'.' | b',' | b'[') {
+                spans.push(position);
+            }
+            match byte {
+                b'>' => nodes.push(AstNode::Command(Command::Right)),
+                b'<' => nodes.push(AstNode::Command(Command::Left)),
+                b'+' => nodes.push(AstNode::Command(Command::Increment)),
+                b'-' => nodes.push(AstNode::Command(Command::Decrement)),
+                b'.' => nodes.push(AstNode::Command(Command::Output)),
+                b',' => nodes.push(AstNode::Command(Command::Input)),
+                b'[' => {
+                    // The parse itself is iterative, but the AST it builds
+                    // is walked recursively
+                    if open_loops.len() >= DEFAULT_MAX_NESTING_DEPTH {
+                        return Err(LexerError::NestingTooDeep(position));
+                    }
+                    open_loops.push((std::mem::take(&mut nodes), position))
+                }
+                b']' => match open_loops.pop() {
+                    Some((outer, _)) => {
+                        let body = std::mem::replace(&mut nodes, outer);
+                        nodes.push(AstNode::Loop(body));
+                    }
+                    None => return Err(LexerError::UnmatchedClosingBracket(position)),
+                },
+                _ => {}
+            }
+            let starts_char = utf8.starts_char(byte);
+            if byte.is_ascii() {
+                position.advance_byte_with(byte, previous, LineEnding::default());
+            } else if starts_char || columns == ColumnMode::Bytes {
+                position.column += 1;
+            }
+            previous = Some(byte);
+        }
+
+        let len = buf.len();
+        reader.consume(len);
+    }
+
+    match open_loops.pop() {
+        Some((_, open)) => Err(LexerError::UnmatchedOpeningBracket {
+            open,
+            eof: position,
+        }),
+        None => Ok((nodes, spans)),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -253,6 +711,312 @@ mod tests {
     fn test_unexpected_end_of_input() {
         let source = "[+";
         let result = parse_brainfuck(source);
-        assert!(matches!(result, Err(LexerError::UnexpectedEndOfInput(_))));
+        assert!(matches!(
+            result,
+            Err(LexerError::UnmatchedOpeningBracket { .. })
+        ));
+    }
+
+    fn pos(line: usize, column: usize) -> Position {
+        Position { line, column }
+    }
+
+    #[test]
+    fn test_single_closing_bracket_position() {
+        assert_eq!(
+            parse_brainfuck("]"),
+            Err(LexerError::UnmatchedClosingBracket(pos(1, 1)))
+        );
+        assert_eq!(
+            parse_brainfuck("]["),
+            Err(LexerError::UnmatchedClosingBracket(pos(1, 1)))
+        );
+    }
+
+    #[test]
+    fn test_single_opening_bracket_position() {
+        let unmatched = |open, eof| LexerError::UnmatchedOpeningBracket { open, eof };
+        assert_eq!(parse_brainfuck("["), Err(unmatched(pos(1, 1), pos(1, 2))));
+        assert_eq!(
+            parse_brainfuck("[  \n\t "),
+            Err(unmatched(pos(1, 1), pos(2, 3)))
+        );
+        assert_eq!(
+            parse_brainfuck("+\n  [-"),
+            Err(unmatched(pos(2, 3), pos(2, 5)))
+        );
+
+        // The inner loop is closed, so the outer bracket is the unmatched one
+        let error = unmatched(pos(1, 1), pos(1, 5));
+        assert_eq!(parse_brainfuck("[[+]"), Err(error.clone()));
+        assert_eq!(parse_reader("[[+]".as_bytes()), Err(error.clone()));
+        assert_eq!(
+            error.to_string(),
+            "Unmatched opening bracket '[' at line 1, column 1: input ends at line 1, column 5"
+        );
+    }
+
+    #[test]
+    fn test_deep_nesting_is_an_error() {
+        let source = "[".repeat(5000) + &"]".repeat(5000);
+        assert_eq!(
+            parse_brainfuck(&source),
+            Err(LexerError::NestingTooDeep(pos(
+                1,
+                DEFAULT_MAX_NESTING_DEPTH + 1
+            )))
+        );
+
+        let too_deep = Err(LexerError::NestingTooDeep(pos(
+            1,
+            DEFAULT_MAX_NESTING_DEPTH + 1,
+        )));
+        assert_eq!(parse_reader(source.as_bytes()), too_deep);
+        assert_eq!(
+            parse_reader_spanned(source.as_bytes(), ColumnMode::default()).map(|(nodes, _)| nodes),
+            too_deep
+        );
+        let source = "[".repeat(DEFAULT_MAX_NESTING_DEPTH) + &"]".repeat(DEFAULT_MAX_NESTING_DEPTH);
+        assert!(parse_reader(source.as_bytes()).is_ok());
+
+        let source = "[[[-]]]";
+        assert!(Lexer::new(source).max_nesting_depth(3).parse().is_ok());
+        assert_eq!(
+            Lexer::new(source).max_nesting_depth(2).parse(),
+            Err(LexerError::NestingTooDeep(pos(1, 3)))
+        );
+    }
+
+    #[test]
+    fn test_check_brackets_reports_every_error() {
+        assert!(check_brackets("+[->[+]<]").is_empty());
+
+        let eof = pos(2, 6);
+        assert_eq!(
+            check_brackets("][+]]\n[[-]["),
+            vec![
+                LexerError::UnmatchedClosingBracket(pos(1, 1)),
+                LexerError::UnmatchedClosingBracket(pos(1, 5)),
+                LexerError::UnmatchedOpeningBracket {
+                    open: pos(2, 1),
+                    eof
+                },
+                LexerError::UnmatchedOpeningBracket {
+                    open: pos(2, 5),
+                    eof
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ast_to_brainfuck_round_trips() {
+        for source in [
+            "",
+            "+-<>.,",
+            "++[>+<-]>.",
+            "a comment [ with [nested] loops ] and more,.\n",
+            "[[[]]][-][+>]",
+        ] {
+            let ast = parse_brainfuck(source).unwrap();
+            let rendered = ast_to_brainfuck(&ast);
+            assert_eq!(parse_brainfuck(&rendered).unwrap(), ast);
+            assert!(rendered.chars().all(|c| "<>+-.,[]".contains(c)));
+        }
+    }
+
+    #[test]
+    fn test_check_brackets_agrees_with_parse() {
+        for source in ["]", "[", "+[[-]", "[]]", "a\r\nb ] ["] {
+            let errors = check_brackets(source);
+            assert_eq!(Some(&errors[0]), parse_brainfuck(source).err().as_ref());
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_ast_round_trips_through_json() {
+        let ast = parse_brainfuck("++[>+[>++<-]<-]>>.").unwrap();
+        let json = serde_json::to_string(&ast).unwrap();
+        assert_eq!(serde_json::from_str::<Vec<AstNode>>(&json).unwrap(), ast);
+
+        let optimized = crate::optimize_ast(&ast);
+        let json = serde_json::to_string(&optimized).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Vec<AstNode>>(&json).unwrap(),
+            optimized
+        );
+
+        let json = serde_json::to_string(&pos(3, 7)).unwrap();
+        assert_eq!(json, r#"{"line":3,"column":7}"#);
+        assert_eq!(serde_json::from_str::<Position>(&json).unwrap(), pos(3, 7));
+    }
+
+    #[test]
+    fn test_parse_spanned_positions() {
+        let (ast, spans) = parse_brainfuck_spanned("+ [\n >[-]\n]<").unwrap();
+        assert_eq!(ast, parse_brainfuck("+[>[-]]<").unwrap());
+        assert_eq!(
+            spans,
+            vec![
+                pos(1, 1),
+                pos(1, 3),
+                pos(2, 2),
+                pos(2, 3),
+                pos(2, 4),
+                pos(3, 2)
+            ]
+        );
+    }
+
+    /// Reader that hands out at most `chunk` bytes per read
+    struct ChunkedReader<'a> {
+        data: &'a [u8],
+        chunk: usize,
+    }
+
+    impl std::io::Read for ChunkedReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let n = self.chunk.min(buf.len()).min(self.data.len());
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            Ok(n)
+        }
+    }
+
+    fn parse_chunked(source: &str, chunk: usize) -> Result<Vec<AstNode>> {
+        let reader = ChunkedReader {
+            data: source.as_bytes(),
+            chunk,
+        };
+        parse_reader(std::io::BufReader::with_capacity(chunk, reader))
+    }
+
+    #[test]
+    fn test_parse_reader_matches_parse_brainfuck() {
+        let source = "héllo ✨ +[->+<]\n>> 🦀 [.,]";
+        let expected = parse_brainfuck(source);
+
+        assert_eq!(parse_reader(std::io::Cursor::new(source)), expected);
+        for chunk in 1..5 {
+            assert_eq!(parse_chunked(source, chunk), expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_reader_spans_match_parse_spanned() {
+        let source = "+ [\n >[-]\n]< \u{1F600}.";
+        let spanned = parse_reader_spanned(source.as_bytes(), ColumnMode::CodePoints);
+        assert_eq!(spanned, parse_brainfuck_spanned(source));
+
+        let reader = ChunkedReader {
+            data: source.as_bytes(),
+            chunk: 1,
+        };
+        let spanned = parse_reader_spanned(
+            std::io::BufReader::with_capacity(1, reader),
+            ColumnMode::CodePoints,
+        );
+        assert_eq!(spanned, parse_brainfuck_spanned(source));
+    }
+
+    #[test]
+    fn test_line_endings_are_counted_consistently() {
+        for newline in ["\n", "\r", "\r\n"] {
+            let source = format!("+{0}-{0}{0}  ]", newline);
+            assert_eq!(
+                parse_brainfuck(&source),
+                Err(LexerError::UnmatchedClosingBracket(pos(4, 3))),
+                "newline {:?}",
+                newline
+            );
+            assert_eq!(
+                parse_reader(std::io::Cursor::new(&source)),
+                parse_brainfuck(&source)
+            );
+        }
+    }
+
+    #[test]
+    fn test_lf_line_ending_ignores_carriage_return() {
+        let lexer = Lexer::with_line_ending("+\r\r]", LineEnding::Lf);
+        assert_eq!(
+            lexer.parse(),
+            Err(LexerError::UnmatchedClosingBracket(pos(1, 4)))
+        );
+
+        let lexer = Lexer::with_line_ending("+\r\n]", LineEnding::Lf);
+        assert_eq!(
+            lexer.parse(),
+            Err(LexerError::UnmatchedClosingBracket(pos(2, 1)))
+        );
+    }
+
+    #[test]
+    fn test_comment_inside_loop() {
+        let source = "top level comment +[- decrement\n]";
+        let expected = vec![
+            AstNode::Command(Command::Increment),
+            AstNode::Loop(vec![AstNode::Command(Command::Decrement)]),
+        ];
+
+        assert_eq!(Lexer::new(source).parse(), Ok(expected.clone()));
+        assert_eq!(
+            Lexer::new(source).strict_in_loops(true).parse(),
+            Err(LexerError::UnexpectedCharacter('d', pos(1, 23)))
+        );
+
+        // Whitespace and top-level comments are fine in strict mode
+        let source = "comment +[\n  - \n] done";
+        assert_eq!(
+            Lexer::new(source).strict_in_loops(true).parse(),
+            Ok(expected)
+        );
+    }
+
+    #[test]
+    fn test_parse_reader_error_positions() {
+        for source in ["ünïcode 🦀 ]", "+\n✨✨ [[-]", "🦀\n\n  ][", "✨\r\n🦀\r["] {
+            let expected = parse_brainfuck(source);
+            assert!(expected.is_err());
+            assert_eq!(parse_reader(std::io::Cursor::new(source)), expected);
+            for chunk in 1..5 {
+                assert_eq!(parse_chunked(source, chunk), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_emoji_comments_before_error_column() {
+        let source = "🦀🦀 +[ comment 👍🏽 ]] ✨";
+        let expected = parse_brainfuck(source);
+        assert!(matches!(
+            expected,
+            Err(LexerError::UnmatchedClosingBracket(Position {
+                line: 1,
+                column: 19
+            }))
+        ));
+
+        let bytes = parse_reader_with_columns(source.as_bytes(), ColumnMode::CodePoints);
+        assert_eq!(bytes, expected);
+
+        let column = source.find("]]").unwrap() + 2;
+        assert_eq!(
+            parse_reader_with_columns(source.as_bytes(), ColumnMode::Bytes),
+            Err(LexerError::UnmatchedClosingBracket(pos(1, column)))
+        );
+    }
+
+    #[test]
+    fn test_invalid_utf8_counts_one_column_per_bad_byte() {
+        // Stray continuation bytes, a truncated sequence and an invalid lead
+        let source: &[u8] = b"\x80\xbf+\xe2\x9c-\xff]";
+        let lossy = String::from_utf8_lossy(source);
+        assert_eq!(parse_reader(source), parse_brainfuck(&lossy));
+        assert_eq!(
+            parse_reader(source),
+            Err(LexerError::UnmatchedClosingBracket(pos(1, 7)))
+        );
     }
 }
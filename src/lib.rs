@@ -2,16 +2,66 @@
 //!
 //! This crate provides a Brainfuck compiler implementation using the Lamina
 //! compiler framework as the backend.
+//!
+//! The optimizer is available from the crate root: [`optimize_ast`] runs every
+//! pass, and the individual passes live in [`optimize`]. Optimized programs
+//! use the extra [`AstNode`] variants, which every backend and the
+//! interpreter accept.
+//!
+//! ```
+//! use bfina::optimize::coalesce_moves;
+//! use bfina::{AstNode, optimize_ast, parse_brainfuck};
+//!
+//! let ast = parse_brainfuck("+[-]>>.<<").unwrap();
+//! assert_eq!(
+//!     optimize_ast(&ast),
+//!     vec![
+//!         AstNode::Add(1),
+//!         AstNode::SetZero,
+//!         AstNode::OutputAtOffset(2),
+//!     ]
+//! );
+//! assert_eq!(coalesce_moves(&parse_brainfuck(">><").unwrap()), vec![AstNode::Move(1)]);
+//! ```
 
+pub mod analysis;
+pub mod interpreter;
 pub mod lamina_builder;
 pub mod lexer;
+#[cfg(feature = "mmap")]
+pub mod mapped;
+pub mod pretty;
+pub mod runner;
+pub mod tape;
+#[cfg(feature = "testing")]
+pub mod testing;
 pub mod token;
 
 // Re-export commonly used types
+pub use interpreter::{RunError, run_source};
+/// The Lamina IR module returned by [`brainfuck_to_ir_module`]
+pub use lamina::ir::Module;
+pub use lamina_builder::optimize::{
+    self, MAX_OPT_LEVEL, PassReport, optimize_ast, optimize_ast_with_report, optimize_at_level,
+};
 pub use lamina_builder::{
-    BrainfuckConfig, BrainfuckIRBuilder, brainfuck_to_assembly, brainfuck_to_assembly_with_config,
-    brainfuck_to_binary, brainfuck_to_binary_with_config, brainfuck_to_lamina_ir,
-    brainfuck_to_lamina_ir_with_config,
+    BUILD_DIR_ENV, BrainfuckConfig, BrainfuckIRBuilder, CellWrap, CompileError, CompileOutput,
+    CompileTimings, CompilerToolchain, ConfigError, DEFAULT_AUTO_COMPILE_THRESHOLD,
+    DEFAULT_COMPILERS, DEFAULT_INPUT_BUFFER_SIZE, DEFAULT_MAX_TAPE_BYTES, EofBehavior,
+    LARGE_TAPE_SIZE, LaminaOptions, LinkMode, OnError, OutputNewline, SMALL_TAPE_SIZE,
+    STANDARD_TAPE_SIZE, TapeModel, brainfuck_to_assembly, brainfuck_to_assembly_with_config,
+    brainfuck_to_binary, brainfuck_to_binary_with_config, brainfuck_to_c, brainfuck_to_ir_module,
+    brainfuck_to_lamina_ir, brainfuck_to_lamina_ir_with_config, brainfuck_to_llvm_ir,
+    brainfuck_to_llvm_ir_with_spans, brainfuck_to_staticlib, compile_binary, compile_instrumented,
+};
+pub use lexer::{
+    AstNode, ColumnMode, Command, DEFAULT_MAX_NESTING_DEPTH, Lexer, LexerError, LineEnding,
+    MultiplyAdd, Position, ast_to_brainfuck, check_brackets, parse_brainfuck,
+    parse_brainfuck_spanned, parse_reader, parse_reader_spanned, parse_reader_with_columns,
 };
-pub use lexer::{AstNode, Command, Lexer, LexerError, parse_brainfuck};
-pub use token::Token;
+#[cfg(feature = "mmap")]
+pub use mapped::interpret_to_file;
+pub use pretty::{PrettyOpts, pretty_print};
+pub use runner::{RunPath, auto_run, choose_run_path, compile_and_run};
+pub use tape::{FixedTape, PointerMode, SparseTape, Tape};
+pub use token::{Token, Tokenizer};
@@ -1,45 +1,224 @@
+use bfina::analysis::{certain_infinite_loop, estimate_cost, lint};
+use bfina::interpreter;
+use bfina::lamina_builder::utils::count_operations;
 use bfina::{
-    AstNode, Command, brainfuck_to_binary, brainfuck_to_lamina_ir, parse_brainfuck,
+    AstNode, BrainfuckConfig, MAX_OPT_LEVEL, Position, PrettyOpts, ast_to_brainfuck,
+    brainfuck_to_assembly_with_config, brainfuck_to_binary_with_config,
+    brainfuck_to_lamina_ir_with_config, parse_brainfuck_spanned, pretty_print,
 };
 use std::env;
 use std::fs;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 
-/// Print the AST in a human-readable format
-fn print_ast(nodes: &[AstNode], indent: usize) {
-    let indent_str = "  ".repeat(indent);
+/// Filename that stands for standard input
+const STDIN_FILENAME: &str = "-";
 
-    for node in nodes {
-        match node {
-            AstNode::Command(cmd) => {
-                println!("{}{}", indent_str, format_command(*cmd));
-            }
-            AstNode::Loop(body) => {
-                println!("{}Loop [", indent_str);
-                print_ast(body, indent + 1);
-                println!("{}]", indent_str);
-            }
+/// Stem of output files for a program read from standard input
+const STDIN_STEM: &str = "a";
+
+/// Executable built from a program read from standard input
+const STDIN_BINARY: &str = if cfg!(windows) { "a.exe" } else { "a.out" };
+
+/// What the compiler produces, chosen with `--emit`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Emit {
+    /// Lamina IR in a `.lamina` file
+    Ir,
+    /// Assembly in a `.s` file
+    Asm,
+    /// A native executable
+    Bin,
+}
+
+impl Emit {
+    fn parse(kind: &str) -> Result<Self, String> {
+        match kind {
+            "ir" => Ok(Emit::Ir),
+            "asm" => Ok(Emit::Asm),
+            "bin" => Ok(Emit::Bin),
+            _ => Err(format!(
+                "Unknown --emit kind '{}' (expected ir, asm or bin)",
+                kind
+            )),
         }
     }
 }
 
-/// Format a command for display
-fn format_command(cmd: Command) -> &'static str {
-    match cmd {
-        Command::Right => "Right (>)",
-        Command::Left => "Left (<)",
-        Command::Increment => "Increment (+)",
-        Command::Decrement => "Decrement (-)",
-        Command::Output => "Output (.)",
-        Command::Input => "Input (,)",
-    }
+/// Command-line options
+struct Options {
+    /// Paths to the Brainfuck source files, or just [`STDIN_FILENAME`]
+    filenames: Vec<String>,
+    /// Keep the generated Lamina IR next to the source file
+    keep_ir: bool,
+    /// Directory for intermediate build files, overriding `BFINA_BUILD_DIR`
+    build_dir: Option<PathBuf>,
+    /// Run the program in the interpreter instead of compiling it
+    interpret: bool,
+    /// Print the parsed AST instead of compiling
+    dump_ast: bool,
+    /// Print the estimated cost before and after optimization instead of
+    /// compiling
+    stats: bool,
+    /// Write the program without comments instead of compiling
+    minify: bool,
+    /// Don't print warnings about likely mistakes in the program
+    no_warn: bool,
+    /// Write the minified program to a `.min.bf` file next to the source
+    emit_source: bool,
+    /// What to produce when compiling
+    emit: Emit,
+    /// Path to write the output to instead of one derived from the filename
+    output: Option<String>,
+    /// Optimization level for compiled output
+    opt_level: u8,
 }
 
 /// Print usage information
 fn print_usage() {
-    eprintln!("Usage: brainfuck-lamina <filename>");
-    eprintln!("  filename: Path to Brainfuck (.bf or .b) source file");
+    eprintln!(
+        "Usage: brainfuck-lamina [--emit=ir|asm|bin] [--keep-ir] [-O<level>] [-o <path>] [--build-dir <dir>] [--interpret] [--dump-ast] [--stats] [--minify [--emit-source]] [--no-warn] [filename...]"
+    );
+    eprintln!("  filename:          Path to Brainfuck (.bf or .b) source file, or - (the");
+    eprintln!("                     default) to read it from stdin. Several files are");
+    eprintln!("                     compiled in parallel, each next to its source");
+    eprintln!("  --emit=<kind>:     Produce Lamina IR (.lamina), assembly (.s), or an");
+    eprintln!("                     executable (bin, the default)");
+    eprintln!("  --keep-ir:         Also save the generated Lamina IR as a .lamina file");
+    eprintln!("                     (--emit-ir is a deprecated spelling)");
+    eprintln!("  -O<level>:         Optimize the program at level 0 (the default) to 3");
+    eprintln!("  -o, --output <path>:");
+    eprintln!("                     Write the output to <path> instead of next to the source");
+    eprintln!("  --build-dir <dir>: Write intermediate files to <dir> (default: $BFINA_BUILD_DIR");
+    eprintln!("                     or a private directory in the system temp directory)");
+    eprintln!("  --interpret:       Run the program directly instead of compiling it");
+    eprintln!("  --dump-ast:        Print the parsed program's AST instead of compiling it");
+    eprintln!("  --stats:           Print the program's estimated cost before and after");
+    eprintln!("                     optimizing at the -O level, instead of compiling it");
+    eprintln!("  --minify:          Print the program without comments instead of compiling it");
+    eprintln!("  --emit-source:     With --minify, save it as a .min.bf file next to the source");
+    eprintln!("  --no-warn:         Don't warn about infinite loops and redundant clears");
+}
+
+/// Parse command-line arguments (excluding the program name)
+fn parse_args(args: &[String]) -> Result<Options, String> {
+    let mut filenames = Vec::new();
+    let mut keep_ir = false;
+    let mut build_dir = None;
+    let mut interpret = false;
+    let mut dump_ast = false;
+    let mut stats = false;
+    let mut minify = false;
+    let mut emit_source = false;
+    let mut no_warn = false;
+    let mut emit = Emit::Bin;
+    let mut output = None;
+    let mut opt_level = 0;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keep-ir" => keep_ir = true,
+            // Reads like a spelling of --emit=ir, which doesn't build the
+            // executable at all
+            "--emit-ir" => {
+                eprintln!(
+                    "Warning: --emit-ir is deprecated, use --keep-ir (or --emit=ir for the IR alone)"
+                );
+                keep_ir = true;
+            }
+            "--emit" => match args.next() {
+                Some(kind) => emit = Emit::parse(kind)?,
+                None => return Err("--emit requires ir, asm or bin".to_string()),
+            },
+            flag if flag.starts_with("--emit=") => emit = Emit::parse(&flag["--emit=".len()..])?,
+            "--interpret" => interpret = true,
+            "--dump-ast" => dump_ast = true,
+            "--stats" => stats = true,
+            "--minify" => minify = true,
+            "--emit-source" => emit_source = true,
+            "--no-warn" => no_warn = true,
+            "-o" | "--output" => match args.next() {
+                Some(path) => output = Some(path.clone()),
+                None => return Err(format!("{} requires a path", arg)),
+            },
+            flag if flag.starts_with("-O") => {
+                opt_level = match flag["-O".len()..].parse() {
+                    Ok(level) if level <= MAX_OPT_LEVEL => level,
+                    _ => {
+                        return Err(format!(
+                            "Invalid optimization level '{}' (expected -O0 to -O{})",
+                            flag, MAX_OPT_LEVEL
+                        ));
+                    }
+                };
+            }
+            "--build-dir" => match args.next() {
+                Some(dir) => build_dir = Some(PathBuf::from(dir)),
+                None => return Err("--build-dir requires a directory".to_string()),
+            },
+            flag if flag.starts_with("--") => {
+                return Err(format!("Unknown option '{}'", flag));
+            }
+            _ => filenames.push(arg.clone()),
+        }
+    }
+
+    if filenames.len() > 1 {
+        if filenames.iter().any(|filename| filename == STDIN_FILENAME) {
+            return Err("Only a single program can be read from stdin".to_string());
+        }
+        let single_file_flag = [
+            (interpret, "--interpret"),
+            (dump_ast, "--dump-ast"),
+            (stats, "--stats"),
+            (minify, "--minify"),
+            (output.is_some(), "--output"),
+        ]
+        .into_iter()
+        .find_map(|(set, flag)| set.then_some(flag));
+        if let Some(flag) = single_file_flag {
+            return Err(format!("{} takes a single filename", flag));
+        }
+    }
+    if filenames.is_empty() {
+        filenames.push(STDIN_FILENAME.to_string());
+    }
+
+    if emit_source && !minify {
+        return Err("--emit-source requires --minify".to_string());
+    }
+
+    Ok(Options {
+        filenames,
+        keep_ir,
+        build_dir,
+        interpret,
+        dump_ast,
+        stats,
+        minify,
+        emit_source,
+        no_warn,
+        emit,
+        output,
+        opt_level,
+    })
+}
+
+/// Make sure `path` can be written as an output file, creating its parent
+/// directories if needed
+fn prepare_output_path(path: &Path) -> Result<(), String> {
+    if path.is_dir() {
+        return Err(format!("Output path '{}' is a directory", path.display()));
+    }
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent)
+            .map_err(|err| format!("Failed to create directory '{}': {}", parent.display(), err)),
+        _ => Ok(()),
+    }
 }
 
 /// Generate the output filename for the .lamina file
@@ -62,37 +241,267 @@ fn generate_lamina_filename(input_filename: &str) -> String {
 
 /// Generate the output filename for the binary executable
 fn generate_binary_filename(input_filename: &str) -> String {
+    let extension = if cfg!(windows) { ".exe" } else { "" };
+    generate_sibling_filename(input_filename, extension)
+}
+
+/// Generate the output filename for the assembly file
+fn generate_assembly_filename(input_filename: &str) -> String {
+    generate_sibling_filename(input_filename, ".s")
+}
+
+/// The input's stem followed by `suffix`, in the input's directory
+fn generate_sibling_filename(input_filename: &str, suffix: &str) -> String {
     let path = Path::new(input_filename);
     let stem = path.file_stem().unwrap_or_default().to_string_lossy();
     let parent = path.parent().unwrap_or(Path::new(""));
+    let name = format!("{}{}", stem, suffix);
 
-    let binary_name = if cfg!(windows) {
-        format!("{}.exe", stem)
+    if parent.to_string_lossy().is_empty() {
+        name
     } else {
-        stem.to_string()
+        format!("{}/{}", parent.display(), name)
+    }
+}
+
+impl Options {
+    /// Build configuration for compiling with these options
+    fn config(&self) -> BrainfuckConfig {
+        BrainfuckConfig {
+            build_dir: self.build_dir.clone(),
+            opt_level: self.opt_level,
+            ..BrainfuckConfig::default()
+        }
+    }
+}
+
+/// Warnings about likely mistakes in the program, without the file name
+fn program_warnings(ast: &[AstNode], spans: &[Position]) -> Vec<String> {
+    // Pre-order index of a loop that never exits and has code after it.
+    // Spans are in pre-order, so the loop's span follows every node before
+    // it.
+    let unreachable_after = certain_infinite_loop(ast)
+        .filter(|&index| index + 1 < ast.len())
+        .map(|index| {
+            let (commands, loops) = count_operations(&ast[..index]);
+            commands + loops
+        });
+
+    let mut warnings: Vec<String> = lint(ast)
+        .into_iter()
+        // That loop's own warning below already says it never exits
+        .filter(|lint| Some(lint.node) != unreachable_after)
+        .map(|lint| {
+            let position = spans[lint.node];
+            format!(
+                "{} at line {}, column {}",
+                lint.kind, position.line, position.column
+            )
+        })
+        .collect();
+
+    if let Some(node) = unreachable_after {
+        let position = spans[node];
+        warnings.push(format!(
+            "the loop at line {}, column {} never exits; the code after it is unreachable",
+            position.line, position.column
+        ));
+    }
+    warnings
+}
+
+/// Outcome of compiling one file of a batch
+struct FileReport {
+    /// Warnings about the program
+    warnings: Vec<String>,
+    /// What was written, or why the file failed
+    outcome: Result<Vec<String>, String>,
+}
+
+/// Compile one file of a batch, writing its output next to the source
+fn compile_file(filename: &str, options: &Options) -> FileReport {
+    let mut warnings = Vec::new();
+    let outcome = build_file(filename, options, &mut warnings);
+    FileReport { warnings, outcome }
+}
+
+/// Read, parse and compile `filename`, returning what was written
+fn build_file(
+    filename: &str,
+    options: &Options,
+    warnings: &mut Vec<String>,
+) -> Result<Vec<String>, String> {
+    let source =
+        fs::read_to_string(filename).map_err(|err| format!("Error reading file: {}", err))?;
+    let (ast, spans) =
+        parse_brainfuck_spanned(&source).map_err(|err| format!("Parse error: {}", err))?;
+    if !options.no_warn {
+        *warnings = program_warnings(&ast, &spans);
+    }
+
+    let mut messages = Vec::new();
+    emit_program(&ast, filename, options, &mut messages)?;
+    Ok(messages)
+}
+
+/// Compile a parsed program into what `options.emit` asks for, adding a
+/// message for each file written to `messages`
+///
+/// Outputs are named after `filename`, or after [`STDIN_STEM`] when it is
+/// [`STDIN_FILENAME`], and written next to it unless `options.output` is
+/// set.
+fn emit_program(
+    ast: &[AstNode],
+    filename: &str,
+    options: &Options,
+    messages: &mut Vec<String>,
+) -> Result<(), String> {
+    let reads_stdin = filename == STDIN_FILENAME;
+    let stem_source = if reads_stdin { STDIN_STEM } else { filename };
+    let lamina_filename = generate_lamina_filename(stem_source);
+    let output_filename = options
+        .output
+        .clone()
+        .unwrap_or_else(|| match options.emit {
+            Emit::Ir => lamina_filename.clone(),
+            Emit::Asm => generate_assembly_filename(stem_source),
+            Emit::Bin if reads_stdin => STDIN_BINARY.to_string(),
+            Emit::Bin => generate_binary_filename(stem_source),
+        });
+
+    let config = options.config();
+    let write_output = |path: &str, contents: &str| {
+        prepare_output_path(Path::new(path))
+            .and_then(|()| fs::write(path, contents).map_err(|err| err.to_string()))
     };
+    match options.emit {
+        Emit::Ir => {
+            let ir_source = brainfuck_to_lamina_ir_with_config(ast, config)
+                .map_err(|err| format!("Lamina IR Generation Failed: {}", err))?;
+            write_output(&output_filename, &ir_source)
+                .map_err(|err| format!("Failed to save Lamina IR: {}", err))?;
+            messages.push(format!("Lamina IR saved to: {}", output_filename));
+        }
+        Emit::Asm => {
+            let assembly = brainfuck_to_assembly_with_config(ast, config)
+                .map_err(|err| format!("Assembly Generation Failed: {}", err))?;
+            write_output(&output_filename, &assembly)
+                .map_err(|err| format!("Failed to save assembly: {}", err))?;
+            messages.push(format!("Assembly saved to: {}", output_filename));
+        }
+        Emit::Bin => {
+            // Save the Lamina IR next to the source only when requested; the
+            // binary build generates (and cleans up) its own copy
+            if options.keep_ir {
+                let ir_source = brainfuck_to_lamina_ir_with_config(ast, config.clone())
+                    .map_err(|err| format!("Lamina IR Generation Failed: {}", err))?;
+                write_output(&lamina_filename, &ir_source)
+                    .map_err(|err| format!("Failed to save Lamina IR: {}", err))?;
+                messages.push(format!("Lamina IR saved to: {}", lamina_filename));
+            }
+            prepare_output_path(Path::new(&output_filename))?;
+            let result = brainfuck_to_binary_with_config(ast, &output_filename, config)
+                .map_err(|err| format!("Executable Generation Failed: {}", err))?;
+            messages.push(result);
+        }
+    }
+    Ok(())
+}
 
-    if parent.to_string_lossy().is_empty() {
-        binary_name
-    } else {
-        format!("{}/{}", parent.display(), binary_name)
+/// Compile every file in `options` on a pool of threads, printing each
+/// file's results in the order given and then a summary
+///
+/// Returns whether every file compiled.
+fn compile_batch(options: &Options) -> bool {
+    let filenames = &options.filenames;
+    let workers = thread::available_parallelism()
+        .map_or(1, usize::from)
+        .min(filenames.len());
+    let next_file = AtomicUsize::new(0);
+
+    let mut reports: Vec<(usize, FileReport)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut reports = Vec::new();
+                    loop {
+                        let index = next_file.fetch_add(1, Ordering::Relaxed);
+                        let Some(filename) = filenames.get(index) else {
+                            break;
+                        };
+                        reports.push((index, compile_file(filename, options)));
+                    }
+                    reports
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("compile worker panicked"))
+            .collect()
+    });
+    reports.sort_by_key(|(index, _)| *index);
+
+    let mut failed = 0;
+    for (index, report) in &reports {
+        let filename = &filenames[*index];
+        for warning in &report.warnings {
+            eprintln!("Warning: {}: {}", filename, warning);
+        }
+        match &report.outcome {
+            Ok(messages) => {
+                for message in messages {
+                    println!("{}: {}", filename, message);
+                }
+            }
+            Err(err) => {
+                failed += 1;
+                eprintln!("Error: {}: {}", filename, err);
+            }
+        }
     }
+    println!(
+        "Compiled {} of {} files ({} failed)",
+        filenames.len() - failed,
+        filenames.len(),
+        failed
+    );
+    failed == 0
 }
 
 fn main() {
-    let args: Vec<String> = env::args().collect();
+    let args: Vec<String> = env::args().skip(1).collect();
 
-    // Check for correct number of arguments
-    if args.len() != 2 {
-        eprintln!("Error: Expected exactly one argument (filename)");
-        print_usage();
-        process::exit(1);
+    let options = match parse_args(&args) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("Error: {}", err);
+            print_usage();
+            process::exit(1);
+        }
+    };
+
+    if options.filenames.len() > 1 {
+        let all_compiled = compile_batch(&options);
+        process::exit(if all_compiled { 0 } else { 1 });
     }
 
-    let filename = &args[1];
+    let reads_stdin = options.filenames[0] == STDIN_FILENAME;
+    // Name used in messages and, for files, to derive output names
+    let filename = if reads_stdin {
+        "<stdin>"
+    } else {
+        options.filenames[0].as_str()
+    };
 
     // Read the file
-    let source = match fs::read_to_string(filename) {
+    let source = if reads_stdin {
+        let mut source = String::new();
+        std::io::stdin().read_to_string(&mut source).map(|_| source)
+    } else {
+        fs::read_to_string(filename)
+    };
+    let source = match source {
         Ok(content) => content,
         Err(err) => {
             eprintln!("Error reading file '{}': {}", filename, err);
@@ -101,48 +510,87 @@ fn main() {
     };
 
     // Parse the Brainfuck code
-    let ast = match parse_brainfuck(&source) {
-        Ok(nodes) => nodes,
+    let (ast, spans) = match parse_brainfuck_spanned(&source) {
+        Ok(parsed) => parsed,
         Err(err) => {
             eprintln!("Parse error in '{}': {}", filename, err);
             process::exit(1);
         }
     };
 
-    // Generate Lamina IR Module
+    if !options.no_warn {
+        for warning in program_warnings(&ast, &spans) {
+            eprintln!("Warning: {}", warning);
+        }
+    }
 
-    let lamina_filename = generate_lamina_filename(filename);
+    if options.dump_ast {
+        // One line per command, never cut off, so the dump shows the
+        // program exactly as parsed
+        let opts = PrettyOpts {
+            max_width: usize::MAX,
+            group_runs: false,
+            ..PrettyOpts::default()
+        };
+        print!("{}", pretty_print(&ast, opts));
+        return;
+    }
 
-    // Generate and save Lamina IR to file first
-    match brainfuck_to_lamina_ir(&ast) {
-        Ok(ir_source) => {
-            match fs::write(&lamina_filename, &ir_source) {
-                Ok(_) => {
-                    //println!("Lamina IR saved to: {}", lamina_filename);
-                }
-                Err(err) => {
-                    println!("Failed to save Lamina IR: {}", err);
-                }
+    if options.stats {
+        let config = options.config();
+        let optimized = config.optimize_program(&config.wrap_program(&ast));
+        println!("Before optimization: {}", estimate_cost(&ast));
+        println!(
+            "After -O{}:           {}",
+            options.opt_level,
+            estimate_cost(&optimized)
+        );
+        return;
+    }
+
+    let stem_source = if reads_stdin { STDIN_STEM } else { filename };
+
+    if options.minify {
+        let minified = ast_to_brainfuck(&ast);
+        let output_filename = match options.output {
+            Some(output) => output,
+            None if options.emit_source => generate_sibling_filename(stem_source, ".min.bf"),
+            None => {
+                println!("{}", minified);
+                return;
             }
+        };
+        if let Err(err) = prepare_output_path(Path::new(&output_filename))
+            .and_then(|()| fs::write(&output_filename, &minified).map_err(|err| err.to_string()))
+        {
+            eprintln!("Failed to save minified source: {}", err);
+            process::exit(1);
         }
-        Err(err) => {
-            println!("Lamina IR Generation Failed: {}", err);
-        }
+        println!("Minified source saved to: {}", output_filename);
+        return;
     }
 
-    // Generate executable using Lamina toolchain
-    let binary_filename = generate_binary_filename(filename);
-    match brainfuck_to_binary(&ast, &binary_filename) {
-        Ok(result) => {
-            println!("{}", result);
+    if options.interpret {
+        let config = BrainfuckConfig::default();
+        let mut stdin = std::io::stdin().lock();
+        let mut stdout = std::io::stdout().lock();
+        if let Err(err) = interpreter::run(&ast, &config, &mut stdin, &mut stdout) {
+            eprintln!("Runtime error in '{}': {}", filename, err);
+            process::exit(1);
         }
-        Err(err) => {
-            println!("Executable Generation Failed: {}", err);
-            println!("Lamina IR is saved at: {}", lamina_filename);
-            println!(
-                "Try manual compilation: lamina {} -o {}",
-                lamina_filename, binary_filename
-            );
+        return;
+    }
+
+    let mut messages = Vec::new();
+    let result = emit_program(&ast, &options.filenames[0], &options, &mut messages);
+    for message in &messages {
+        println!("{}", message);
+    }
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        if options.emit == Emit::Bin && !options.keep_ir {
+            eprintln!("Re-run with --keep-ir to keep the generated Lamina IR");
         }
+        process::exit(1);
     }
 }
@@ -0,0 +1,172 @@
+//! Interpreter output written into a memory-mapped file
+//!
+//! Enabled by the `mmap` feature. Programs that generate large binary
+//! output, such as image generators, write each byte straight into the
+//! mapped file instead of copying it through a `Write` buffer and a `write`
+//! call.
+
+use crate::interpreter::{Interpreter, Result};
+use crate::lamina_builder::BrainfuckConfig;
+use crate::lexer::AstNode;
+use memmap2::MmapMut;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+/// Smallest mapping created for a new or empty file
+const MIN_CAPACITY: u64 = 64 * 1024;
+
+/// Run a Brainfuck program, writing its output to the file at `output_path`
+///
+/// The file is created if needed and overwritten otherwise. A file that
+/// already exists is mapped at its current size, so pre-sizing it to the
+/// expected output avoids remapping; the mapping grows (doubling) whenever
+/// the output outgrows it. Once the program ends the file is truncated to
+/// the output's length, which is returned.
+pub fn interpret_to_file(
+    ast: &[AstNode],
+    input: &mut impl Read,
+    output_path: impl AsRef<Path>,
+    config: &BrainfuckConfig,
+) -> Result<u64> {
+    let mut output = MappedFile::open(output_path.as_ref(), MIN_CAPACITY)?;
+    let result = Interpreter::new(ast, config)
+        .and_then(|mut interpreter| interpreter.run(input, &mut output));
+    // Trim the file even if the program failed, so it holds exactly the
+    // output written so far
+    let len = output.finish()?;
+    result.map(|_| len)
+}
+
+/// Output file that is written through a writable memory mapping
+struct MappedFile {
+    file: File,
+    map: MmapMut,
+    /// Number of bytes written so far
+    len: u64,
+}
+
+impl MappedFile {
+    fn open(path: &Path, min_capacity: u64) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        let capacity = file.metadata()?.len().max(min_capacity);
+        file.set_len(capacity)?;
+        let map = map(&file)?;
+        Ok(Self { file, map, len: 0 })
+    }
+
+    /// Grow the file and its mapping to hold at least `needed` bytes
+    fn grow(&mut self, needed: u64) -> io::Result<()> {
+        let mut capacity = self.map.len() as u64;
+        while capacity < needed {
+            capacity *= 2;
+        }
+        self.map.flush()?;
+        self.file.set_len(capacity)?;
+        self.map = map(&self.file)?;
+        Ok(())
+    }
+
+    /// Flush the mapping and truncate the file to the bytes written
+    fn finish(self) -> io::Result<u64> {
+        self.map.flush()?;
+        drop(self.map);
+        self.file.set_len(self.len)?;
+        Ok(self.len)
+    }
+}
+
+fn map(file: &File) -> io::Result<MmapMut> {
+    // SAFETY: the file was opened by this module and is only accessed
+    // through this mapping until it is dropped. Another process modifying
+    // the file concurrently could still change the mapped bytes, which
+    // would only corrupt the output, not this process's memory.
+    unsafe { MmapMut::map_mut(file) }
+}
+
+impl Write for MappedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let end = self.len + buf.len() as u64;
+        if end > self.map.len() as u64 {
+            self.grow(end)?;
+        }
+        self.map[self.len as usize..end as usize].copy_from_slice(buf);
+        self.len = end;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.map.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::run;
+    use crate::lexer::parse_brainfuck;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("bfina-mapped-{}-{}", std::process::id(), name))
+    }
+
+    /// Prints every byte value 16 times over, 4 KiB in total
+    const PROGRAM: &str = "++++++++++++++++[>.+[.+]<-]";
+
+    fn expected_output() -> Vec<u8> {
+        let mut output = Vec::new();
+        let ast = parse_brainfuck(PROGRAM).unwrap();
+        run(
+            &ast,
+            &BrainfuckConfig::default(),
+            &mut io::empty(),
+            &mut output,
+        )
+        .unwrap();
+        output
+    }
+
+    #[test]
+    fn test_output_is_written_to_file() {
+        let path = temp_path("output");
+        let ast = parse_brainfuck(PROGRAM).unwrap();
+        let len =
+            interpret_to_file(&ast, &mut io::empty(), &path, &BrainfuckConfig::default()).unwrap();
+
+        let written = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(len, 4096);
+        assert_eq!(written, expected_output());
+    }
+
+    #[test]
+    fn test_presized_file_is_truncated() {
+        let path = temp_path("presized");
+        std::fs::write(&path, vec![0xAA; 100_000]).unwrap();
+        let ast = parse_brainfuck(PROGRAM).unwrap();
+        interpret_to_file(&ast, &mut io::empty(), &path, &BrainfuckConfig::default()).unwrap();
+
+        let written = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(written, expected_output());
+    }
+
+    #[test]
+    fn test_mapping_grows_with_output() {
+        let path = temp_path("grow");
+        let mut output = MappedFile::open(&path, 10).unwrap();
+        output.write_all(&expected_output()).unwrap();
+        output.write_all(b"!").unwrap();
+        assert_eq!(output.finish().unwrap(), 4097);
+
+        let written = std::fs::read(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(&written[..4096], expected_output());
+        assert_eq!(written[4096], b'!');
+    }
+}
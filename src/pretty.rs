@@ -0,0 +1,180 @@
+//! Human-readable rendering of Brainfuck ASTs
+//!
+//! [`pretty_print`] lists one node per line, with loop bodies indented, so
+//! the structure of a program (and what the optimizer made of it) can be
+//! read at a glance.
+
+use crate::lamina_builder::optimize::lowered_ast_to_source;
+use crate::lexer::{AstNode, Command};
+
+/// Options for [`pretty_print`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PrettyOpts {
+    /// Longest line, in characters, before it is cut off with `...`
+    pub max_width: usize,
+    /// Show a run of the same command as one line (`Increment x3 (+++)`)
+    /// instead of one line per command
+    pub group_runs: bool,
+    /// Spaces of indentation per loop level
+    pub indent: usize,
+}
+
+impl Default for PrettyOpts {
+    fn default() -> Self {
+        Self {
+            max_width: 80,
+            group_runs: true,
+            indent: 2,
+        }
+    }
+}
+
+/// Render an AST as indented text, one node per line
+///
+/// Each line names the node and shows the Brainfuck it stands for, so
+/// optimized nodes read as `Move +2 (>>)` or `SetZero ([-])`.
+pub fn pretty_print(ast: &[AstNode], opts: PrettyOpts) -> String {
+    let mut out = String::new();
+    render(ast, &opts, 0, &mut out);
+    out
+}
+
+fn render(nodes: &[AstNode], opts: &PrettyOpts, depth: usize, out: &mut String) {
+    let mut i = 0;
+
+    while i < nodes.len() {
+        let node = &nodes[i];
+        i += 1;
+
+        let text = match node {
+            AstNode::Command(cmd) => {
+                let mut run = 1;
+                if opts.group_runs {
+                    while nodes.get(i) == Some(node) {
+                        run += 1;
+                        i += 1;
+                    }
+                }
+                let source = lowered_ast_to_source(&vec![node.clone(); run]);
+                if run == 1 {
+                    format!("{} ({})", command_name(*cmd), source)
+                } else {
+                    format!("{} x{} ({})", command_name(*cmd), run, source)
+                }
+            }
+            AstNode::Loop(body) => {
+                push_line(out, "Loop [", opts, depth);
+                render(body, opts, depth + 1, out);
+                push_line(out, "]", opts, depth);
+                continue;
+            }
+            AstNode::SetZero => "SetZero".to_string(),
+            AstNode::Add(delta) => format!("Add {:+}", delta),
+            AstNode::Move(delta) => format!("Move {:+}", delta),
+            AstNode::OutputAtOffset(offset) => format!("OutputAtOffset {:+}", offset),
+            AstNode::InputAtOffset(offset) => format!("InputAtOffset {:+}", offset),
+            AstNode::CopyAndClear(offset) => format!("CopyAndClear {:+}", offset),
+            AstNode::MultiplyLoop(targets) => {
+                let targets: Vec<String> = targets
+                    .iter()
+                    .map(|target| format!("{:+}*{}", target.offset, target.factor))
+                    .collect();
+                format!("MultiplyLoop {}", targets.join(" "))
+            }
+        };
+
+        let text = match node {
+            AstNode::Command(_) => text,
+            _ => format!(
+                "{} ({})",
+                text,
+                lowered_ast_to_source(std::slice::from_ref(node))
+            ),
+        };
+        push_line(out, &text, opts, depth);
+    }
+}
+
+/// Append an indented line, cutting it off at `max_width`
+fn push_line(out: &mut String, text: &str, opts: &PrettyOpts, depth: usize) {
+    let line = format!("{}{}", " ".repeat(depth * opts.indent), text);
+    if line.chars().count() > opts.max_width && opts.max_width > 3 {
+        out.extend(line.chars().take(opts.max_width - 3));
+        out.push_str("...");
+    } else {
+        out.push_str(&line);
+    }
+    out.push('\n');
+}
+
+fn command_name(cmd: Command) -> &'static str {
+    match cmd {
+        Command::Right => "Right",
+        Command::Left => "Left",
+        Command::Increment => "Increment",
+        Command::Decrement => "Decrement",
+        Command::Output => "Output",
+        Command::Input => "Input",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lamina_builder::optimize::optimize_ast;
+    use crate::lexer::parse_brainfuck;
+
+    const PROGRAM: &str = "++++[>++++++++<-]>+.>>[-]<<[->>+<<]";
+
+    #[test]
+    fn test_grouped_output() {
+        let ast = parse_brainfuck(PROGRAM).unwrap();
+        assert_eq!(
+            pretty_print(&ast, PrettyOpts::default()),
+            "\
+Increment x4 (++++)
+Loop [
+  Right (>)
+  Increment x8 (++++++++)
+  Left (<)
+  Decrement (-)
+]
+Right (>)
+Increment (+)
+Output (.)
+Right x2 (>>)
+Loop [
+  Decrement (-)
+]
+Left x2 (<<)
+Loop [
+  Decrement (-)
+  Right x2 (>>)
+  Increment (+)
+  Left x2 (<<)
+]
+"
+        );
+    }
+
+    #[test]
+    fn test_ungrouped_narrow_output() {
+        let ast = optimize_ast(&parse_brainfuck(PROGRAM).unwrap());
+        let opts = PrettyOpts {
+            max_width: 20,
+            group_runs: false,
+            indent: 4,
+        };
+        assert_eq!(
+            pretty_print(&ast, opts),
+            "\
+Add +4 (++++)
+MultiplyLoop +1*8...
+Move +1 (>)
+Add +1 (+)
+Output (.)
+CopyAndClear +2 (...
+"
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! Running programs without choosing a backend
+//!
+//! [`auto_run`] interprets small programs, which finish before a compiler
+//! could even start, and compiles large looping programs, where the faster
+//! code pays back the cost of building a binary.
+
+use crate::interpreter::{RunError, RuntimeError, run};
+use crate::lamina_builder::compiler::create_build_dir;
+use crate::lamina_builder::utils::count_operations;
+use crate::lamina_builder::{BrainfuckConfig, CompileError, TapeModel, compile_instrumented};
+use crate::lexer::{AstNode, parse_brainfuck};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Distinguishes the binaries of concurrent calls within one process
+static NEXT_BINARY: AtomicUsize = AtomicUsize::new(0);
+
+/// How [`auto_run`] ran a program
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunPath {
+    /// The program ran in the interpreter
+    Interpreted,
+    /// The program was compiled to a temporary binary, which was then run
+    Compiled,
+}
+
+/// Decide how [`auto_run`] would run a program
+///
+/// Programs without loops run in time proportional to their length, so
+/// they are always interpreted. Looping programs are compiled once they
+/// have at least `config.auto_compile_threshold` commands and loops, unless
+/// they need a [`TapeModel::Growable`] tape, which only the interpreter has.
+pub fn choose_run_path(ast: &[AstNode], config: &BrainfuckConfig) -> RunPath {
+    let (commands, loops) = count_operations(ast);
+    if config.tape_model == TapeModel::Fixed
+        && loops > 0
+        && commands + loops >= config.auto_compile_threshold
+    {
+        RunPath::Compiled
+    } else {
+        RunPath::Interpreted
+    }
+}
+
+/// Parse and run Brainfuck source, interpreting or compiling it as
+/// [`choose_run_path`] decides
+///
+/// Returns the path that was taken. A compiled program is given all of
+/// `input` up front, and its output is written to `output` once it exits.
+pub fn auto_run(
+    source: &str,
+    mut input: impl Read,
+    mut output: impl Write,
+    config: &BrainfuckConfig,
+) -> Result<RunPath, RunError> {
+    let ast = parse_brainfuck(source)?;
+    let path = choose_run_path(&ast, config);
+
+    match path {
+        RunPath::Interpreted => run(&ast, config, &mut input, &mut output)?,
+        RunPath::Compiled => {
+            let mut bytes = Vec::new();
+            input.read_to_end(&mut bytes).map_err(RuntimeError::from)?;
+            let stdout = run_compiled(&ast, &bytes, config).map_err(RunError::Compile)?;
+            output.write_all(&stdout).map_err(RuntimeError::from)?;
+        }
+    }
+
+    Ok(path)
+}
+
+/// Compile a program to a temporary binary, run it on `input`, and return
+/// its standard output and exit code
+///
+/// The binary is built with [`compile_instrumented`] in the configured
+/// build directory and removed once it has run. A program killed by a signal, such as a failed runtime bounds
+/// check, is reported as [`CompileError::Terminated`].
+pub fn compile_and_run(
+    ast: &[AstNode],
+    input: &[u8],
+    config: BrainfuckConfig,
+) -> Result<(Vec<u8>, i32), CompileError> {
+    let binary = temp_binary_path(&config)?;
+    let binary_name = binary.to_string_lossy().into_owned();
+    let result =
+        compile_instrumented(ast, config, &binary_name).and_then(|_| run_binary(&binary, input));
+    let _ = std::fs::remove_file(&binary);
+    result
+}
+
+/// Compile and run a program, returning only its standard output
+pub(crate) fn run_compiled(
+    ast: &[AstNode],
+    input: &[u8],
+    config: &BrainfuckConfig,
+) -> Result<Vec<u8>, String> {
+    compile_and_run(ast, input, config.clone())
+        .map(|(stdout, _)| stdout)
+        .map_err(|e| e.to_string())
+}
+
+/// Run a binary with `input` on its standard input, returning its standard
+/// output and exit code
+///
+/// A binary killed by a signal has no exit code, and whatever it wrote
+/// before then is not a result, so that is an error.
+fn run_binary(binary: &Path, input: &[u8]) -> Result<(Vec<u8>, i32), CompileError> {
+    let mut child = Command::new(binary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+
+    // A program that exits without reading all its input closes the pipe,
+    // which is not an error
+    let _ = child.stdin.take().unwrap().write_all(input);
+    let output = child.wait_with_output()?;
+    match output.status.code() {
+        Some(code) => Ok((output.stdout, code)),
+        None => Err(CompileError::Terminated(output.status.to_string())),
+    }
+}
+
+/// Path for a binary built by [`compile_and_run`], creating the build
+/// directory if needed
+///
+/// The binary goes in [`BrainfuckConfig::resolved_build_dir`] with the
+/// intermediate files, rather than straight in the shared temp directory.
+fn temp_binary_path(config: &BrainfuckConfig) -> std::io::Result<PathBuf> {
+    let build_dir = config.resolved_build_dir();
+    create_build_dir(&build_dir)?;
+    let id = NEXT_BINARY.fetch_add(1, Ordering::Relaxed);
+    Ok(build_dir.join(format!("bfina-run-{}-{}", std::process::id(), id)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tape::PointerMode;
+
+    #[test]
+    fn test_run_path_follows_program_size() {
+        let config = BrainfuckConfig {
+            auto_compile_threshold: 15,
+            ..BrainfuckConfig::default()
+        };
+
+        let small = parse_brainfuck("++[>+<-]>.").unwrap();
+        assert_eq!(choose_run_path(&small, &config), RunPath::Interpreted);
+
+        let large = parse_brainfuck(&"++[>+<-]>.".repeat(2)).unwrap();
+        assert_eq!(choose_run_path(&large, &config), RunPath::Compiled);
+
+        // Without loops the program can't run for long, however long it is
+        let straight = parse_brainfuck(&"+".repeat(100)).unwrap();
+        assert_eq!(choose_run_path(&straight, &config), RunPath::Interpreted);
+    }
+
+    #[test]
+    fn test_small_program_is_interpreted() {
+        let mut output = Vec::new();
+        let path = auto_run(
+            ",[.[-],]",
+            &b"hi"[..],
+            &mut output,
+            &BrainfuckConfig::default(),
+        )
+        .unwrap();
+        assert_eq!(path, RunPath::Interpreted);
+        assert_eq!(output, b"hi");
+    }
+
+    #[test]
+    fn test_killed_compiled_program_is_an_error() {
+        if Command::new("gcc").arg("--version").output().is_err() {
+            eprintln!("gcc not available, skipping");
+            return;
+        }
+
+        let config = BrainfuckConfig {
+            tape_size: 4,
+            runtime_bounds_check: Some(PointerMode::Trap),
+            auto_compile_threshold: 1,
+            ..BrainfuckConfig::default()
+        };
+        let mut output = Vec::new();
+        let result = auto_run("+.[>+]", &b""[..], &mut output, &config);
+        assert!(
+            matches!(&result, Err(RunError::Compile(msg)) if msg.contains("terminated abnormally")),
+            "{result:?}"
+        );
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_binary_is_built_in_the_build_dir() {
+        let build_dir =
+            std::env::temp_dir().join(format!("bfina-runner-test-{}", std::process::id()));
+        let config = BrainfuckConfig {
+            build_dir: Some(build_dir.clone()),
+            ..BrainfuckConfig::default()
+        };
+        let binary = temp_binary_path(&config).unwrap();
+        assert_eq!(binary.parent(), Some(build_dir.as_path()));
+        assert!(build_dir.is_dir());
+        std::fs::remove_dir(&build_dir).unwrap();
+    }
+
+    #[test]
+    fn test_exit_from_cell_needs_an_int_main() {
+        let ast = parse_brainfuck("+++").unwrap();
+        let config = BrainfuckConfig {
+            exit_from_cell: true,
+            emit_int_main: false,
+            ..BrainfuckConfig::default()
+        };
+        assert!(matches!(
+            compile_and_run(&ast, b"", config),
+            Err(CompileError::IrGeneration(msg)) if msg.contains("int main")
+        ));
+    }
+}
@@ -0,0 +1,246 @@
+//! Tape storage shared by the interpreter and compile-time evaluation
+//!
+//! A [`Tape`] is a number of 8-bit cells, which some tapes can extend on
+//! request. Tapes only take indices on the tape: a pointer that may have
+//! left it is brought back with [`PointerMode::resolve`], the one place
+//! that decides what an index off the tape means.
+
+use std::collections::HashMap;
+
+/// How an index that is off the tape is handled
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PointerMode {
+    /// Treat it as an error
+    #[default]
+    Trap,
+    /// Wrap around to the other end of the tape
+    Wrap,
+    /// Use the first or last cell, whichever end it is past
+    Clamp,
+}
+
+impl PointerMode {
+    /// Map `index` onto a tape of `len` cells
+    ///
+    /// Returns `None` if the index is off the tape and this is
+    /// [`PointerMode::Trap`], or the tape has no cells.
+    pub fn resolve(self, index: isize, len: usize) -> Option<usize> {
+        if len == 0 {
+            return None;
+        }
+        if let Ok(index) = usize::try_from(index)
+            && index < len
+        {
+            return Some(index);
+        }
+        match self {
+            PointerMode::Trap => None,
+            PointerMode::Wrap => Some(index.rem_euclid(len as isize) as usize),
+            PointerMode::Clamp if index < 0 => Some(0),
+            PointerMode::Clamp => Some(len - 1),
+        }
+    }
+}
+
+/// Storage for the cells of a Brainfuck tape
+///
+/// `index` is always less than [`Tape::len`]; implementations may panic
+/// otherwise.
+pub trait Tape {
+    /// Value of the cell at `index`
+    fn get(&self, index: usize) -> u8;
+
+    /// Store `value` in the cell at `index`
+    fn set(&mut self, index: usize, value: u8);
+
+    /// Number of cells on the tape
+    fn len(&self) -> usize;
+
+    /// Returns true if the tape has no cells
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Extend the tape to `len` cells, all of them zero
+    ///
+    /// Returns false if the tape can't grow, which is the default.
+    fn grow(&mut self, _len: usize) -> bool {
+        false
+    }
+}
+
+/// Tape that allocates every cell up front
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedTape {
+    cells: Vec<u8>,
+}
+
+impl FixedTape {
+    /// Create a zeroed tape of `len` cells
+    pub fn new(len: usize) -> Self {
+        Self {
+            cells: vec![0; len],
+        }
+    }
+
+    /// All cells, in order
+    pub fn as_slice(&self) -> &[u8] {
+        &self.cells
+    }
+}
+
+impl Tape for FixedTape {
+    fn get(&self, index: usize) -> u8 {
+        self.cells[index]
+    }
+
+    fn set(&mut self, index: usize, value: u8) {
+        self.cells[index] = value;
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn grow(&mut self, len: usize) -> bool {
+        self.cells.resize(len, 0);
+        true
+    }
+}
+
+/// Tape that only stores nonzero cells
+///
+/// Memory use grows with the number of cells the program actually touches,
+/// so very large tapes cost nothing until they are used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SparseTape {
+    cells: HashMap<usize, u8>,
+    len: usize,
+}
+
+impl SparseTape {
+    /// Create a zeroed tape of `len` cells
+    pub fn new(len: usize) -> Self {
+        Self {
+            cells: HashMap::new(),
+            len,
+        }
+    }
+
+    /// Number of cells currently holding a nonzero value
+    pub fn nonzero_cells(&self) -> usize {
+        self.cells.len()
+    }
+
+    fn check_index(&self, index: usize) {
+        assert!(
+            index < self.len,
+            "tape index {} is out of bounds for a tape of {} cells",
+            index,
+            self.len
+        );
+    }
+}
+
+impl Tape for SparseTape {
+    fn get(&self, index: usize) -> u8 {
+        self.check_index(index);
+        self.cells.get(&index).copied().unwrap_or(0)
+    }
+
+    fn set(&mut self, index: usize, value: u8) {
+        self.check_index(index);
+        if value == 0 {
+            self.cells.remove(&index);
+        } else {
+            self.cells.insert(index, value);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn grow(&mut self, len: usize) -> bool {
+        self.len = len;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pointer_mode_boundaries() {
+        for mode in [PointerMode::Trap, PointerMode::Wrap, PointerMode::Clamp] {
+            assert_eq!(mode.resolve(0, 4), Some(0));
+            assert_eq!(mode.resolve(3, 4), Some(3));
+            assert_eq!(mode.resolve(0, 0), None);
+        }
+
+        assert_eq!(PointerMode::Trap.resolve(-1, 4), None);
+        assert_eq!(PointerMode::Trap.resolve(4, 4), None);
+
+        assert_eq!(PointerMode::Wrap.resolve(-1, 4), Some(3));
+        assert_eq!(PointerMode::Wrap.resolve(4, 4), Some(0));
+        assert_eq!(PointerMode::Wrap.resolve(-9, 4), Some(3));
+
+        assert_eq!(PointerMode::Clamp.resolve(-1, 4), Some(0));
+        assert_eq!(PointerMode::Clamp.resolve(isize::MIN, 4), Some(0));
+        assert_eq!(PointerMode::Clamp.resolve(isize::MAX, 4), Some(3));
+    }
+
+    /// Check the boundary behavior shared by every tape implementation
+    fn check_boundaries(mut tape: Box<dyn Tape>) {
+        assert_eq!(tape.len(), 4);
+        tape.set(0, 9);
+        tape.set(3, 7);
+        assert_eq!(tape.get(0), 9);
+        assert_eq!(tape.get(3), 7);
+        assert_eq!(tape.get(1), 0);
+
+        let off_tape = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| tape.get(4)));
+        assert!(off_tape.is_err());
+    }
+
+    #[test]
+    fn test_fixed_tape_boundaries() {
+        check_boundaries(Box::new(FixedTape::new(4)));
+
+        let mut tape = FixedTape::new(3);
+        tape.set(2, 1);
+        assert_eq!(tape.as_slice(), [0, 0, 1]);
+    }
+
+    #[test]
+    fn test_sparse_tape_boundaries() {
+        check_boundaries(Box::new(SparseTape::new(4)));
+    }
+
+    #[test]
+    fn test_tapes_grow_with_zeroed_cells() {
+        let mut tape = FixedTape::new(2);
+        tape.set(1, 4);
+        assert!(tape.grow(5));
+        assert_eq!(tape.as_slice(), [0, 4, 0, 0, 0]);
+
+        let mut tape = SparseTape::new(2);
+        assert!(tape.grow(1 << 40));
+        tape.set((1 << 40) - 1, 1);
+        assert_eq!(tape.len(), 1 << 40);
+    }
+
+    #[test]
+    fn test_sparse_tape_only_stores_nonzero_cells() {
+        let mut tape = SparseTape::new(1 << 40);
+        tape.set(1 << 39, 3);
+        tape.set(12, 1);
+        assert_eq!(tape.nonzero_cells(), 2);
+        assert_eq!(tape.get(1 << 39), 3);
+
+        tape.set(12, 0);
+        assert_eq!(tape.nonzero_cells(), 1);
+        assert_eq!(tape.get(12), 0);
+    }
+}
@@ -0,0 +1,74 @@
+//! Helpers for differential testing of Brainfuck programs
+//!
+//! Enabled by the `testing` feature. The helpers run a program through the
+//! interpreter and as a compiled binary, and fail loudly when the two
+//! disagree, so downstream crates can check their own programs the same way
+//! this crate checks its corpus.
+
+use crate::interpreter::run_source;
+use crate::lamina_builder::BrainfuckConfig;
+use crate::lexer::parse_brainfuck;
+use crate::runner::run_compiled;
+
+/// Run `source` through the interpreter and as a compiled binary, and panic
+/// if their outputs differ
+///
+/// Both runs read `input` and use `config`. The panic message shows where
+/// the outputs first diverge. Parse, interpreter, and compile errors panic
+/// as well, since the program can't be compared in that case.
+pub fn assert_compiled_matches_interpreted(source: &str, input: &[u8], config: &BrainfuckConfig) {
+    let mut interpreted = Vec::new();
+    if let Err(err) = run_source(source, input, &mut interpreted, config) {
+        panic!("Interpreter failed: {}", err);
+    }
+
+    let compiled = parse_brainfuck(source)
+        .map_err(|e| e.to_string())
+        .and_then(|ast| run_compiled(&ast, input, config))
+        .unwrap_or_else(|err| panic!("Compiled program failed: {}", err));
+
+    if let Some(diff) = describe_difference(&interpreted, &compiled) {
+        panic!("Compiled output differs from the interpreter:\n{}", diff);
+    }
+}
+
+/// Describe how `actual` differs from `expected`, or `None` if they are equal
+fn describe_difference(expected: &[u8], actual: &[u8]) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+
+    let offset = expected
+        .iter()
+        .zip(actual)
+        .position(|(a, b)| a != b)
+        .unwrap_or(expected.len().min(actual.len()));
+    let line = expected[..offset].iter().filter(|&&b| b == b'\n').count() + 1;
+
+    Some(format!(
+        "first difference at byte {} (line {})\n  interpreted ({} bytes): {:?}\n  compiled    ({} bytes): {:?}",
+        offset,
+        line,
+        expected.len(),
+        String::from_utf8_lossy(expected),
+        actual.len(),
+        String::from_utf8_lossy(actual),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_describe_difference() {
+        assert_eq!(describe_difference(b"same", b"same"), None);
+
+        let diff = describe_difference(b"ab\ncd", b"ab\nxd").unwrap();
+        assert!(diff.starts_with("first difference at byte 3 (line 2)"));
+
+        let diff = describe_difference(b"abc", b"ab").unwrap();
+        assert!(diff.starts_with("first difference at byte 2 (line 1)"));
+        assert!(diff.contains("compiled    (2 bytes): \"ab\""));
+    }
+}
@@ -1,3 +1,5 @@
+use crate::lexer::Command;
+
 /// Represents a single Brainfuck token/command
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Token {
@@ -55,8 +57,135 @@ impl Token {
     }
 }
 
+impl From<Command> for Token {
+    fn from(command: Command) -> Self {
+        match command {
+            Command::Right => Token::Right,
+            Command::Left => Token::Left,
+            Command::Increment => Token::Increment,
+            Command::Decrement => Token::Decrement,
+            Command::Output => Token::Output,
+            Command::Input => Token::Input,
+        }
+    }
+}
+
 impl std::fmt::Display for Token {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}", self.as_char())
     }
 }
+
+/// Iterator over the tokens of Brainfuck source
+///
+/// Tokens are produced lazily, comments are skipped, and the last token is
+/// always a single [`Token::Eof`]. Brackets aren't checked for balance; see
+/// [`parse_brainfuck`](crate::lexer::parse_brainfuck) for that.
+#[derive(Debug, Clone)]
+pub struct Tokenizer<'a> {
+    chars: std::str::Chars<'a>,
+    done: bool,
+}
+
+impl<'a> Tokenizer<'a> {
+    /// Create a tokenizer over `source`
+    pub fn new(source: &'a str) -> Self {
+        Self {
+            chars: source.chars(),
+            done: false,
+        }
+    }
+}
+
+impl Iterator for Tokenizer<'_> {
+    type Item = Token;
+
+    fn next(&mut self) -> Option<Token> {
+        if self.done {
+            return None;
+        }
+
+        match self.chars.find_map(Token::from_char) {
+            Some(token) => Some(token),
+            None => {
+                self.done = true;
+                Some(Token::Eof)
+            }
+        }
+    }
+}
+
+impl std::iter::FusedIterator for Tokenizer<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_commands_round_trip() {
+        let commands = [
+            Command::Right,
+            Command::Left,
+            Command::Increment,
+            Command::Decrement,
+            Command::Output,
+            Command::Input,
+        ];
+        for command in commands {
+            let token = Token::from(command);
+            assert_eq!(Command::from_token(token), Some(command));
+            assert_eq!(token.as_char(), command.as_char());
+            assert_eq!(Command::from_char(command.as_char()), Some(command));
+            assert_eq!(Token::from_char(command.as_char()), Some(token));
+        }
+
+        for token in [Token::LoopStart, Token::LoopEnd, Token::Eof] {
+            assert_eq!(Command::from_token(token), None);
+        }
+        assert_eq!(Command::from_char('['), None);
+        assert_eq!(Command::from_char('x'), None);
+    }
+
+    #[test]
+    fn test_tokenizer_skips_comments() {
+        let tokens: Vec<Token> = Tokenizer::new("add 2: ++ [->+<] done.\n").collect();
+        assert_eq!(
+            tokens,
+            [
+                Token::Increment,
+                Token::Increment,
+                Token::LoopStart,
+                Token::Decrement,
+                Token::Right,
+                Token::Increment,
+                Token::Left,
+                Token::LoopEnd,
+                Token::Output,
+                Token::Eof,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenizer_ends_with_one_eof() {
+        assert_eq!(Tokenizer::new("").collect::<Vec<_>>(), [Token::Eof]);
+        assert_eq!(
+            Tokenizer::new("no commands here").collect::<Vec<_>>(),
+            [Token::Eof]
+        );
+
+        let mut tokenizer = Tokenizer::new(",");
+        assert_eq!(tokenizer.next(), Some(Token::Input));
+        assert_eq!(tokenizer.next(), Some(Token::Eof));
+        assert_eq!(tokenizer.next(), None);
+        assert_eq!(tokenizer.next(), None);
+    }
+
+    #[test]
+    fn test_tokenizer_is_lazy() {
+        // Only as much source as needed is read
+        let mut tokenizer = Tokenizer::new("+ comment ]");
+        assert_eq!(tokenizer.next(), Some(Token::Increment));
+        assert_eq!(tokenizer.chars.as_str(), " comment ]");
+    }
+}
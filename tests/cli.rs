@@ -0,0 +1,387 @@
+//! End-to-end tests for the `bfina` command-line driver
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Create a fresh scratch directory for a single test
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("bfina-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("failed to create scratch directory");
+    dir
+}
+
+#[test]
+fn test_default_build_leaves_no_lamina_file() {
+    let dir = scratch_dir("no-lamina");
+    let source = dir.join("prog.bf");
+    fs::write(&source, "++++++++[>++++++++<-]>+.").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_bfina"))
+        .arg(&source)
+        .status()
+        .expect("failed to run bfina");
+    assert!(status.success());
+    assert!(!dir.join("prog.lamina").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_keep_ir_keeps_lamina_file() {
+    let dir = scratch_dir("keep-ir");
+    let source = dir.join("prog.bf");
+    fs::write(&source, "+.").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_bfina"))
+        .arg("--keep-ir")
+        .arg(&source)
+        .status()
+        .expect("failed to run bfina");
+    assert!(status.success());
+    assert!(dir.join("prog.lamina").exists());
+    assert!(dir.join("prog").exists());
+    fs::remove_file(dir.join("prog.lamina")).unwrap();
+
+    // The old spelling still works, with a warning
+    let output = Command::new(env!("CARGO_BIN_EXE_bfina"))
+        .arg("--emit-ir")
+        .arg(&source)
+        .output()
+        .expect("failed to run bfina");
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--emit-ir is deprecated"));
+    assert!(dir.join("prog.lamina").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_build_dir_holds_intermediates() {
+    let dir = scratch_dir("build-dir");
+    let source = dir.join("prog.bf");
+    let build_dir = dir.join("build");
+    fs::write(&source, "+.").unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_bfina"))
+        .arg("--build-dir")
+        .arg(&build_dir)
+        .arg(&source)
+        .status()
+        .expect("failed to run bfina");
+    assert!(status.success());
+    assert!(build_dir.is_dir());
+    assert!(dir.join("prog").exists());
+    assert!(!dir.join("prog.s").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_interpret_runs_without_compiling() {
+    let dir = scratch_dir("interpret");
+    let source = dir.join("prog.bf");
+    fs::write(&source, ",[.[-],]").unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_bfina"))
+        .arg("--interpret")
+        .arg(&source)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to run bfina");
+    child.stdin.take().unwrap().write_all(b"echo").unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert!(output.status.success());
+    assert_eq!(output.stdout, b"echo");
+    assert!(!dir.join("prog").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_emit_selects_the_output() {
+    let dir = scratch_dir("emit");
+    let source = dir.join("prog.bf");
+    fs::write(&source, "+.").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_bfina"))
+            .args(args)
+            .arg(&source)
+            .output()
+            .expect("failed to run bfina")
+    };
+
+    let output = run(&["--emit=ir"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Lamina IR saved to"));
+    assert!(dir.join("prog.lamina").exists());
+    assert!(!dir.join("prog").exists());
+
+    let output = run(&["--emit", "asm"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Assembly saved to"));
+    assert!(
+        fs::read_to_string(dir.join("prog.s"))
+            .unwrap()
+            .contains("main")
+    );
+    assert!(!dir.join("prog").exists());
+
+    assert!(run(&["--emit=bin"]).status.success());
+    assert!(dir.join("prog").exists());
+
+    assert!(!run(&["--emit=obj"]).status.success());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_opt_level_flag_shrinks_the_ir() {
+    let dir = scratch_dir("opt-level");
+    let source = dir.join("prog.bf");
+    fs::write(&source, "++++++++[>++++++++<-]>+.[-]").unwrap();
+    let ir_lines = |level: &str| {
+        let output = dir.join(format!("prog{}.lamina", level));
+        let status = Command::new(env!("CARGO_BIN_EXE_bfina"))
+            .args([level, "--emit=ir", "-o"])
+            .arg(&output)
+            .arg(&source)
+            .status()
+            .expect("failed to run bfina");
+        assert!(status.success());
+        fs::read_to_string(output).unwrap().lines().count()
+    };
+
+    assert!(ir_lines("-O3") < ir_lines("-O0"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bfina"))
+        .arg("-O4")
+        .arg(&source)
+        .output()
+        .expect("failed to run bfina");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Invalid optimization level"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_output_flag_sets_the_output_path() {
+    let dir = scratch_dir("output");
+    let source = dir.join("prog.bf");
+    fs::write(&source, "+.").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_bfina"))
+            .current_dir(&dir)
+            .args(args)
+            .arg(&source)
+            .output()
+            .expect("failed to run bfina")
+    };
+
+    // Missing parent directories are created
+    assert!(run(&["-o", "build/nested/app"]).status.success());
+    assert!(dir.join("build/nested/app").exists());
+    assert!(!dir.join("prog").exists());
+
+    assert!(run(&["--emit=asm", "--output", "out.s"]).status.success());
+    assert!(dir.join("out.s").exists());
+    assert!(!dir.join("prog.s").exists());
+
+    let output = run(&["-o", "build"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("is a directory"));
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_source_is_read_from_stdin() {
+    let dir = scratch_dir("stdin");
+    let run = |args: &[&str]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_bfina"))
+            .current_dir(&dir)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .expect("failed to run bfina");
+        child.stdin.take().unwrap().write_all(b"+++.").unwrap();
+        child.wait_with_output().unwrap()
+    };
+
+    assert!(run(&["-"]).status.success());
+    assert!(dir.join("a.out").exists());
+
+    assert!(run(&["--emit=ir"]).status.success());
+    assert!(dir.join("a.lamina").exists());
+
+    let output = run(&["--interpret", "-"]);
+    assert!(output.status.success());
+    assert_eq!(output.stdout, [3]);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_dump_ast_prints_nested_loops() {
+    let dir = scratch_dir("dump-ast");
+    let source = dir.join("prog.bf");
+    fs::write(&source, "+[>[-]<]").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bfina"))
+        .arg("--dump-ast")
+        .arg(&source)
+        .output()
+        .expect("failed to run bfina");
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "Increment (+)\n\
+         Loop [\n  \
+           Right (>)\n  \
+           Loop [\n    \
+             Decrement (-)\n  \
+           ]\n  \
+           Left (<)\n\
+         ]\n"
+    );
+    assert!(!dir.join("prog").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_minify_strips_comments() {
+    let dir = scratch_dir("minify");
+    let source = dir.join("prog.bf");
+    fs::write(&source, "add two: ++\n[ move it > + < - ]\nprint it: >.\n").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_bfina"))
+            .current_dir(&dir)
+            .args(args)
+            .arg(&source)
+            .output()
+            .expect("failed to run bfina")
+    };
+
+    let output = run(&["--minify"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "++[>+<-]>.\n");
+    assert!(!dir.join("prog").exists());
+
+    assert!(run(&["--minify", "--emit-source"]).status.success());
+    assert_eq!(
+        fs::read_to_string(dir.join("prog.min.bf")).unwrap(),
+        "++[>+<-]>."
+    );
+
+    assert!(run(&["--minify", "-o", "out/small.bf"]).status.success());
+    assert_eq!(
+        fs::read_to_string(dir.join("out/small.bf")).unwrap(),
+        "++[>+<-]>."
+    );
+
+    assert!(!run(&["--emit-source"]).status.success());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_stats_compare_the_cost_before_and_after_optimization() {
+    let dir = scratch_dir("stats");
+    let source = dir.join("prog.bf");
+    fs::write(&source, "+++[>++<-]>.").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bfina"))
+        .args(["--stats", "-O3"])
+        .arg(&source)
+        .output()
+        .expect("failed to run bfina");
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 2, "{}", stdout);
+    assert!(lines[0].starts_with("Before optimization: ~"));
+    assert!(lines[0].ends_with("unbounded at runtime (loops)"));
+    // The multiply loop is straight-line code once lowered
+    assert!(lines[1].starts_with("After -O3:"));
+    assert!(lines[1].ends_with(" executed"));
+    assert!(!dir.join("prog").exists());
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_lints_are_printed_unless_no_warn() {
+    let dir = scratch_dir("lint");
+    let source = dir.join("prog.bf");
+    fs::write(&source, "+[-]\n[-]+[]").unwrap();
+    let run = |args: &[&str]| {
+        Command::new(env!("CARGO_BIN_EXE_bfina"))
+            .args(args)
+            .arg(&source)
+            .output()
+            .expect("failed to run bfina")
+    };
+
+    let output = run(&["--dump-ast"]);
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("clear loop on a cell that is already zero at line 2, column 1"));
+    assert!(stderr.contains("empty loop never exits once entered at line 2, column 5"));
+
+    let output = run(&["--dump-ast", "--no-warn"]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+
+    // A loop that hides the code after it gets a single warning
+    fs::write(&source, "+[]+").unwrap();
+    let output = run(&["--dump-ast"]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("the loop at line 1, column 2 never exits"));
+    assert_eq!(stderr.matches("line 1, column 2").count(), 1, "{}", stderr);
+
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn test_several_files_are_compiled_with_a_summary() {
+    let dir = scratch_dir("batch");
+    let first = dir.join("first.bf");
+    let second = dir.join("second.bf");
+    let broken = dir.join("broken.bf");
+    fs::write(&first, "+.").unwrap();
+    fs::write(&second, "++.").unwrap();
+    fs::write(&broken, "[+.").unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bfina"))
+        .arg("--emit=ir")
+        .arg(&first)
+        .arg(&second)
+        .output()
+        .expect("failed to run bfina");
+    assert!(output.status.success());
+    assert!(dir.join("first.lamina").exists());
+    assert!(dir.join("second.lamina").exists());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Compiled 2 of 2 files (0 failed)"));
+
+    let output = Command::new(env!("CARGO_BIN_EXE_bfina"))
+        .arg("--emit=ir")
+        .arg(&first)
+        .arg(&broken)
+        .output()
+        .expect("failed to run bfina");
+    assert!(!output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Compiled 1 of 2 files (1 failed)"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains(&format!("Error: {}: Parse error", broken.display())));
+
+    let _ = fs::remove_dir_all(&dir);
+}
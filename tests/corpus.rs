@@ -0,0 +1,143 @@
+//! Differential tests over the program corpus in `tests/corpus/`
+//!
+//! Every `<name>.bf` program comes with its recorded output in `<name>.out`
+//! and, optionally, the input it reads in `<name>.in`. Each program is run
+//! through the interpreter and, when the toolchain is available, as a
+//! compiled binary; both must reproduce the recorded output exactly.
+
+use bfina::{BrainfuckConfig, brainfuck_to_binary, interpreter, optimize_ast, parse_brainfuck};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// A corpus program with its recorded input and expected output
+struct Case {
+    name: String,
+    source: String,
+    input: Vec<u8>,
+    expected: Vec<u8>,
+}
+
+fn corpus_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus")
+}
+
+fn load_corpus() -> Vec<Case> {
+    let dir = corpus_dir();
+    let mut cases: Vec<Case> = fs::read_dir(&dir)
+        .expect("missing tests/corpus directory")
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bf"))
+        .map(|path| {
+            let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+            Case {
+                source: fs::read_to_string(&path).unwrap(),
+                input: fs::read(dir.join(format!("{}.in", name))).unwrap_or_default(),
+                expected: fs::read(dir.join(format!("{}.out", name)))
+                    .unwrap_or_else(|_| panic!("missing recorded output for {}", name)),
+                name,
+            }
+        })
+        .collect();
+
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    assert!(!cases.is_empty(), "corpus is empty");
+    cases
+}
+
+/// Compile a program and run the resulting binary with the given input
+fn run_compiled(case: &Case, workdir: &Path) -> Result<Vec<u8>, String> {
+    let ast = parse_brainfuck(&case.source).map_err(|e| e.to_string())?;
+    let binary = workdir.join(&case.name);
+    brainfuck_to_binary(&ast, &binary.to_string_lossy()).map_err(|e| e.to_string())?;
+
+    let mut child = Command::new(&binary)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(&case.input)
+        .map_err(|e| e.to_string())?;
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    // A binary killed by a signal didn't produce a result, even if what it
+    // printed first matches
+    if output.status.code().is_none() {
+        return Err(format!("binary terminated abnormally ({})", output.status));
+    }
+    Ok(output.stdout)
+}
+
+#[test]
+fn test_corpus_interpreted() {
+    for case in load_corpus() {
+        let ast = parse_brainfuck(&case.source).unwrap();
+        let mut output = Vec::new();
+        interpreter::run(
+            &ast,
+            &BrainfuckConfig::default(),
+            &mut &case.input[..],
+            &mut output,
+        )
+        .unwrap_or_else(|e| panic!("{} failed: {}", case.name, e));
+
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            String::from_utf8_lossy(&case.expected),
+            "interpreted output of {} differs",
+            case.name
+        );
+    }
+}
+
+/// A quine's recorded output is its own source, so it checks the whole
+/// pipeline on a program that leans hard on loops, arithmetic, and output
+#[test]
+fn test_quine_reproduces_its_source() {
+    let case = load_corpus()
+        .into_iter()
+        .find(|case| case.name == "quine")
+        .expect("missing quine in the corpus");
+    assert_eq!(case.expected, case.source.as_bytes());
+
+    let ast = parse_brainfuck(&case.source).unwrap();
+    for ast in [ast.clone(), optimize_ast(&ast)] {
+        let mut output = Vec::new();
+        interpreter::run(
+            &ast,
+            &BrainfuckConfig::default(),
+            &mut &b""[..],
+            &mut output,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8_lossy(&output), case.source);
+    }
+}
+
+#[test]
+fn test_corpus_compiled() {
+    if Command::new("gcc").arg("--version").output().is_err() {
+        eprintln!("gcc not available, skipping");
+        return;
+    }
+
+    let workdir = std::env::temp_dir().join(format!("bfina-corpus-{}", std::process::id()));
+    fs::create_dir_all(&workdir).unwrap();
+
+    for case in load_corpus() {
+        let output = run_compiled(&case, &workdir)
+            .unwrap_or_else(|e| panic!("{} failed to compile: {}", case.name, e));
+        assert_eq!(
+            String::from_utf8_lossy(&output),
+            String::from_utf8_lossy(&case.expected),
+            "compiled output of {} differs",
+            case.name
+        );
+    }
+
+    let _ = fs::remove_dir_all(&workdir);
+}
@@ -0,0 +1,8 @@
+Print the first seven Fibonacci numbers as rows of asterisks
++++++++ >+ >+ >>> ++++++[<+++++++>-] ++++++++++ <<<<<
+[
+  >[>>+>>>+<<<<<-] >>>>>[<<<<<+>>>>>-]
+  <<<[>.<-] >>.
+  <<<<[>>+<<-] >[<+>>>>>+<<<<-] >>>>[<<<<+>>>>-] <<<[<+>-]
+  <<<-
+]
@@ -0,0 +1,21 @@
+//! Self-test of the `testing` feature's differential helper
+
+use bfina::BrainfuckConfig;
+use bfina::testing::assert_compiled_matches_interpreted;
+use std::fs;
+use std::path::Path;
+
+#[test]
+fn test_corpus_program_matches_interpreter() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let source = fs::read_to_string(dir.join("rot13.bf")).unwrap();
+    let input = fs::read(dir.join("rot13.in")).unwrap();
+
+    assert_compiled_matches_interpreted(&source, &input, &BrainfuckConfig::default());
+}
+
+#[test]
+#[should_panic(expected = "Interpreter failed")]
+fn test_interpreter_errors_are_reported() {
+    assert_compiled_matches_interpreted("<", b"", &BrainfuckConfig::default());
+}
@@ -0,0 +1,121 @@
+//! Golden snapshot tests for generated LLVM and Lamina IR
+//!
+//! Each canonical program's IR from both backends is compared against a
+//! checked-in file in `tests/golden/`, so any change to the generated code
+//! shows up in review.
+//! After an intentional codegen change, regenerate the snapshots with:
+//!
+//! ```text
+//! UPDATE_GOLDEN=1 cargo test --test golden
+//! ```
+
+use bfina::{
+    BrainfuckConfig, brainfuck_to_lamina_ir_with_config, brainfuck_to_llvm_ir, parse_brainfuck,
+};
+use std::fs;
+use std::path::Path;
+
+/// Compare `actual` against the golden file `name`, or rewrite the golden
+/// file when `UPDATE_GOLDEN` is set
+fn check_golden(name: &str, actual: &str) {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/golden")
+        .join(name);
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&path).unwrap_or_else(|_| {
+        panic!(
+            "missing golden file {}; run with UPDATE_GOLDEN=1 to create it",
+            path.display()
+        )
+    });
+    assert!(
+        expected == actual,
+        "generated IR differs from {}; run with UPDATE_GOLDEN=1 to update it\n\
+         --- expected\n{}\n--- actual\n{}",
+        path.display(),
+        expected,
+        actual
+    );
+}
+
+fn check_llvm_ir(name: &str, source: &str) {
+    let ast = parse_brainfuck(source).unwrap();
+    let ir = brainfuck_to_llvm_ir(&ast, &BrainfuckConfig::default()).unwrap();
+    check_golden(&format!("{}.ll", name), &ir);
+}
+
+/// Put the blocks of each function in the order they were created
+///
+/// Lamina keeps a function's blocks in a hash map, so it prints them in a
+/// different order from run to run. Every label but `entry` ends in the
+/// number the builder gave it.
+fn sort_blocks(ir: &str) -> String {
+    let mut sorted = String::new();
+    let mut blocks: Vec<(usize, String)> = Vec::new();
+    let flush = |sorted: &mut String, blocks: &mut Vec<(usize, String)>| {
+        blocks.sort_by_key(|(order, _)| *order);
+        for (_, block) in blocks.drain(..) {
+            sorted.push_str(&block);
+        }
+    };
+    for line in ir.lines() {
+        if let Some(label) = line.strip_suffix(':').filter(|_| !line.starts_with(' ')) {
+            let order = label
+                .rsplit('_')
+                .next()
+                .and_then(|n| n.parse::<usize>().ok())
+                .map_or(0, |n| n + 1);
+            blocks.push((order, String::new()));
+        } else if line == "}" {
+            flush(&mut sorted, &mut blocks);
+        }
+        let out = match blocks.last_mut() {
+            Some((_, block)) => block,
+            None => &mut sorted,
+        };
+        out.push_str(line);
+        out.push('\n');
+    }
+    flush(&mut sorted, &mut blocks);
+    sorted
+}
+
+fn check_lamina_ir(name: &str, source: &str) {
+    let ast = parse_brainfuck(source).unwrap();
+    // Otherwise programs without input compile to just their output
+    let config = BrainfuckConfig {
+        precompute_output: false,
+        ..BrainfuckConfig::default()
+    };
+    let ir = brainfuck_to_lamina_ir_with_config(&ast, config).unwrap();
+    check_golden(&format!("{}.lamina", name), &sort_blocks(&ir));
+}
+
+#[test]
+fn test_golden_increment() {
+    check_llvm_ir("increment", "+");
+    check_lamina_ir("increment", "+");
+}
+
+#[test]
+fn test_golden_clear_loop() {
+    check_llvm_ir("clear_loop", "[-]");
+    check_lamina_ir("clear_loop", "[-]");
+}
+
+#[test]
+fn test_golden_echo() {
+    check_llvm_ir("echo", ",.");
+    check_lamina_ir("echo", ",.");
+}
+
+#[test]
+fn test_golden_nested_loop() {
+    check_llvm_ir("nested_loop", "++[>++[>+<-]<-]");
+    check_lamina_ir("nested_loop", "++[>++[>+<-]<-]");
+}
@@ -0,0 +1,28 @@
+; Generated by bfina
+declare i32 @getchar()
+declare i32 @putchar(i32)
+declare i32 @fflush(ptr)
+@tape = internal global [30000 x i8] zeroinitializer, align 16
+
+define i32 @main() {
+entry:
+  %ptr = alloca i64
+  store i64 0, ptr %ptr
+  %v2 = load i64, ptr %ptr
+  %v3 = getelementptr i8, ptr @tape, i64 %v2
+  %v4 = load i8, ptr %v3
+  %v5 = icmp ne i8 %v4, 0
+  br i1 %v5, label %loop_body1, label %loop_end1
+loop_body1:
+  %v6 = load i64, ptr %ptr
+  %v7 = getelementptr i8, ptr @tape, i64 %v6
+  %v8 = load i8, ptr %v7
+  %v9 = add i8 %v8, -1
+  store i8 %v9, ptr %v7
+  %v10 = load i8, ptr %v7
+  %v11 = icmp ne i8 %v10, 0
+  br i1 %v11, label %loop_body1, label %loop_end1
+loop_end1:
+  call i32 @fflush(ptr null)
+  ret i32 0
+}
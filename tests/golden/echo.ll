@@ -0,0 +1,29 @@
+; Generated by bfina
+declare i32 @getchar()
+declare i32 @putchar(i32)
+declare i32 @fflush(ptr)
+@tape = internal global [30000 x i8] zeroinitializer, align 16
+
+define i32 @main() {
+entry:
+  %ptr = alloca i64
+  store i64 0, ptr %ptr
+  %v1 = load i64, ptr %ptr
+  %v2 = getelementptr i8, ptr @tape, i64 %v1
+  call i32 @fflush(ptr null)
+  %v3 = call i32 @getchar()
+  %v4 = icmp eq i32 %v3, -1
+  br i1 %v4, label %input_done6, label %input_store6
+input_store6:
+  %v5 = trunc i32 %v3 to i8
+  store i8 %v5, ptr %v2
+  br label %input_done6
+input_done6:
+  %v7 = load i64, ptr %ptr
+  %v8 = getelementptr i8, ptr @tape, i64 %v7
+  %v9 = load i8, ptr %v8
+  %v10 = zext i8 %v9 to i32
+  call i32 @putchar(i32 %v10)
+  call i32 @fflush(ptr null)
+  ret i32 0
+}
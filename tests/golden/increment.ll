@@ -0,0 +1,18 @@
+; Generated by bfina
+declare i32 @getchar()
+declare i32 @putchar(i32)
+declare i32 @fflush(ptr)
+@tape = internal global [30000 x i8] zeroinitializer, align 16
+
+define i32 @main() {
+entry:
+  %ptr = alloca i64
+  store i64 0, ptr %ptr
+  %v1 = load i64, ptr %ptr
+  %v2 = getelementptr i8, ptr @tape, i64 %v1
+  %v3 = load i8, ptr %v2
+  %v4 = add i8 %v3, 1
+  store i8 %v4, ptr %v2
+  call i32 @fflush(ptr null)
+  ret i32 0
+}
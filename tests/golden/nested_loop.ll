@@ -0,0 +1,64 @@
+; Generated by bfina
+declare i32 @getchar()
+declare i32 @putchar(i32)
+declare i32 @fflush(ptr)
+@tape = internal global [30000 x i8] zeroinitializer, align 16
+
+define i32 @main() {
+entry:
+  %ptr = alloca i64
+  store i64 0, ptr %ptr
+  %v1 = load i64, ptr %ptr
+  %v2 = getelementptr i8, ptr @tape, i64 %v1
+  %v3 = load i8, ptr %v2
+  %v4 = add i8 %v3, 1
+  store i8 %v4, ptr %v2
+  %v5 = load i64, ptr %ptr
+  %v6 = getelementptr i8, ptr @tape, i64 %v5
+  %v7 = load i8, ptr %v6
+  %v8 = add i8 %v7, 1
+  store i8 %v8, ptr %v6
+  %v10 = load i64, ptr %ptr
+  %v11 = getelementptr i8, ptr @tape, i64 %v10
+  %v12 = load i8, ptr %v11
+  %v13 = icmp ne i8 %v12, 0
+  br i1 %v13, label %loop_body9, label %loop_end9
+loop_body9:
+  %v14 = load i64, ptr %ptr
+  %v15 = getelementptr i8, ptr @tape, i64 %v14
+  %v16 = getelementptr i8, ptr %v15, i64 1
+  %v17 = load i8, ptr %v16
+  %v18 = add i8 %v17, 1
+  store i8 %v18, ptr %v16
+  %v19 = getelementptr i8, ptr %v15, i64 1
+  %v20 = load i8, ptr %v19
+  %v21 = add i8 %v20, 1
+  store i8 %v21, ptr %v19
+  %v23 = getelementptr i8, ptr %v15, i64 1
+  %v24 = load i8, ptr %v23
+  %v25 = icmp ne i8 %v24, 0
+  br i1 %v25, label %loop_body22, label %loop_end22
+loop_body22:
+  %v26 = getelementptr i8, ptr %v15, i64 2
+  %v27 = load i8, ptr %v26
+  %v28 = add i8 %v27, 1
+  store i8 %v28, ptr %v26
+  %v29 = getelementptr i8, ptr %v15, i64 1
+  %v30 = load i8, ptr %v29
+  %v31 = add i8 %v30, -1
+  store i8 %v31, ptr %v29
+  %v32 = getelementptr i8, ptr %v15, i64 1
+  %v33 = load i8, ptr %v32
+  %v34 = icmp ne i8 %v33, 0
+  br i1 %v34, label %loop_body22, label %loop_end22
+loop_end22:
+  %v35 = load i8, ptr %v15
+  %v36 = add i8 %v35, -1
+  store i8 %v36, ptr %v15
+  %v37 = load i8, ptr %v15
+  %v38 = icmp ne i8 %v37, 0
+  br i1 %v38, label %loop_body9, label %loop_end9
+loop_end9:
+  call i32 @fflush(ptr null)
+  ret i32 0
+}